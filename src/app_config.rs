@@ -1,48 +1,820 @@
 use std::path::PathBuf;
-use clap::{arg, command, value_parser};
+use clap::{arg, command, value_parser, Arg, ArgMatches, Command};
+use clap_complete::Shell;
+use crate::audio::key::KeyNotation;
+use crate::history::journal::UndoTarget;
+use crate::history::state_db::SkipMode;
+use crate::library::duplicates::DuplicateAction;
+use crate::library::query::OutputFormat;
+use crate::metadata::album_matcher::AlbumGrouping;
+use crate::metadata::id3v2_writer::{Id3v2Version, TextEncoding};
+use crate::metadata::tag_target::TagTarget;
+use crate::organise::file_organiser::OrganiseMode;
+use crate::settings::Settings;
+
+pub enum AppCommand {
+    /// Find the best match for a file, writing it when enabled in the settings.
+    Match {
+        path: PathBuf,
+        organise: Option<OrganiseOptions>,
+    },
+    /// Match every album below a directory as a whole.
+    Album {
+        path: PathBuf,
+    },
+    /// Report inconsistent tags within the albums below a directory, fixing what can be fixed locally.
+    Check {
+        path: PathBuf,
+        fix: bool,
+    },
+    /// Measure loudness and write ReplayGain tags, with album gains when given a directory.
+    ReplayGain {
+        path: PathBuf,
+    },
+    /// Detect the tempo and key of a file, or of every file below a directory.
+    Analyse {
+        path: PathBuf,
+    },
+    /// Find the same song stored more than once below a directory.
+    Duplicates {
+        path: PathBuf,
+        action: DuplicateAction,
+        /// Delete without asking.
+        confirmed: bool,
+    },
+    /// Match, write and organise every file arriving in a directory until interrupted.
+    Watch {
+        path: PathBuf,
+        organise: Option<OrganiseOptions>,
+    },
+    /// Serve reading, matching and writing tags over a local JSON HTTP API.
+    Serve,
+    /// Pick, edit and write matches for a file or every file below a directory in a terminal UI.
+    Review {
+        path: PathBuf,
+    },
+    /// Read the tags of every file below a directory into the library index.
+    Index {
+        path: PathBuf,
+    },
+    Query(QueryOptions),
+    Show {
+        path: PathBuf,
+    },
+    Diff {
+        path: PathBuf,
+    },
+    /// Print the file's acoustic fingerprint, optionally looking up the recordings it matches.
+    Fingerprint {
+        path: PathBuf,
+        lookup: bool,
+    },
+    Undo {
+        target: UndoTarget,
+        force: bool,
+        list: bool,
+    },
+    Organise {
+        path: PathBuf,
+        options: OrganiseOptions,
+    },
+    Cache(CacheAction),
+    Providers(ProvidersAction),
+    Config,
+    Completions(Shell),
+}
+
+#[derive(Clone)]
+pub struct OrganiseOptions {
+    pub destination: PathBuf,
+    pub dry_run: bool,
+}
+
+/// Filters and output options for querying the library index.
+pub struct QueryOptions {
+    pub filters: Vec<String>,
+    /// Field to sort by, descending when prefixed with `-`.
+    pub sort: Option<String>,
+    pub limit: Option<usize>,
+    pub columns: Vec<String>,
+    pub format: OutputFormat,
+}
+
+pub enum CacheAction {
+    Info,
+    Clear {
+        expired_only: bool,
+    },
+}
+
+pub enum ProvidersAction {
+    List,
+    Test(Option<String>),
+}
 
 pub struct AppConfig {
-    pub path: PathBuf,
-    pub debug: bool,
-    pub write: bool,
+    pub command: AppCommand,
+    pub settings: Settings,
 }
 
 impl AppConfig {
-    fn new(path: PathBuf, debug: bool, write: bool) -> AppConfig {
+    fn new(command: AppCommand, settings: Settings) -> AppConfig {
         AppConfig {
-            path,
-            debug,
-            write,
+            command,
+            settings,
         }
     }
 
     pub fn from_command_args() -> AppConfig {
-        let matches = command!()
-            .arg(
-                arg!(
-                    [path] "Path of the music file"
-                )
-                .required(true)
-                .value_parser(value_parser!(PathBuf))
-            )
-            .arg(arg!(
-                -d --debug ... "Turn debugging information on"
-            ))
-            .arg(arg!(
-                -w --write ... "Apply the matched metadata tags to the file"
-            ))
-            .get_matches();
-
-        return AppConfig::new(
-            matches.get_one::<PathBuf>("path").unwrap().clone(),
-            match matches.get_one::<u8>("debug") {
-                Some(0) => false,
-                _ => true,
-            },
-            match matches.get_one::<u8>("write") {
-                Some(0) => false,
-                _ => true,
+        let matches = build_cli().get_matches();
+        let (name, subcommand_matches) = matches.subcommand().expect("clap requires a subcommand");
+
+        let mut settings = Settings::load(
+            subcommand_matches.get_one::<PathBuf>("config").map(|p| p.as_path()),
+            subcommand_matches.get_one::<String>("profile").map(|s| s.as_str()),
+        );
+        apply_command_line_overrides(&mut settings, subcommand_matches);
+
+        let command = match name {
+            "match" => AppCommand::Match {
+                path: path_arg(subcommand_matches),
+                organise: organise_options(subcommand_matches),
+            },
+            "write" => {
+                settings.general.write = true;
+                AppCommand::Match {
+                    path: path_arg(subcommand_matches),
+                    organise: organise_options(subcommand_matches),
+                }
             },
+            "album" => AppCommand::Album {
+                path: path_arg(subcommand_matches),
+            },
+            "check" => AppCommand::Check {
+                path: path_arg(subcommand_matches),
+                fix: subcommand_matches.get_flag("fix"),
+            },
+            "replaygain" => AppCommand::ReplayGain {
+                path: path_arg(subcommand_matches),
+            },
+            "analyse" => AppCommand::Analyse {
+                path: path_arg(subcommand_matches),
+            },
+            "duplicates" => AppCommand::Duplicates {
+                path: path_arg(subcommand_matches),
+                action: DuplicateAction::from_name(subcommand_matches.get_one::<String>("action").unwrap()).expect("clap only accepts known actions"),
+                confirmed: subcommand_matches.get_flag("yes"),
+            },
+            "watch" => {
+                settings.general.write = true;
+                AppCommand::Watch {
+                    path: path_arg(subcommand_matches),
+                    organise: subcommand_matches.get_one::<PathBuf>("organise").map(|destination| OrganiseOptions {
+                        destination: destination.clone(),
+                        dry_run: false,
+                    }),
+                }
+            },
+            "serve" => AppCommand::Serve,
+            "review" => AppCommand::Review {
+                path: path_arg(subcommand_matches),
+            },
+            "index" => AppCommand::Index {
+                path: path_arg(subcommand_matches),
+            },
+            "query" => AppCommand::Query(QueryOptions {
+                filters: subcommand_matches.get_many::<String>("filter").map(|filters| filters.cloned().collect()).unwrap_or_default(),
+                sort: subcommand_matches.get_one::<String>("sort").cloned(),
+                limit: subcommand_matches.get_one::<usize>("limit").copied(),
+                columns: subcommand_matches.get_one::<String>("columns").unwrap().split(',').map(|column| column.trim().to_string()).collect(),
+                format: OutputFormat::from_name(subcommand_matches.get_one::<String>("format").unwrap()).expect("clap only accepts known formats"),
+            }),
+            "show" => AppCommand::Show {
+                path: path_arg(subcommand_matches),
+            },
+            "diff" => AppCommand::Diff {
+                path: path_arg(subcommand_matches),
+            },
+            "fingerprint" => AppCommand::Fingerprint {
+                path: path_arg(subcommand_matches),
+                lookup: subcommand_matches.get_flag("lookup"),
+            },
+            "undo" => AppCommand::Undo {
+                target: undo_target(subcommand_matches),
+                force: subcommand_matches.get_flag("force"),
+                list: subcommand_matches.get_flag("list"),
+            },
+            "organise" => AppCommand::Organise {
+                path: path_arg(subcommand_matches),
+                options: OrganiseOptions {
+                    destination: subcommand_matches.get_one::<PathBuf>("destination").unwrap().clone(),
+                    dry_run: subcommand_matches.get_flag("dry-run"),
+                },
+            },
+            "cache" => AppCommand::Cache(match subcommand_matches.subcommand() {
+                Some(("clear", clear_matches)) => CacheAction::Clear {
+                    expired_only: clear_matches.get_flag("expired"),
+                },
+                _ => CacheAction::Info,
+            }),
+            "providers" => AppCommand::Providers(match subcommand_matches.subcommand() {
+                Some(("test", test_matches)) => ProvidersAction::Test(test_matches.get_one::<String>("name").cloned()),
+                _ => ProvidersAction::List,
+            }),
+            "config" => AppCommand::Config,
+            "completions" => AppCommand::Completions(*subcommand_matches.get_one::<Shell>("shell").unwrap()),
+            _ => unreachable!("clap only accepts known subcommands"),
+        };
+
+        return AppConfig::new(command, settings);
+    }
+}
+
+pub fn build_cli() -> Command {
+    return command!()
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(arg!(
+            -d --debug ... "Turn debugging information on"
+        ).global(true))
+        .arg(
+            arg!(
+                -c --config <FILE> "Path of the config file, defaults to imd/config.toml in the user config directory"
+            )
+            .value_parser(value_parser!(PathBuf))
+            .global(true)
+        )
+        .arg(arg!(
+            -p --profile <NAME> "Name of the config file profile to apply"
+        ).global(true))
+        .arg(
+            arg!(
+                --storefront <COUNTRY> "Storefront country code to search, may be given multiple times"
+            )
+            .action(clap::ArgAction::Append)
+            .global(true)
+        )
+        .arg(
+            arg!(
+                --provider <NAME> "Metadata provider to query, may be given multiple times"
+            )
+            .action(clap::ArgAction::Append)
+            .global(true)
+        )
+        .subcommand(
+            Command::new("match")
+                .about("Find the best matching metadata for a music file, or for every file in a directory")
+                .arg(file_or_directory_arg_definition())
+                .arg(arg!(
+                    -w --write ... "Apply the matched metadata tags to the file"
+                ))
+                .args(inference_arg_definitions())
+                .args(batch_arg_definitions())
+                .args(quality_arg_definitions())
+                .args(writer_arg_definitions())
+                .args(match_organise_arg_definitions())
+        )
+        .subcommand(
+            Command::new("album")
+                .about("Match the albums in a directory, giving every track of an album the same release")
+                .arg(directory_arg_definition())
+                .arg(arg!(
+                    -w --write ... "Apply the matched metadata tags to the files"
+                ))
+                .arg(group_by_arg_definition())
+                .args(writer_arg_definitions())
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Report missing, duplicate and inconsistent tags within the albums in a directory")
+                .arg(directory_arg_definition())
+                .arg(arg!(
+                    --fix "Fix the issues that can be resolved from the album's own tags"
+                ))
+                .arg(group_by_arg_definition())
+                .args(writer_arg_definitions())
+        )
+        .subcommand(
+            Command::new("replaygain")
+                .about("Measure EBU R128 loudness and true peak and write ReplayGain 2.0 tags")
+                .arg(
+                    arg!(
+                        <path> "Music file, or directory to analyse album by album"
+                    )
+                    .value_parser(value_parser!(PathBuf))
+                )
+                .args(batch_arg_definitions())
+                .arg(arg!(
+                    -w --write ... "Write the ReplayGain tags to the files"
+                ))
+                .arg(group_by_arg_definition())
+                .args(writer_arg_definitions())
+        )
+        .subcommand(
+            Command::new("analyse")
+                .about("Detect the tempo and musical key of the audio and write them as BPM and key tags")
+                .arg(file_or_directory_arg_definition())
+                .args(batch_arg_definitions())
+                .args(quality_arg_definitions())
+                .arg(arg!(
+                    -w --write ... "Write the BPM and key tags to the files"
+                ))
+                .arg(
+                    arg!(
+                        --"key-notation" <NOTATION> "How keys are written"
+                    )
+                    .value_parser(["standard", "camelot", "open_key"])
+                )
+                .arg(arg!(
+                    --overwrite "Replace BPM and key values already in the tags"
+                ))
+                .args(writer_arg_definitions())
+        )
+        .subcommand(
+            Command::new("duplicates")
+                .about("Find songs stored more than once and recommend which copy to keep")
+                .arg(directory_arg_definition())
+                .arg(
+                    arg!(
+                        --action <ACTION> "What to do with every copy but the keeper"
+                    )
+                    .value_parser(["report", "quarantine", "delete"])
+                    .default_value("report")
+                )
+                .arg(
+                    arg!(
+                        --"quarantine-dir" <DIR> "Where --action quarantine moves the duplicates"
+                    )
+                    .value_parser(value_parser!(PathBuf))
+                )
+                .arg(arg!(
+                    --fingerprint "Also compare acoustic fingerprints, slower but finds duplicates with differing tags"
+                ))
+                .arg(arg!(
+                    -y --yes "Delete without asking for confirmation"
+                ))
+                .args(worker_arg_definitions())
+                .args(quality_arg_definitions())
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Watch a directory and match, write and organise every music file arriving in it")
+                .arg(directory_arg_definition())
+                .arg(
+                    arg!(
+                        -o --organise <DIR> "Rename matched files into DIR using the naming template"
+                    )
+                    .value_parser(value_parser!(PathBuf))
+                )
+                .args(organise_arg_definitions().into_iter().filter(|definition| definition.get_id() != "dry-run"))
+                .arg(
+                    arg!(
+                        --"review-dir" <DIR> "Where files below the auto accept score are moved, defaults to review in the watched directory"
+                    )
+                    .value_parser(value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(
+                        --settle <SECS> "Seconds a new file must go unchanged before it is processed"
+                    )
+                    .value_parser(value_parser!(u64))
+                )
+                .args(inference_arg_definitions())
+                .args(writer_arg_definitions())
+                .arg(
+                    arg!(
+                        --workers <N> "Files processed at the same time, 0 for one per CPU core"
+                    )
+                    .value_parser(value_parser!(usize))
+                )
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Serve reading, matching and writing tags over a JSON HTTP API")
+                .arg(arg!(
+                    --bind <ADDRESS> "Address to listen on, defaults to 127.0.0.1:7878"
+                ))
+                .args(writer_arg_definitions())
+                .arg(
+                    arg!(
+                        --workers <N> "Requests handled at the same time, 0 for one per CPU core"
+                    )
+                    .value_parser(value_parser!(usize))
+                )
+        )
+        .subcommand(
+            Command::new("review")
+                .about("Review the candidates for a music file, or every file in a directory, in a full-screen terminal UI")
+                .arg(file_or_directory_arg_definition())
+                .args(inference_arg_definitions())
+                .args(writer_arg_definitions())
+        )
+        .subcommand(
+            Command::new("index")
+                .about("Read the tags of every music file in a directory into the library index")
+                .arg(directory_arg_definition())
+                .args(worker_arg_definitions())
+        )
+        .subcommand(
+            Command::new("query")
+                .about("List the files in the library index matching filters like artist=~Beatles year<1970 missing:album_artist")
+                .arg(arg!(
+                    [filter] ... "Filters that must all match: FIELD=VALUE, !=, =~REGEX, !~, <, <=, >, >=, missing:FIELD or has:FIELD"
+                ))
+                .arg(arg!(
+                    --sort <FIELD> "Field to sort by, prefix with - for descending, e.g. -missing for the worst tagged files first"
+                ).allow_hyphen_values(true))
+                .arg(
+                    arg!(
+                        --limit <N> "Only print the first N files"
+                    )
+                    .value_parser(value_parser!(usize))
+                )
+                .arg(
+                    arg!(
+                        --columns <FIELDS> "Comma separated fields to print"
+                    )
+                    .default_value("path,artist,title,album,year,missing")
+                )
+                .arg(
+                    arg!(
+                        --format <FORMAT> "Output format"
+                    )
+                    .value_parser(["table", "csv", "json"])
+                    .default_value("table")
+                )
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Print the tags of a music file")
+                .arg(path_arg_definition())
+        )
+        .subcommand(
+            Command::new("write")
+                .about("Find the best matching metadata and write it to the music file, or to every file in a directory")
+                .arg(file_or_directory_arg_definition())
+                .args(inference_arg_definitions())
+                .args(batch_arg_definitions())
+                .args(quality_arg_definitions())
+                .args(writer_arg_definitions())
+                .args(match_organise_arg_definitions())
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show how the best matching metadata differs from the current tags")
+                .arg(path_arg_definition())
+                .args(inference_arg_definitions())
+        )
+        .subcommand(
+            Command::new("fingerprint")
+                .about("Print the acoustic fingerprint of a music file")
+                .arg(path_arg_definition())
+                .arg(arg!(
+                    --lookup "Look up the recordings matching the fingerprint"
+                ))
+        )
+        .subcommand(undo_command())
+        .subcommand(
+            Command::new("organise")
+                .about("Rename a music file into a directory using the naming template")
+                .arg(path_arg_definition())
+                .arg(
+                    arg!(
+                        <destination> "Directory to organise the file into"
+                    )
+                    .value_parser(value_parser!(PathBuf))
+                )
+                .args(organise_arg_definitions())
+        )
+        .subcommand(
+            Command::new("cache")
+                .about("Inspect or clear the provider response cache")
+                .subcommand(Command::new("info").about("Show the cache location and size"))
+                .subcommand(
+                    Command::new("clear")
+                        .about("Remove cached responses")
+                        .arg(arg!(
+                            --expired "Only remove expired responses"
+                        ))
+                )
+        )
+        .subcommand(
+            Command::new("providers")
+                .about("List or test the metadata providers")
+                .subcommand(Command::new("list").about("List the known providers and their order"))
+                .subcommand(
+                    Command::new("test")
+                        .about("Run a test search against the configured providers")
+                        .arg(arg!(
+                            [name] "Only test this provider"
+                        ))
+                )
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Print the effective configuration")
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Generate a shell completion script")
+                .arg(
+                    arg!(
+                        <shell> "Shell to generate completions for"
+                    )
+                    .value_parser(value_parser!(Shell))
+                )
         );
+}
+
+fn path_arg_definition() -> Arg {
+    return arg!(
+        <path> "Path of the music file"
+    )
+    .value_parser(value_parser!(PathBuf));
+}
+
+fn directory_arg_definition() -> Arg {
+    return arg!(
+        <path> "Directory of music files"
+    )
+    .value_parser(value_parser!(PathBuf));
+}
+
+fn file_or_directory_arg_definition() -> Arg {
+    return arg!(
+        <path> "Music file, or directory of music files"
+    )
+    .value_parser(value_parser!(PathBuf));
+}
+
+fn worker_arg_definitions() -> Vec<Arg> {
+    return vec![
+        arg!(
+            --workers <N> "Files processed at the same time when given a directory, 0 for one per CPU core"
+        )
+        .value_parser(value_parser!(usize)),
+        arg!(
+            --"no-progress" "Don't show a progress bar"
+        ),
+    ];
+}
+
+fn batch_arg_definitions() -> Vec<Arg> {
+    let mut definitions = worker_arg_definitions();
+    definitions.extend([
+        arg!(
+            --resume "Skip files an earlier run processed without failing, unless they changed since"
+        )
+        .conflicts_with("only-changed"),
+        arg!(
+            --"only-changed" "Only process files that are new or changed since an earlier run processed them"
+        ),
+    ]);
+    return definitions;
+}
+
+fn quality_arg_definitions() -> Vec<Arg> {
+    return vec![
+        arg!(
+            --"min-bitrate" <KBPS> "Leave out lossy files below this bitrate"
+        )
+        .value_parser(value_parser!(u32)),
+        arg!(
+            --"lossless-only" "Leave out files in lossy formats"
+        ),
+    ];
+}
+
+fn group_by_arg_definition() -> Arg {
+    return arg!(
+        --"group-by" <GROUPING> "How files are grouped into albums"
+    )
+    .value_parser(["folder", "album_tag"]);
+}
+
+fn inference_arg_definitions() -> Vec<Arg> {
+    return vec![
+        arg!(
+            --"no-fingerprint" "Don't identify files without a title or artist by their acoustic fingerprint"
+        ),
+        arg!(
+            --"no-path-inference" "Don't fill fields missing from the tags from the file name and folders"
+        ),
+    ];
+}
+
+fn writer_arg_definitions() -> Vec<Arg> {
+    return vec![
+        arg!(
+            --"preserve-mtime" "Keep the file's modification time when writing tags"
+        ),
+        arg!(
+            --"tag-target" <TARGET> "Which tags to write"
+        )
+        .value_parser(["primary", "all_existing", "types"]),
+        arg!(
+            --"tag-type" <TYPE> "Tag type to write, may be given multiple times, takes precedence over --tag-target"
+        )
+        .value_parser(["id3v1", "id3v2", "ape", "mp4_ilst", "vorbis_comments", "riff_info", "aiff_text"])
+        .action(clap::ArgAction::Append),
+        arg!(
+            --"strip-legacy-tags" "Remove ID3v1 and APE tags unless they are the format's primary tag"
+        ),
+        arg!(
+            --padding <BYTES> "Bytes of padding to leave after written tags"
+        )
+        .value_parser(value_parser!(u32)),
+        arg!(
+            --"id3v2-version" <VERSION> "ID3v2 version to write"
+        )
+        .value_parser(["2.3", "2.4"]),
+        arg!(
+            --"id3v2-encoding" <ENCODING> "Text encoding of written ID3v2 frames, utf8 requires ID3v2.4"
+        )
+        .value_parser(["latin1", "utf16", "utf8"]),
+        arg!(
+            --"drop-unknown-frames" "Don't keep ID3v2 frames imd doesn't know about"
+        ),
+    ];
+}
+
+fn organise_arg_definitions() -> Vec<Arg> {
+    return vec![
+        arg!(
+            --"organise-mode" <MODE> "How organised files are placed"
+        )
+        .value_parser(["copy", "move", "hardlink"]),
+        arg!(
+            --template <TEMPLATE> "Naming template used when organising, e.g. \"{artist}/{album}/{track:02} {title}.{ext}\""
+        ),
+        arg!(
+            -n --"dry-run" "Show what organising would do without touching any files"
+        ),
+    ];
+}
+
+fn match_organise_arg_definitions() -> Vec<Arg> {
+    let mut definitions = vec![
+        arg!(
+            -o --organise <DIR> "Rename the file into DIR using the naming template"
+        )
+        .value_parser(value_parser!(PathBuf)),
+    ];
+    definitions.extend(organise_arg_definitions());
+    return definitions;
+}
+
+fn undo_command() -> Command {
+    return Command::new("undo")
+        .about("Restore the tags overwritten by the last run, a specific run or a specific file")
+        .arg(arg!(
+            --run <RUN_ID> "Undo every write made by this run"
+        ).conflicts_with("file"))
+        .arg(
+            arg!(
+                --file <FILE> "Undo the most recent write to this file"
+            )
+            .value_parser(value_parser!(PathBuf))
+        )
+        .arg(arg!(
+            -f --force "Restore files even if they have changed since imd wrote them"
+        ))
+        .arg(arg!(
+            -l --list "List the recorded runs instead of undoing anything"
+        ));
+}
+
+fn path_arg(matches: &ArgMatches) -> PathBuf {
+    return matches.get_one::<PathBuf>("path").unwrap().clone();
+}
+
+fn organise_options(matches: &ArgMatches) -> Option<OrganiseOptions> {
+    return matches.get_one::<PathBuf>("organise").map(|destination| OrganiseOptions {
+        destination: destination.clone(),
+        dry_run: matches.get_flag("dry-run"),
+    });
+}
+
+fn undo_target(matches: &ArgMatches) -> UndoTarget {
+    if let Some(run_id) = matches.get_one::<String>("run") {
+        return UndoTarget::Run(run_id.clone());
+    }
+    if let Some(file) = matches.get_one::<PathBuf>("file") {
+        return UndoTarget::File(file.clone());
+    }
+    return UndoTarget::LastRun;
+}
+
+/// Applies the flags that override config file values. Subcommands only define the flags that
+/// apply to them, so every lookup tolerates an undefined argument.
+fn apply_command_line_overrides(settings: &mut Settings, matches: &ArgMatches) {
+    if count_is_set(matches, "debug") {
+        settings.general.debug = true;
+    }
+    if count_is_set(matches, "write") {
+        settings.general.write = true;
+    }
+    if flag_is_set(matches, "no-fingerprint") {
+        settings.fingerprint.enabled = false;
+    }
+    if flag_is_set(matches, "no-path-inference") {
+        settings.path_inference.enabled = false;
+    }
+    if flag_is_set(matches, "preserve-mtime") {
+        settings.writer.preserve_mtime = true;
+    }
+    if let Ok(Some(tag_target)) = matches.try_get_one::<String>("tag-target") {
+        settings.writer.tag_target = TagTarget::from_name(tag_target).expect("clap only accepts known tag targets");
+    }
+    if let Ok(Some(tag_types)) = matches.try_get_many::<String>("tag-type") {
+        settings.writer.tag_types = tag_types.cloned().collect();
+        settings.writer.tag_target = TagTarget::Types;
+    }
+    if flag_is_set(matches, "strip-legacy-tags") {
+        settings.writer.strip_legacy_tags = true;
+    }
+    if let Ok(Some(padding)) = matches.try_get_one::<u32>("padding") {
+        settings.writer.padding = Some(*padding);
+    }
+    if let Ok(Some(version)) = matches.try_get_one::<String>("id3v2-version") {
+        settings.writer.id3v2.version = Id3v2Version::from_name(version).expect("clap only accepts known ID3v2 versions");
+    }
+    if let Ok(Some(encoding)) = matches.try_get_one::<String>("id3v2-encoding") {
+        settings.writer.id3v2.encoding = Some(TextEncoding::from_name(encoding).expect("clap only accepts known encodings"));
+    }
+    if flag_is_set(matches, "drop-unknown-frames") {
+        settings.writer.id3v2.preserve_unknown_frames = false;
+    }
+    if let Ok(Some(grouping)) = matches.try_get_one::<String>("group-by") {
+        settings.album.group_by = AlbumGrouping::from_name(grouping).expect("clap only accepts known groupings");
+    }
+    if let Ok(Some(workers)) = matches.try_get_one::<usize>("workers") {
+        settings.batch.workers = *workers;
+    }
+    if flag_is_set(matches, "no-progress") {
+        settings.batch.progress = false;
+    }
+    if let Ok(Some(bind)) = matches.try_get_one::<String>("bind") {
+        settings.server.bind = bind.clone();
+    }
+    if let Ok(Some(review_dir)) = matches.try_get_one::<PathBuf>("review-dir") {
+        settings.watch.review_dir = Some(review_dir.clone());
+    }
+    if let Ok(Some(settle_secs)) = matches.try_get_one::<u64>("settle") {
+        settings.watch.settle_secs = *settle_secs;
+    }
+    if let Ok(Some(min_bitrate)) = matches.try_get_one::<u32>("min-bitrate") {
+        settings.quality.min_bitrate = Some(*min_bitrate);
+    }
+    if flag_is_set(matches, "lossless-only") {
+        settings.quality.lossless_only = true;
+    }
+    if let Ok(Some(quarantine_dir)) = matches.try_get_one::<PathBuf>("quarantine-dir") {
+        settings.duplicates.quarantine_dir = Some(quarantine_dir.clone());
+    }
+    if flag_is_set(matches, "fingerprint") {
+        settings.duplicates.use_fingerprints = true;
+    }
+    if flag_is_set(matches, "resume") {
+        settings.batch.skip = SkipMode::Done;
+    }
+    if flag_is_set(matches, "only-changed") {
+        settings.batch.skip = SkipMode::Unchanged;
+    }
+    if let Ok(Some(notation)) = matches.try_get_one::<String>("key-notation") {
+        settings.analysis.key_notation = KeyNotation::from_name(notation).expect("clap only accepts known key notations");
+    }
+    if flag_is_set(matches, "overwrite") {
+        settings.analysis.overwrite = true;
+    }
+    if let Ok(Some(storefronts)) = matches.try_get_many::<String>("storefront") {
+        settings.providers.storefronts = storefronts.cloned().collect();
+    }
+    if let Ok(Some(providers)) = matches.try_get_many::<String>("provider") {
+        settings.providers.order = providers.cloned().collect();
+    }
+    if let Ok(Some(mode)) = matches.try_get_one::<String>("organise-mode") {
+        settings.naming.mode = OrganiseMode::from_name(mode).expect("clap only accepts known organise modes");
+    }
+    if let Ok(Some(template)) = matches.try_get_one::<String>("template") {
+        settings.naming.template = template.clone();
+    }
+}
+
+fn count_is_set(matches: &ArgMatches, id: &str) -> bool {
+    return !matches!(matches.try_get_one::<u8>(id), Ok(Some(0) | None) | Err(_));
+}
+
+fn flag_is_set(matches: &ArgMatches, id: &str) -> bool {
+    return matches!(matches.try_get_one::<bool>(id), Ok(Some(true)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_is_valid() {
+        build_cli().debug_assert();
     }
 }
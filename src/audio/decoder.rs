@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Decoded audio as one buffer of samples in [-1, 1] per channel.
+pub struct DecodedAudio {
+    pub channels: Vec<Vec<f32>>,
+    pub sample_rate: u32,
+}
+
+impl DecodedAudio {
+    pub fn duration(&self) -> Duration {
+        let frames = self.channels.first().map(|channel| channel.len()).unwrap_or(0);
+        return Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+    }
+
+    /// The average of all channels.
+    pub fn to_mono(&self) -> Vec<f32> {
+        let frames = self.channels.first().map(|channel| channel.len()).unwrap_or(0);
+        let channel_count = self.channels.len() as f32;
+        return (0..frames)
+            .map(|i| self.channels.iter().map(|channel| channel[i]).sum::<f32>() / channel_count)
+            .collect();
+    }
+}
+
+/// Decodes the default audio track of a file, stopping after `max_duration` when given.
+pub fn decode_audio_file(file_path: &Path, max_duration: Option<Duration>) -> Result<DecodedAudio, String> {
+    let file = File::open(file_path).map_err(|e| format!("Failed to open {:?}: {}", file_path, e))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = file_path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Unsupported audio format: {}", e))?;
+    let mut format = probed.format;
+    let track = format.tracks().iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track found")?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.ok_or("Unknown sample rate")?;
+    // Symphonia has no Opus decoder.
+    if track.codec_params.codec == CODEC_TYPE_OPUS {
+        return Err("Opus audio can't be decoded".to_string());
+    }
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+    let max_frames = max_duration.map(|duration| (duration.as_secs_f64() * sample_rate as f64) as usize);
+
+    let mut channels: Vec<Vec<f32>> = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(Error::ResetRequired) => break,
+            Err(e) => return Err(format!("Failed to read audio: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet only loses a few milliseconds of audio.
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        };
+        let spec = *decoded.spec();
+        let channel_count = spec.channels.count();
+        if channels.is_empty() {
+            channels = vec![Vec::new(); channel_count];
+        }
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        for frame in samples.samples().chunks(channel_count) {
+            for (channel, sample) in channels.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+        }
+
+        if max_frames.is_some_and(|max_frames| channels[0].len() >= max_frames) {
+            for channel in channels.iter_mut() {
+                channel.truncate(max_frames.unwrap());
+            }
+            break;
+        }
+    }
+
+    if channels.is_empty() {
+        return Err("No audio decoded".to_string());
+    }
+    return Ok(DecodedAudio {
+        channels,
+        sample_rate,
+    });
+}
+
+/// Resamples by linear interpolation. When downsampling, each output sample first averages the
+/// input samples it covers, a cheap low-pass filter against aliasing.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from_rate as f64 / to_rate as f64;
+    let output_len = (samples.len() as f64 / ratio) as usize;
+    let window = if ratio > 1.0 { ratio.ceil() as usize } else { 1 };
+
+    return (0..output_len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let sample_at = |index: usize| {
+                let start = index.min(samples.len() - 1);
+                let end = (start + window).min(samples.len());
+                samples[start..end].iter().sum::<f32>() / (end - start) as f32
+            };
+            let fraction = (position - index as f64) as f32;
+            sample_at(index) * (1.0 - fraction) + sample_at(index + 1) * fraction
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_decode_wav() {
+        let audio_file = TestAudioFile::wav("decode");
+        let audio = decode_audio_file(audio_file.path(), None).unwrap();
+        assert_eq!(1, audio.channels.len());
+        assert_eq!(8000, audio.sample_rate);
+        assert_eq!(Duration::from_secs(1), audio.duration());
+    }
+
+    #[test]
+    fn test_resample() {
+        let samples: Vec<f32> = (0..8000).map(|i| i as f32 / 8000.0).collect();
+        let resampled = resample(&samples, 8000, 4000);
+        assert_eq!(4000, resampled.len());
+        assert!((resampled[2000] - 0.5).abs() < 0.001);
+    }
+}
@@ -0,0 +1,166 @@
+//! Musical key detection by correlating a chromagram with the Krumhansl-Kessler key profiles.
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use serde::{Deserialize, Serialize};
+use super::decoder::resample;
+
+const ANALYSIS_SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 8192;
+const HOP_SIZE: usize = 4096;
+const MIN_FREQUENCY: f64 = 55.0;
+const MAX_FREQUENCY: f64 = 2000.0;
+const MAJOR_PROFILE: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+const MAJOR_NAMES: [&str; 12] = ["C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B"];
+const MINOR_NAMES: [&str; 12] = ["Cm", "C#m", "Dm", "Ebm", "Em", "Fm", "F#m", "Gm", "G#m", "Am", "Bbm", "Bm"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyNotation {
+    /// Note names, e.g. `Am` or `F#`.
+    Standard,
+    /// Mixed In Key's wheel, e.g. `8A` for A minor.
+    Camelot,
+    /// Traktor's wheel, e.g. `1m` for A minor.
+    OpenKey,
+}
+
+impl KeyNotation {
+    pub fn from_name(name: &str) -> Option<KeyNotation> {
+        return match name {
+            "standard" => Some(KeyNotation::Standard),
+            "camelot" => Some(KeyNotation::Camelot),
+            "open_key" => Some(KeyNotation::OpenKey),
+            _ => None,
+        };
+    }
+}
+
+/// A key by its tonic pitch class, C being 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MusicalKey {
+    pub tonic: usize,
+    pub minor: bool,
+}
+
+impl MusicalKey {
+    pub fn format(&self, notation: KeyNotation) -> String {
+        // Relative keys share a wheel number, a minor key's relative major lies 3 semitones up.
+        let major_tonic = if self.minor { (self.tonic + 3) % 12 } else { self.tonic };
+        let camelot_number = match (8 + 7 * major_tonic) % 12 {
+            0 => 12,
+            number => number,
+        };
+        return match notation {
+            KeyNotation::Standard => if self.minor { MINOR_NAMES[self.tonic] } else { MAJOR_NAMES[self.tonic] }.to_string(),
+            KeyNotation::Camelot => format!("{}{}", camelot_number, if self.minor { "A" } else { "B" }),
+            KeyNotation::OpenKey => format!("{}{}", (camelot_number + 4) % 12 + 1, if self.minor { "m" } else { "d" }),
+        };
+    }
+}
+
+/// Estimates the key of mono samples, `None` for audio without pitched content.
+pub fn detect_key(samples: &[f32], sample_rate: u32) -> Option<MusicalKey> {
+    let chroma = chromagram(&resample(samples, sample_rate, ANALYSIS_SAMPLE_RATE));
+    if chroma.iter().all(|energy| *energy <= f64::EPSILON) {
+        return None;
+    }
+
+    let mut best: Option<(f64, MusicalKey)> = None;
+    for tonic in 0..12 {
+        for (profile, minor) in [(&MAJOR_PROFILE, false), (&MINOR_PROFILE, true)] {
+            let rotated: Vec<f64> = (0..12).map(|pitch_class| profile[(pitch_class + 12 - tonic) % 12]).collect();
+            let correlation = pearson_correlation(&chroma, &rotated);
+            if best.is_none_or(|(best_correlation, _)| correlation > best_correlation) {
+                best = Some((correlation, MusicalKey { tonic, minor }));
+            }
+        }
+    }
+    return best.map(|(_, key)| key);
+}
+
+/// Spectral magnitude summed per pitch class over the whole signal.
+fn chromagram(samples: &[f32]) -> [f64; 12] {
+    let fft = FftPlanner::<f64>::new().plan_fft_forward(FRAME_SIZE);
+    let window: Vec<f64> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / FRAME_SIZE as f64).cos())
+        .collect();
+    let bin_frequency = |bin: usize| bin as f64 * ANALYSIS_SAMPLE_RATE as f64 / FRAME_SIZE as f64;
+    let pitch_classes: Vec<Option<usize>> = (0..FRAME_SIZE / 2)
+        .map(|bin| {
+            let frequency = bin_frequency(bin);
+            if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+                return None;
+            }
+            // MIDI note 69 is A4 at 440 Hz.
+            let note = (69.0 + 12.0 * (frequency / 440.0).log2()).round() as i64;
+            Some(note.rem_euclid(12) as usize)
+        })
+        .collect();
+
+    let mut chroma = [0.0; 12];
+    let mut start = 0;
+    while start + FRAME_SIZE <= samples.len() {
+        let mut frame: Vec<Complex<f64>> = samples[start..start + FRAME_SIZE].iter().zip(&window)
+            .map(|(sample, weight)| Complex::new(*sample as f64 * weight, 0.0))
+            .collect();
+        fft.process(&mut frame);
+        for (bin, pitch_class) in pitch_classes.iter().enumerate() {
+            if let Some(pitch_class) = pitch_class {
+                chroma[*pitch_class] += frame[bin].norm();
+            }
+        }
+        start += HOP_SIZE;
+    }
+    return chroma;
+}
+
+fn pearson_correlation(a: &[f64], b: &[f64]) -> f64 {
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let covariance: f64 = a.iter().zip(b).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+    let deviation = |values: &[f64], mean: f64| values.iter().map(|x| (x - mean).powi(2)).sum::<f64>().sqrt();
+    let denominator = deviation(a, mean_a) * deviation(b, mean_b);
+    if denominator == 0.0 {
+        return 0.0;
+    }
+    return covariance / denominator;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chords given as MIDI notes, one second each.
+    fn chords(progression: &[&[u8]], sample_rate: u32) -> Vec<f32> {
+        return progression.iter()
+            .flat_map(|notes| (0..sample_rate as usize).map(move |i| {
+                let t = i as f64 / sample_rate as f64;
+                notes.iter()
+                    .map(|note| (2.0 * std::f64::consts::PI * 440.0 * 2f64.powf((*note as f64 - 69.0) / 12.0) * t).sin())
+                    .sum::<f64>() as f32 * 0.2
+            }))
+            .collect();
+    }
+
+    #[test]
+    fn test_detect_key() {
+        // I - IV - V - I in C major and i - iv - V - i in A minor.
+        let c_major = chords(&[&[60, 64, 67], &[65, 69, 72], &[67, 71, 74], &[60, 64, 67]], 22050);
+        assert_eq!(Some(MusicalKey { tonic: 0, minor: false }), detect_key(&c_major, 22050));
+        let a_minor = chords(&[&[57, 60, 64], &[62, 65, 69], &[64, 68, 71], &[57, 60, 64]], 22050);
+        assert_eq!(Some(MusicalKey { tonic: 9, minor: true }), detect_key(&a_minor, 22050));
+        assert_eq!(None, detect_key(&vec![0.0; 22050 * 2], 22050));
+    }
+
+    #[test]
+    fn test_key_notations() {
+        let a_minor = MusicalKey { tonic: 9, minor: true };
+        let e_major = MusicalKey { tonic: 4, minor: false };
+        let f_sharp_minor = MusicalKey { tonic: 6, minor: true };
+        assert_eq!(("Am", "8A", "1m"), (a_minor.format(KeyNotation::Standard).as_str(), a_minor.format(KeyNotation::Camelot).as_str(), a_minor.format(KeyNotation::OpenKey).as_str()));
+        assert_eq!(("E", "12B", "5d"), (e_major.format(KeyNotation::Standard).as_str(), e_major.format(KeyNotation::Camelot).as_str(), e_major.format(KeyNotation::OpenKey).as_str()));
+        assert_eq!(("F#m", "11A", "4m"), (f_sharp_minor.format(KeyNotation::Standard).as_str(), f_sharp_minor.format(KeyNotation::Camelot).as_str(), f_sharp_minor.format(KeyNotation::OpenKey).as_str()));
+    }
+}
@@ -0,0 +1,217 @@
+//! EBU R128 / ITU-R BS.1770 loudness: K-weighted, gated integrated loudness and true peak.
+
+use super::decoder::DecodedAudio;
+
+const BLOCK_SECS: f64 = 0.4;
+const BLOCKS_PER_WINDOW: usize = 4;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+const OVERSAMPLING_TAPS_PER_PHASE: usize = 12;
+
+/// Loudness of one track. The gating block powers are kept so tracks can be combined into an
+/// album loudness, which is gated over all blocks of the album rather than averaged.
+#[derive(Clone, Debug)]
+pub struct Loudness {
+    block_powers: Vec<f64>,
+    /// Linear true peak, 1.0 being full scale.
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    pub fn measure(audio: &DecodedAudio) -> Loudness {
+        let weights = channel_weights(audio.channels.len());
+        let filtered: Vec<Vec<f64>> = audio.channels.iter().map(|channel| k_weight(channel, audio.sample_rate)).collect();
+
+        // Blocks overlap by 75%, so sum the squares per quarter block once and add them up.
+        let step = ((BLOCK_SECS / BLOCKS_PER_WINDOW as f64) * audio.sample_rate as f64).round() as usize;
+        let frames = filtered.first().map(|channel| channel.len()).unwrap_or(0);
+        let steps = frames.checked_div(step).unwrap_or(0);
+        let step_powers: Vec<f64> = (0..steps)
+            .map(|index| {
+                filtered.iter().zip(&weights)
+                    .map(|(channel, weight)| weight * channel[index * step..(index + 1) * step].iter().map(|sample| sample * sample).sum::<f64>())
+                    .sum()
+            })
+            .collect();
+        let block_powers = step_powers.windows(BLOCKS_PER_WINDOW)
+            .map(|window| window.iter().sum::<f64>() / (step * BLOCKS_PER_WINDOW) as f64)
+            .collect();
+
+        let true_peak = audio.channels.iter()
+            .map(|channel| true_peak(channel, audio.sample_rate))
+            .fold(0.0, f64::max);
+        return Loudness { block_powers, true_peak };
+    }
+
+    /// Gated integrated loudness in LUFS, `None` for silence or audio shorter than one block.
+    pub fn integrated(&self) -> Option<f64> {
+        return gated_loudness(&self.block_powers);
+    }
+
+    /// The loudness and true peak of tracks played as one album.
+    pub fn album(tracks: &[Loudness]) -> Loudness {
+        return Loudness {
+            block_powers: tracks.iter().flat_map(|track| track.block_powers.iter().copied()).collect(),
+            true_peak: tracks.iter().map(|track| track.true_peak).fold(0.0, f64::max),
+        };
+    }
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    return -0.691 + 10.0 * power.log10();
+}
+
+fn gated_loudness(block_powers: &[f64]) -> Option<f64> {
+    let mean_above = |threshold: f64| {
+        let gated: Vec<f64> = block_powers.iter().copied().filter(|power| power_to_lufs(*power) > threshold).collect();
+        (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+    };
+    let relative_gate = power_to_lufs(mean_above(ABSOLUTE_GATE_LUFS)?) + RELATIVE_GATE_LU;
+    return mean_above(relative_gate.max(ABSOLUTE_GATE_LUFS)).map(power_to_lufs);
+}
+
+/// BS.1770 channel weights for mono, stereo and 5.1 (L, R, C, LFE, Ls, Rs) layouts.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    return (0..channels)
+        .map(|channel| match (channels, channel) {
+            (6, 3) => 0.0,
+            (6, 4 | 5) => 1.41,
+            _ => 1.0,
+        })
+        .collect();
+}
+
+/// The K-weighting pre-filter, a high shelf followed by a high pass, with coefficients derived for
+/// any sample rate the way libebur128 does.
+fn k_weight(samples: &[f32], sample_rate: u32) -> Vec<f64> {
+    let rate = sample_rate as f64;
+
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    return high_pass.filter(&shelf.filter(&samples.iter().map(|sample| *sample as f64).collect::<Vec<f64>>()));
+}
+
+/// A second order IIR filter with `a0` normalized to 1.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn filter(&self, input: &[f64]) -> Vec<f64> {
+        let (mut z1, mut z2) = (0.0, 0.0);
+        return input.iter()
+            .map(|x| {
+                let w = x - self.a[0] * z1 - self.a[1] * z2;
+                let y = self.b[0] * w + self.b[1] * z1 + self.b[2] * z2;
+                z2 = z1;
+                z1 = w;
+                y
+            })
+            .collect();
+    }
+}
+
+/// The peak of the signal oversampled to at least 192 kHz, catching the inter-sample peaks a DAC
+/// reconstructs.
+fn true_peak(samples: &[f32], sample_rate: u32) -> f64 {
+    let sample_peak = samples.iter().map(|sample| sample.abs() as f64).fold(0.0, f64::max);
+    let factor = match sample_rate {
+        0..=95_999 => 4,
+        96_000..=191_999 => 2,
+        _ => return sample_peak,
+    };
+
+    let half_taps = OVERSAMPLING_TAPS_PER_PHASE / 2;
+    let phases: Vec<Vec<f64>> = (1..factor)
+        .map(|phase| {
+            let offset = phase as f64 / factor as f64;
+            (0..OVERSAMPLING_TAPS_PER_PHASE)
+                .map(|tap| {
+                    // Distance from the interpolated point to input sample `tap`, windowed sinc.
+                    let x = tap as f64 - (half_taps as f64 - 1.0) - offset;
+                    let window = 0.5 + 0.5 * (std::f64::consts::PI * x / half_taps as f64).cos();
+                    sinc(x) * window
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut peak = sample_peak;
+    for start in 0..samples.len().saturating_sub(OVERSAMPLING_TAPS_PER_PHASE) {
+        let window = &samples[start..start + OVERSAMPLING_TAPS_PER_PHASE];
+        for coefficients in &phases {
+            let value: f64 = window.iter().zip(coefficients).map(|(sample, coefficient)| *sample as f64 * coefficient).sum();
+            peak = peak.max(value.abs());
+        }
+    }
+    return peak;
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    let pi_x = std::f64::consts::PI * x;
+    return pi_x.sin() / pi_x;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, secs: f64, sample_rate: u32, phase: f64) -> Vec<f32> {
+        return (0..(secs * sample_rate as f64) as usize)
+            .map(|i| (amplitude * (2.0 * std::f64::consts::PI * frequency * i as f64 / sample_rate as f64 + phase).sin()) as f32)
+            .collect();
+    }
+
+    #[test]
+    fn test_sine_loudness() {
+        // A 1 kHz sine at -20 dBFS reads -23 LUFS in one channel and -20 LUFS in both.
+        let mono = DecodedAudio { channels: vec![sine(1000.0, 0.1, 5.0, 48000, 0.0)], sample_rate: 48000 };
+        let loudness = Loudness::measure(&mono);
+        assert!((loudness.integrated().unwrap() + 23.0).abs() < 0.05, "{:?}", loudness.integrated());
+
+        let stereo = DecodedAudio { channels: vec![sine(1000.0, 0.1, 5.0, 44100, 0.0); 2], sample_rate: 44100 };
+        assert!((Loudness::measure(&stereo).integrated().unwrap() + 20.0).abs() < 0.05);
+
+        let silence = DecodedAudio { channels: vec![vec![0.0; 48000]], sample_rate: 48000 };
+        assert_eq!(None, Loudness::measure(&silence).integrated());
+    }
+
+    #[test]
+    fn test_true_peak_between_samples() {
+        // A quarter sample rate sine sampled 45 degrees off its peaks never hits a sample at 1.0.
+        let samples = sine(12000.0, 1.0, 0.1, 48000, std::f64::consts::FRAC_PI_4);
+        let sample_peak = samples.iter().map(|sample| sample.abs()).fold(0.0, f32::max);
+        assert!((sample_peak - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.001);
+        assert!(true_peak(&samples, 48000) > 0.95);
+    }
+
+    #[test]
+    fn test_album_loudness_gates_all_blocks() {
+        let loud = Loudness::measure(&DecodedAudio { channels: vec![sine(1000.0, 0.1, 5.0, 48000, 0.0)], sample_rate: 48000 });
+        let quiet = Loudness::measure(&DecodedAudio { channels: vec![sine(1000.0, 0.01, 5.0, 48000, 0.0)], sample_rate: 48000 });
+        let album = Loudness::album(&[loud.clone(), quiet.clone()]);
+        // The quiet track sits 20 LU below, under the relative gate, so the album is as loud as the loud track.
+        assert!((album.integrated().unwrap() - loud.integrated().unwrap()).abs() < 0.05);
+        assert!((album.true_peak - loud.true_peak).abs() < 1e-9);
+    }
+}
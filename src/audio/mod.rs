@@ -0,0 +1,4 @@
+pub mod decoder;
+pub mod loudness;
+pub mod tempo;
+pub mod key;
@@ -0,0 +1,112 @@
+//! Tempo estimation from the autocorrelation of an onset strength envelope.
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use super::decoder::resample;
+
+const ANALYSIS_SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 1024;
+const HOP_SIZE: usize = 128;
+/// Seconds over which the envelope's local mean is removed.
+const DETREND_SECS: f64 = 1.0;
+
+/// Estimates the tempo of mono samples in beats per minute, within `[min_bpm, max_bpm]`.
+/// Returns `None` for audio without rhythmic onsets, like silence or a steady tone.
+pub fn estimate_bpm(samples: &[f32], sample_rate: u32, min_bpm: f64, max_bpm: f64) -> Option<f64> {
+    let samples = resample(samples, sample_rate, ANALYSIS_SAMPLE_RATE);
+    let envelope = onset_envelope(&samples);
+    let frames_per_minute = 60.0 * ANALYSIS_SAMPLE_RATE as f64 / HOP_SIZE as f64;
+    let min_lag = (frames_per_minute / max_bpm).floor().max(1.0) as usize;
+    let max_lag = (frames_per_minute / min_bpm).ceil() as usize;
+    if envelope.len() < 4 * max_lag {
+        return None;
+    }
+
+    let correlations: Vec<f64> = (0..=2 * max_lag + 1).map(|lag| autocorrelation(&envelope, lag)).collect();
+    if correlations[0] <= 0.0 {
+        return None;
+    }
+    // Rewarding the correlation at twice the lag favours the beat over its off-beats.
+    let score = |lag: usize| correlations[lag] + 0.5 * correlations[2 * lag];
+    let best_lag = (min_lag..=max_lag).max_by(|a, b| score(*a).total_cmp(&score(*b)))?;
+    if score(best_lag) <= 0.0 {
+        return None;
+    }
+
+    // Parabolic interpolation around the peak for a tempo between whole frame lags.
+    let (left, centre, right) = (score(best_lag.saturating_sub(1).max(1)), score(best_lag), score(best_lag + 1));
+    let denominator = left - 2.0 * centre + right;
+    let offset = if denominator.abs() > f64::EPSILON { (0.5 * (left - right) / denominator).clamp(-0.5, 0.5) } else { 0.0 };
+    return Some(frames_per_minute / (best_lag as f64 + offset));
+}
+
+/// Spectral flux of the log magnitude spectrum, with its local mean removed so only onsets remain.
+fn onset_envelope(samples: &[f32]) -> Vec<f64> {
+    let fft = FftPlanner::<f64>::new().plan_fft_forward(FRAME_SIZE);
+    let window: Vec<f64> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / FRAME_SIZE as f64).cos())
+        .collect();
+
+    let mut flux: Vec<f64> = Vec::new();
+    let mut previous: Vec<f64> = vec![0.0; FRAME_SIZE / 2];
+    let mut start = 0;
+    while start + FRAME_SIZE <= samples.len() {
+        let mut frame: Vec<Complex<f64>> = samples[start..start + FRAME_SIZE].iter().zip(&window)
+            .map(|(sample, weight)| Complex::new(*sample as f64 * weight, 0.0))
+            .collect();
+        fft.process(&mut frame);
+        let magnitudes: Vec<f64> = frame[..FRAME_SIZE / 2].iter().map(|bin| (1.0 + 100.0 * bin.norm()).ln()).collect();
+        flux.push(magnitudes.iter().zip(&previous).map(|(current, previous)| (current - previous).max(0.0)).sum());
+        previous = magnitudes;
+        start += HOP_SIZE;
+    }
+    if !flux.is_empty() {
+        flux[0] = 0.0;
+    }
+
+    let half_window = (DETREND_SECS * ANALYSIS_SAMPLE_RATE as f64 / HOP_SIZE as f64 / 2.0) as usize;
+    return (0..flux.len())
+        .map(|i| {
+            let window = &flux[i.saturating_sub(half_window)..(i + half_window + 1).min(flux.len())];
+            (flux[i] - window.iter().sum::<f64>() / window.len() as f64).max(0.0)
+        })
+        .collect();
+}
+
+fn autocorrelation(envelope: &[f64], lag: usize) -> f64 {
+    if lag >= envelope.len() {
+        return 0.0;
+    }
+    let products: f64 = envelope.iter().zip(&envelope[lag..]).map(|(a, b)| a * b).sum();
+    return products / (envelope.len() - lag) as f64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Short decaying 1 kHz clicks at the given tempo.
+    fn click_track(bpm: f64, secs: f64, sample_rate: u32) -> Vec<f32> {
+        let beat = 60.0 / bpm;
+        return (0..(secs * sample_rate as f64) as usize)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let since_beat = t % beat;
+                ((2.0 * std::f64::consts::PI * 1000.0 * t).sin() * (-since_beat * 60.0).exp() * 0.5) as f32
+            })
+            .collect();
+    }
+
+    #[test]
+    fn test_estimate_bpm() {
+        for bpm in [90.0, 120.0, 128.0, 174.0] {
+            let estimate = estimate_bpm(&click_track(bpm, 12.0, 22050), 22050, 70.0, 180.0).unwrap();
+            assert!((estimate - bpm).abs() < 1.0, "expected {} BPM, estimated {}", bpm, estimate);
+        }
+    }
+
+    #[test]
+    fn test_no_tempo_in_silence() {
+        assert_eq!(None, estimate_bpm(&vec![0.0; 22050 * 10], 22050, 70.0, 180.0));
+    }
+}
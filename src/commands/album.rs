@@ -0,0 +1,92 @@
+use std::path::Path;
+use crate::history::journal::new_run_id;
+use crate::library::scan::find_audio_files;
+use crate::metadata::album_matcher::{group_files, match_album, AlbumGroup};
+use crate::metadata::itunes_metadata_extractor::find_album_releases;
+use crate::metadata::metadata_fixer::combine_metadata;
+use crate::metadata::path_inference::infer_from_path;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::{open_journal, write_tags};
+
+pub fn run(path: &Path, settings: &Settings) {
+    if !path.is_dir() {
+        panic!("ERROR: Provided path is not a directory!");
+    }
+
+    let files = find_audio_files(path).into_iter()
+        .map(|file| {
+            let song_metadata = infer_from_path(&file, SongMetadata::read_metadata_from_audio_file(&file), &settings.path_inference);
+            (file, song_metadata)
+        })
+        .collect();
+    let groups = group_files(files, settings.album.group_by);
+    println!("Found {} album(s)", groups.len());
+
+    let run_id = new_run_id();
+    let mut written = 0;
+    for group in &groups {
+        written += match_group(group, &run_id, settings);
+    }
+    if written > 0 && settings.journal.enabled {
+        println!("Run ID: {}", run_id);
+    }
+}
+
+/// Matches one album and writes the release to its files when enabled, returning how many files were written.
+fn match_group(group: &AlbumGroup, run_id: &str, settings: &Settings) -> usize {
+    let folder = group.files[0].0.parent().unwrap_or(Path::new(""));
+    println!("########################################################################################");
+    println!("Album in {:?} with {} file(s)", folder, group.files.len());
+    let Some(term) = group.search_term() else {
+        eprintln!("WARN: No artist or album to search for, skipping");
+        return 0;
+    };
+
+    let releases = find_album_releases(&term, settings);
+    let files: Vec<SongMetadata> = group.files.iter().map(|(_, song_metadata)| song_metadata.clone()).collect();
+    let album_match = match match_album(&files, &releases, &settings.scoring, &settings.album) {
+        Some(album_match) if album_match.score >= settings.thresholds.minimum_score => album_match,
+        _ => {
+            eprintln!("WARN: No release scored at least {:.2}, skipping", settings.thresholds.minimum_score);
+            return 0;
+        }
+    };
+
+    let release = album_match.release;
+    println!("Best release: {} - {} ({} tracks, iTunes id {}), score {:.2}",
+        release.artist.as_deref().unwrap_or("?"), release.album, release.tracks.len(), release.collection_id, album_match.score);
+    // Files fitting their assigned track worse than a single match may are left alone.
+    let tracks: Vec<Option<usize>> = (0..group.files.len())
+        .map(|file| album_match.track_for(file, settings.thresholds.minimum_score))
+        .collect();
+    for ((path, _), track) in group.files.iter().zip(&tracks) {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        match track.map(|track| &release.tracks[track]) {
+            Some(track) => println!("  {:<40} -> {:>2}-{:02} {}", file_name,
+                track.disc_number.unwrap_or(1), track.track_number.unwrap_or(0), track.title.as_deref().unwrap_or("?")),
+            None => println!("  {:<40} -> no matching track", file_name),
+        }
+    }
+
+    if !settings.general.write {
+        return 0;
+    }
+    if album_match.score < settings.thresholds.auto_accept_score {
+        println!("Album score {:.2} is below the auto accept score {:.2}, not writing metadata", album_match.score, settings.thresholds.auto_accept_score);
+        return 0;
+    }
+
+    let mut journal = settings.journal.enabled.then(|| open_journal(settings));
+    let mut written = 0;
+    for ((path, song_metadata), track) in group.files.iter().zip(&tracks) {
+        let Some(track) = track else {
+            continue;
+        };
+        let fixed_metadata = combine_metadata(song_metadata, &release.tracks[*track], &settings.merge);
+        write_tags(path, &fixed_metadata, settings, run_id, journal.as_mut());
+        written += 1;
+    }
+    println!("Wrote {} file(s)", written);
+    return written;
+}
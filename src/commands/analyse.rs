@@ -0,0 +1,77 @@
+use std::path::Path;
+use std::sync::Mutex;
+use crate::audio::decoder::decode_audio_file;
+use crate::audio::key::detect_key;
+use crate::audio::tempo::estimate_bpm;
+use crate::history::journal::{new_run_id, Journal};
+use crate::history::state_db::FileOutcome;
+use crate::library::scan::find_audio_files;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::batch::run_batch;
+use super::{open_journal, write_tags};
+
+/// Detects the tempo and key of a file, or of every file below a directory, and writes them when
+/// enabled. Values already in the tags are kept unless overwriting is enabled. Files are decoded
+/// and analysed in parallel.
+pub fn run(path: &Path, settings: &Settings) {
+    let files = if path.is_dir() {
+        find_audio_files(path)
+    } else if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        panic!("ERROR: Provided path is not a file or directory!");
+    };
+
+    let run_id = new_run_id();
+    let journal = Mutex::new((settings.general.write && settings.journal.enabled).then(|| open_journal(settings)));
+    let task = if settings.general.write { "analyse_write" } else { "analyse" };
+    run_batch(files, task, &run_id, settings, |file| analyse_file(file, settings, &run_id, &journal));
+
+    let journal = journal.into_inner().expect("ERROR: Journal lock poisoned");
+    if journal.is_some_and(|journal| journal.entries().iter().any(|entry| entry.run_id == run_id)) {
+        println!("Run ID: {}", run_id);
+    }
+}
+
+/// Analyses one file and writes the results.
+fn analyse_file(file: &Path, settings: &Settings, run_id: &str, journal: &Mutex<Option<Journal>>) -> FileOutcome {
+    let analysis = &settings.analysis;
+    let audio = match decode_audio_file(file, None) {
+        Ok(audio) => audio,
+        Err(e) => return FileOutcome::failed(format!("Failed to decode: {}", e)),
+    };
+    let mono = audio.to_mono();
+    let bpm = estimate_bpm(&mono, audio.sample_rate, analysis.min_bpm, analysis.max_bpm).map(|bpm| bpm.round() as u16);
+    let key = detect_key(&mono, audio.sample_rate).map(|key| key.format(analysis.key_notation));
+    let summary = format!(
+        "{:>7}  {}",
+        bpm.map(|bpm| format!("{} BPM", bpm)).unwrap_or_else(|| "no BPM".to_string()),
+        key.as_deref().unwrap_or("no key"),
+    );
+    if !settings.general.write {
+        return FileOutcome::new("analysed", summary);
+    }
+    if bpm.is_none() && key.is_none() {
+        return FileOutcome::new("nothing detected", summary);
+    }
+
+    let song_metadata = SongMetadata::read_metadata_from_audio_file(file);
+    let updated = SongMetadata {
+        bpm: detected_or_existing(bpm, &song_metadata.bpm, analysis.overwrite),
+        initial_key: detected_or_existing(key, &song_metadata.initial_key, analysis.overwrite),
+        ..song_metadata.clone()
+    };
+    if updated.bpm == song_metadata.bpm && updated.initial_key == song_metadata.initial_key {
+        return FileOutcome::new("already tagged", summary);
+    }
+    write_tags(file, &updated, settings, run_id, journal.lock().expect("ERROR: Journal lock poisoned").as_mut());
+    return FileOutcome::new("written", summary);
+}
+
+fn detected_or_existing<T: Clone>(detected: Option<T>, existing: &Option<T>, overwrite: bool) -> Option<T> {
+    if existing.is_some() && !overwrite {
+        return existing.clone();
+    }
+    return detected.or(existing.clone());
+}
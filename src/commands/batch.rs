@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+use crate::history::state_db::{default_state_path, FileOutcome, SkipMode, StateDb};
+use crate::library::progress::BatchProgress;
+use crate::library::worker_pool::{run_parallel, worker_count};
+use crate::metadata::audio_properties::AudioProperties;
+use crate::settings::{QualitySettings, Settings};
+
+/// Files left out by the quality settings aren't recorded, so they are picked up once the
+/// settings change.
+const FILTERED_OUTCOME: &str = "filtered";
+
+/// Runs `process` on every file with the worker pool and a progress bar. Files the state database
+/// says can be skipped are left out, as are files below the quality settings, and every other
+/// outcome is recorded there under `task`.
+pub fn run_batch<F>(files: Vec<PathBuf>, task: &str, run_id: &str, settings: &Settings, process: F)
+where
+    F: Fn(&Path) -> FileOutcome + Sync,
+{
+    let batch = &settings.batch;
+    let state = (batch.record_state || batch.skip != SkipMode::None)
+        .then(|| StateDb::open(&batch.state_path.clone().unwrap_or_else(default_state_path)));
+
+    let total_files = files.len();
+    let files = match &state {
+        Some(state) => state.pending(files, task, batch.skip),
+        None => files,
+    };
+    if files.len() < total_files {
+        println!("Skipping {} file(s) already processed", total_files - files.len());
+    }
+    if files.is_empty() {
+        println!("Nothing to process");
+        return;
+    }
+
+    let progress = BatchProgress::new(files.len(), batch.progress && files.len() > 1);
+    run_parallel(
+        &files,
+        worker_count(batch.workers),
+        |file| match quality_shortfall(file, &settings.quality) {
+            Some(reason) => FileOutcome::new(FILTERED_OUTCOME, format!("Skipped, {}", reason)),
+            None => process(file),
+        },
+        |file, result| {
+            let failed;
+            let outcome = match result {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    failed = FileOutcome::failed(e.clone());
+                    &failed
+                },
+            };
+            progress.println(&format!("{}: {}", file.display(), outcome.summary));
+            if let Some(state) = state.as_ref().filter(|_| batch.record_state && outcome.state != FILTERED_OUTCOME) {
+                state.record(file, task, run_id, outcome);
+            }
+            progress.finish_file(outcome.state);
+        },
+    );
+    progress.finish();
+}
+
+/// Unreadable files pass, `process` reports them.
+fn quality_shortfall(file: &Path, quality: &QualitySettings) -> Option<String> {
+    if quality.min_bitrate.is_none() && !quality.lossless_only {
+        return None;
+    }
+    return AudioProperties::read(file).ok()?.quality_shortfall(quality);
+}
@@ -0,0 +1,25 @@
+use crate::app_config::CacheAction;
+use crate::settings::Settings;
+
+pub fn run(action: &CacheAction, settings: &Settings) {
+    let cache = match settings.cache.open() {
+        Some(cache) => cache,
+        None => {
+            println!("The response cache is disabled");
+            return;
+        },
+    };
+
+    match action {
+        CacheAction::Info => {
+            let stats = cache.stats();
+            println!("Cache directory: {:?}", cache.dir());
+            println!("Responses:       {} ({} expired)", stats.entries, stats.expired);
+            println!("Size:            {} bytes", stats.size_bytes);
+        },
+        CacheAction::Clear { expired_only } => {
+            let removed = cache.clear(*expired_only);
+            println!("Removed {} cached responses", removed);
+        },
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+use crate::history::journal::new_run_id;
+use crate::library::scan::find_audio_files;
+use crate::metadata::album_checker::check_album;
+use crate::metadata::album_matcher::group_files;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::{open_journal, write_tags};
+
+pub fn run(path: &Path, fix: bool, settings: &Settings) {
+    if !path.is_dir() {
+        panic!("ERROR: Provided path is not a directory!");
+    }
+
+    let files = find_audio_files(path).into_iter()
+        .map(|file| {
+            let song_metadata = SongMetadata::read_metadata_from_audio_file(&file);
+            (file, song_metadata)
+        })
+        .collect();
+    let groups = group_files(files, settings.album.group_by);
+
+    let run_id = new_run_id();
+    let mut journal = (fix && settings.journal.enabled).then(|| open_journal(settings));
+    let (mut issue_count, mut fixable_count, mut written) = (0, 0, 0);
+    for group in &groups {
+        let issues = check_album(group, &settings.path_inference);
+        if issues.is_empty() {
+            continue;
+        }
+
+        let folder = group.files[0].0.parent().unwrap_or(Path::new(""));
+        println!("Album in {:?} with {} file(s):", folder, group.files.len());
+        for issue in &issues {
+            let marker = if issue.fixes.is_empty() { "" } else { " (fixable)" };
+            println!("  {}: {}{}", issue.kind.description(), issue.details, marker);
+        }
+        issue_count += issues.len();
+        fixable_count += issues.iter().filter(|issue| !issue.fixes.is_empty()).count();
+        if !fix {
+            continue;
+        }
+
+        for (index, (file, song_metadata)) in group.files.iter().enumerate() {
+            let fixes: Vec<_> = issues.iter().flat_map(|issue| &issue.fixes).filter(|(file, _)| *file == index).collect();
+            if fixes.is_empty() {
+                continue;
+            }
+            let mut fixed_metadata = song_metadata.clone();
+            for (_, field_fix) in fixes {
+                field_fix.apply(&mut fixed_metadata);
+            }
+            write_tags(file, &fixed_metadata, settings, &run_id, journal.as_mut());
+            written += 1;
+        }
+    }
+
+    println!("Checked {} album(s), found {} issue(s), {} fixable", groups.len(), issue_count, fixable_count);
+    if fix {
+        println!("Fixed {} file(s)", written);
+        if written > 0 && journal.is_some() {
+            println!("Run ID: {}", run_id);
+        }
+    } else if fixable_count > 0 {
+        println!("Run with --fix to apply the fixable changes");
+    }
+}
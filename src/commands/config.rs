@@ -0,0 +1,17 @@
+use std::io;
+use clap_complete::Shell;
+use crate::app_config::build_cli;
+use crate::settings::{default_config_path, Settings};
+
+pub fn run(settings: &Settings) {
+    if let Some(config_path) = default_config_path() {
+        println!("# Default config file: {:?}", config_path);
+    }
+    println!("{}", toml::to_string(settings).expect("settings serialize to TOML"));
+}
+
+pub fn print_completions(shell: Shell) {
+    let mut cli = build_cli();
+    let name = cli.get_name().to_string();
+    clap_complete::generate(shell, &mut cli, name, &mut io::stdout());
+}
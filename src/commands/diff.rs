@@ -0,0 +1,29 @@
+use std::path::Path;
+use crate::fingerprint::identify_by_fingerprint;
+use crate::metadata::metadata_fixer;
+use crate::metadata::path_inference::infer_from_path;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::require_file;
+
+pub fn run(path: &Path, settings: &Settings) {
+    require_file(path);
+    let song_metadata = identify_by_fingerprint(path, SongMetadata::read_metadata_from_audio_file(path), settings);
+    let song_metadata = infer_from_path(path, song_metadata, &settings.path_inference);
+    let Some((fixed_metadata, score)) = metadata_fixer::get_fixed_metadata(&song_metadata, settings) else {
+        println!("No match found");
+        return;
+    };
+
+    println!("Match score: {:.2}", score);
+    let mut changes = 0;
+    for ((label, current), (_, fixed)) in song_metadata.display_fields().into_iter().zip(fixed_metadata.display_fields()) {
+        if current != fixed {
+            println!("{:<20}{:?} -> {:?}", format!("{}:", label), current, fixed);
+            changes += 1;
+        }
+    }
+    if changes == 0 {
+        println!("No changes");
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::fingerprint::Fingerprint;
+use crate::library::duplicates::{find_duplicates, Candidate, DuplicateAction};
+use crate::library::progress::BatchProgress;
+use crate::library::scan::find_audio_files;
+use crate::library::worker_pool::{run_parallel, worker_count};
+use crate::metadata::song_metadata::SongMetadata;
+use crate::organise::file_organiser::{move_file, resolve_collision};
+use crate::settings::Settings;
+
+const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+/// Finds the duplicate songs below a directory and reports them, or moves or deletes every copy
+/// but the recommended keeper.
+pub fn run(path: &Path, action: DuplicateAction, confirmed: bool, settings: &Settings) {
+    if !path.is_dir() {
+        panic!("ERROR: Provided path is not a directory!");
+    }
+
+    let files = find_audio_files(path);
+    if files.is_empty() {
+        println!("No audio files found");
+        return;
+    }
+    let candidates = read_candidates(files, settings);
+    let groups = find_duplicates(&candidates, &settings.duplicates, &settings.quality);
+    let mut redundant: Vec<&Candidate> = Vec::new();
+    for (number, group) in groups.iter().enumerate() {
+        let keeper = &candidates[group.files[0]];
+        println!("Duplicate group {}: {} - {}", number + 1, keeper.metadata.artist().unwrap_or_default(), keeper.metadata.title.clone().unwrap_or_default());
+        for (position, index) in group.files.iter().enumerate() {
+            let candidate = &candidates[*index];
+            let audio = candidate.metadata.audio.as_ref().map(|audio| audio.describe()).unwrap_or_default();
+            println!("  {:<6} {}  ({})", if position == 0 { "keep" } else { "remove" }, candidate.path.display(), audio);
+            if position > 0 {
+                redundant.push(candidate);
+            }
+        }
+    }
+    let redundant_bytes: u64 = redundant.iter().filter_map(|candidate| fs::metadata(&candidate.path).ok()).map(|metadata| metadata.len()).sum();
+    println!("{} duplicate group(s), {} file(s) to remove ({:.1} MB)", groups.len(), redundant.len(), redundant_bytes as f64 / 1_000_000.0);
+    if redundant.is_empty() {
+        return;
+    }
+
+    match action {
+        DuplicateAction::Report => {},
+        DuplicateAction::Quarantine => {
+            let quarantine_dir = settings.duplicates.quarantine_dir.clone().unwrap_or_else(default_quarantine_dir);
+            for candidate in redundant {
+                // Keeping the path below the scanned folder tells copies with the same name apart.
+                let relative_path = candidate.path.strip_prefix(path).unwrap_or(&candidate.path);
+                let target = resolve_collision(&quarantine_dir.join(relative_path));
+                let moved = target.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| move_file(&candidate.path, &target));
+                match moved {
+                    Ok(_) => println!("Moved {:?} to {:?}", candidate.path, target),
+                    Err(e) => eprintln!("WARN: Failed to move {:?} to {:?}: {}", candidate.path, target, e),
+                }
+            }
+        },
+        DuplicateAction::Delete => {
+            if !confirmed && !confirm(&format!("Delete {} file(s)?", redundant.len())) {
+                println!("Nothing deleted");
+                return;
+            }
+            for candidate in redundant {
+                match fs::remove_file(&candidate.path) {
+                    Ok(_) => println!("Deleted {:?}", candidate.path),
+                    Err(e) => eprintln!("WARN: Failed to delete {:?}: {}", candidate.path, e),
+                }
+            }
+        },
+    }
+}
+
+/// Reads the tags and audio properties of every file in parallel, and the fingerprints when enabled.
+fn read_candidates(files: Vec<PathBuf>, settings: &Settings) -> Vec<Candidate> {
+    let progress = BatchProgress::new(files.len(), settings.batch.progress && files.len() > 1);
+    let max_length = Duration::from_secs(settings.fingerprint.max_length_secs);
+    let results = run_parallel(
+        &files,
+        worker_count(settings.batch.workers),
+        |file| {
+            let fingerprint = settings.duplicates.use_fingerprints
+                .then(|| Fingerprint::calculate(file, max_length))
+                .and_then(|fingerprint| fingerprint.map_err(|e| progress.println(&format!("WARN: Failed to fingerprint {:?}: {}", file, e))).ok());
+            Candidate {
+                path: file.clone(),
+                metadata: SongMetadata::read_metadata_from_audio_file(file),
+                format: file.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default(),
+                fingerprint,
+            }
+        },
+        |file, result| match result {
+            Ok(_) => progress.finish_file("read"),
+            Err(e) => {
+                progress.println(&format!("{}: {}", file.display(), e));
+                progress.finish_file("failed");
+            },
+        },
+    );
+    progress.finish();
+    return results.into_iter().filter_map(Result::ok).collect();
+}
+
+fn default_quarantine_dir() -> PathBuf {
+    return dirs::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("imd")
+        .join(QUARANTINE_DIR_NAME);
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    io::stdout().flush().expect("ERROR: Failed to write to stdout");
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    return matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+}
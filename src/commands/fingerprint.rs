@@ -0,0 +1,32 @@
+use std::path::Path;
+use std::time::Duration;
+use crate::fingerprint::{acoustid, Fingerprint};
+use crate::settings::Settings;
+use super::require_file;
+
+pub fn run(path: &Path, lookup: bool, settings: &Settings) {
+    require_file(path);
+    let max_length = Duration::from_secs(settings.fingerprint.max_length_secs);
+    let fingerprint = Fingerprint::calculate(path, max_length)
+        .unwrap_or_else(|e| panic!("ERROR: Failed to fingerprint {:?}: {}", path, e));
+    println!("Duration: {}", fingerprint.duration.as_secs());
+    println!("Fingerprint: {}", fingerprint.encoded);
+    if !lookup {
+        return;
+    }
+
+    let matches = acoustid::lookup(&fingerprint, &settings.fingerprint, settings.cache.open().as_ref())
+        .unwrap_or_else(|e| panic!("ERROR: {}", e));
+    if matches.is_empty() {
+        println!("No matching recordings");
+    }
+    for recording in matches {
+        println!("{:.2}  {}  {} - {}{}",
+            recording.score,
+            recording.recording_id,
+            recording.artists.join(" & "),
+            recording.title.as_deref().unwrap_or("?"),
+            recording.album.map(|album| format!(" ({})", album)).unwrap_or_default(),
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+use crate::library::progress::BatchProgress;
+use crate::library::scan::find_audio_files;
+use crate::library::worker_pool::{run_parallel, worker_count};
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::open_library_index;
+
+/// Reads the tags of the new and changed files below a directory into the library index, and
+/// drops the entries of files that no longer exist.
+pub fn run(path: &Path, settings: &Settings) {
+    if !path.is_dir() {
+        panic!("ERROR: Provided path is not a directory!");
+    }
+
+    let index = open_library_index(settings);
+    let files = find_audio_files(path);
+    let total_files = files.len();
+    let changed: Vec<PathBuf> = files.into_iter().filter(|file| !index.is_current(file)).collect();
+
+    let progress = BatchProgress::new(changed.len(), settings.batch.progress && changed.len() > 1);
+    run_parallel(
+        &changed,
+        worker_count(settings.batch.workers),
+        |file| index.update(file, &SongMetadata::read_metadata_from_audio_file(file)),
+        |file, result| match result {
+            Ok(_) => progress.finish_file("indexed"),
+            Err(e) => {
+                progress.println(&format!("{}: {}", file.display(), e));
+                progress.finish_file("failed");
+            },
+        },
+    );
+    if !changed.is_empty() {
+        progress.finish();
+    }
+
+    let removed = index.remove_missing(path);
+    println!("{} file(s) up to date, {} read, {} removed from the index", total_files - changed.len(), changed.len(), removed);
+}
@@ -0,0 +1,135 @@
+use std::path::Path;
+use std::sync::Mutex;
+use crate::app_config::OrganiseOptions;
+use crate::history::journal::{new_run_id, Journal};
+use crate::fingerprint::identify_by_fingerprint;
+use crate::history::state_db::FileOutcome;
+use crate::library::scan::find_audio_files;
+use crate::metadata::metadata_fixer;
+use crate::metadata::path_inference::infer_from_path;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::batch::run_batch;
+use super::organise::organise_file;
+use super::{open_journal, require_file, write_tags, write_tags_shared};
+
+pub const WRITTEN_OUTCOME: &str = "written";
+
+pub fn run(path: &Path, organise: Option<&OrganiseOptions>, settings: &Settings) {
+    if path.is_dir() {
+        run_batch_match(path, organise, settings);
+        return;
+    }
+    require_file(path);
+
+    let song_metadata = identify_by_fingerprint(path, SongMetadata::read_metadata_from_audio_file(path), settings);
+    let song_metadata = infer_from_path(path, song_metadata, &settings.path_inference);
+    let fixed = metadata_fixer::get_fixed_metadata(&song_metadata, settings);
+    if let Some((fixed_metadata, _)) = &fixed {
+        println!("Fixed metadata:");
+        fixed_metadata.pretty_print();
+    }
+
+    let mut journal = settings.journal.enabled.then(|| open_journal(settings));
+    let mut final_metadata = &song_metadata;
+    if settings.general.write {
+        match &fixed {
+            None => println!("No match found, not writing metadata"),
+            Some((_, score)) if *score < settings.thresholds.auto_accept_score => {
+                println!("Match score {:.2} is below the auto accept score {:.2}, not writing metadata", score, settings.thresholds.auto_accept_score);
+            },
+            Some((fixed_metadata, _)) => {
+                println!("Writing metadata to file...");
+                let run_id = new_run_id();
+                write_tags(path, fixed_metadata, settings, &run_id, journal.as_mut());
+                if journal.is_some() {
+                    println!("Run ID: {}", run_id);
+                }
+                final_metadata = fixed_metadata;
+            },
+        }
+    }
+
+    if let Some(organise) = organise {
+        // Organise from the tags the file ends up with, so the path always agrees with the tags.
+        organise_file(path, final_metadata, organise, settings, journal.as_mut());
+    }
+}
+
+/// Matches every file below a directory, reading, matching and writing several files at once.
+/// Provider requests from all workers share the providers' rate limits.
+fn run_batch_match(dir: &Path, organise: Option<&OrganiseOptions>, settings: &Settings) {
+    let run_id = new_run_id();
+    // Writes and moves go through one lock, so the journal and organised file names stay consistent.
+    let journal = Mutex::new(settings.journal.enabled.then(|| open_journal(settings)));
+    let task = if settings.general.write { "write" } else { "match" };
+    run_batch(find_audio_files(dir), task, &run_id, settings, |file| match_batch_file(file, organise, settings, &run_id, &journal));
+
+    let wrote = journal.into_inner().expect("ERROR: Journal lock poisoned")
+        .is_some_and(|journal| journal.entries().iter().any(|entry| entry.run_id == run_id));
+    if wrote {
+        println!("Run ID: {}", run_id);
+    }
+}
+
+/// Matches one file of a batch.
+fn match_batch_file(file: &Path, organise: Option<&OrganiseOptions>, settings: &Settings, run_id: &str, journal: &Mutex<Option<Journal>>) -> FileOutcome {
+    let (outcome, final_metadata) = match_and_write(file, settings, run_id, journal);
+    if let Some(organise) = organise {
+        let mut journal = journal.lock().expect("ERROR: Journal lock poisoned");
+        organise_file(file, &final_metadata, organise, settings, journal.as_mut());
+    }
+    return outcome;
+}
+
+/// Matches a file and, when writing, writes the match if it reaches the auto accept score.
+/// Returns the outcome and the tags the file ends up with.
+pub fn match_and_write(file: &Path, settings: &Settings, run_id: &str, journal: &Mutex<Option<Journal>>) -> (FileOutcome, SongMetadata) {
+    let song_metadata = identify_by_fingerprint(file, SongMetadata::read_metadata_from_audio_file(file), settings);
+    let song_metadata = infer_from_path(file, song_metadata, &settings.path_inference);
+    let Some((fixed_metadata, score)) = metadata_fixer::get_fixed_metadata(&song_metadata, settings) else {
+        return (FileOutcome::new("unmatched", "no match reached the minimum score".to_string()), song_metadata);
+    };
+    let summary = format!(
+        "{} - {} (score {:.2})",
+        fixed_metadata.artist().unwrap_or_default(),
+        fixed_metadata.title.clone().unwrap_or_default(),
+        score,
+    );
+
+    let mut state = "matched";
+    let mut final_metadata = song_metadata;
+    if settings.general.write {
+        if score < settings.thresholds.auto_accept_score {
+            state = "below auto accept";
+        } else {
+            write_tags_shared(file, &fixed_metadata, settings, run_id, journal);
+            state = WRITTEN_OUTCOME;
+            final_metadata = fixed_metadata;
+        }
+    }
+
+    let outcome = FileOutcome {
+        score: Some(score),
+        ..FileOutcome::new(state, summary)
+    };
+    return (outcome, final_metadata);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_untagged_file_without_api_key_is_unmatched() {
+        let audio_file = TestAudioFile::mp3("untagged");
+        let mut settings = Settings::default();
+        settings.cache.enabled = false;
+        assert!(settings.fingerprint.api_key.is_none());
+
+        let (outcome, final_metadata) = match_and_write(audio_file.path(), &settings, &new_run_id(), &Mutex::new(None));
+        assert_eq!("unmatched", outcome.state);
+        assert_eq!(None, final_metadata.title);
+    }
+}
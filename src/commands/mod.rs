@@ -0,0 +1,109 @@
+mod album;
+mod analyse;
+mod batch;
+mod cache;
+mod check;
+mod config;
+mod diff;
+mod duplicates;
+mod fingerprint;
+mod index;
+mod match_command;
+mod organise;
+mod providers;
+mod query;
+mod replay_gain;
+mod review;
+mod serve;
+mod show;
+mod undo;
+mod watch;
+
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use crate::app_config::{AppCommand, AppConfig};
+use crate::history::journal::{default_journal_path, Journal};
+use crate::history::tag_snapshot::FileSnapshot;
+use crate::library::index::{default_index_path, LibraryIndex};
+use crate::metadata::artwork::fetch_front_cover;
+use crate::metadata::path_inference::without_inferred;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+
+pub fn run(app_config: &AppConfig) {
+    let settings = &app_config.settings;
+
+    match &app_config.command {
+        AppCommand::Match { path, organise } => match_command::run(path, organise.as_ref(), settings),
+        AppCommand::Album { path } => album::run(path, settings),
+        AppCommand::Check { path, fix } => check::run(path, *fix, settings),
+        AppCommand::ReplayGain { path } => replay_gain::run(path, settings),
+        AppCommand::Analyse { path } => analyse::run(path, settings),
+        AppCommand::Duplicates { path, action, confirmed } => duplicates::run(path, *action, *confirmed, settings),
+        AppCommand::Watch { path, organise } => watch::run(path, organise.as_ref(), settings),
+        AppCommand::Serve => serve::run(settings),
+        AppCommand::Review { path } => review::run(path, settings),
+        AppCommand::Index { path } => index::run(path, settings),
+        AppCommand::Query(options) => query::run(options, settings),
+        AppCommand::Show { path } => show::run(path),
+        AppCommand::Diff { path } => diff::run(path, settings),
+        AppCommand::Fingerprint { path, lookup } => fingerprint::run(path, *lookup, settings),
+        AppCommand::Undo { target, force, list } => undo::run(target, *force, *list, settings),
+        AppCommand::Organise { path, options } => organise::run(path, options, settings),
+        AppCommand::Cache(action) => cache::run(action, settings),
+        AppCommand::Providers(action) => providers::run(action, settings),
+        AppCommand::Config => config::run(settings),
+        AppCommand::Completions(shell) => config::print_completions(*shell),
+    }
+}
+
+fn require_file(path: &Path) {
+    if !path.is_file() {
+		panic!("ERROR: Provided path is not a file!");
+	}
+}
+
+fn open_library_index(settings: &Settings) -> LibraryIndex {
+    return LibraryIndex::open(&settings.library.index_path.clone().unwrap_or_else(default_index_path));
+}
+
+/// Writes the tags, without values guessed from the file path and with the matched artwork when `[artwork]` asks for it, recording the previous
+/// ones in the undo journal when there is one.
+fn write_tags(path: &Path, metadata: &SongMetadata, settings: &Settings, run_id: &str, journal: Option<&mut Journal>) {
+    let previous_tags = write_unrecorded(path, metadata, settings, journal.is_some());
+    if let (Some(journal), Some(previous_tags)) = (journal, previous_tags) {
+        journal.record(run_id, path, previous_tags);
+    }
+}
+
+/// Like `write_tags` for a journal shared between threads. The lock is only held to record the
+/// write, so other threads aren't kept waiting while artwork is downloaded or the file written.
+fn write_tags_shared(path: &Path, metadata: &SongMetadata, settings: &Settings, run_id: &str, journal: &Mutex<Option<Journal>>) {
+    let journaled = journal.lock().unwrap_or_else(PoisonError::into_inner).is_some();
+    if let Some(previous_tags) = write_unrecorded(path, metadata, settings, journaled) {
+        if let Some(journal) = journal.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+            journal.record(run_id, path, previous_tags);
+        }
+    }
+}
+
+/// Writes the tags, returning the snapshot of the previous ones when `snapshot` is set.
+fn write_unrecorded(path: &Path, metadata: &SongMetadata, settings: &Settings, snapshot: bool) -> Option<FileSnapshot> {
+    let mut metadata = without_inferred(metadata.clone());
+    if settings.artwork.embed && metadata.front_cover.is_none() {
+        if let Some(url) = &metadata.artwork_url {
+            match fetch_front_cover(url, settings.artwork.size) {
+                Ok(front_cover) => metadata.front_cover = Some(front_cover),
+                Err(e) => eprintln!("WARN: Failed to fetch the artwork {}: {}", url, e),
+            }
+        }
+    }
+    let previous_tags = snapshot.then(|| FileSnapshot::take(path));
+    metadata.write_metadata_to_audio_file(path, &settings.writer);
+    return previous_tags;
+}
+
+fn open_journal(settings: &Settings) -> Journal {
+    let journal_path = settings.journal.path.clone().unwrap_or_else(default_journal_path);
+    return Journal::open(&journal_path);
+}
@@ -0,0 +1,35 @@
+use std::fs;
+use std::path::Path;
+use crate::app_config::OrganiseOptions;
+use crate::history::journal::Journal;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::organise::file_organiser::{FileOrganiser, OrganiseMode};
+use crate::organise::path_template::PathTemplate;
+use crate::settings::Settings;
+use super::{open_journal, require_file};
+
+pub fn run(path: &Path, options: &OrganiseOptions, settings: &Settings) {
+    require_file(path);
+    let song_metadata = SongMetadata::read_metadata_from_audio_file(path);
+    let mut journal = settings.journal.enabled.then(|| open_journal(settings));
+    organise_file(path, &song_metadata, options, settings, journal.as_mut());
+}
+
+/// Organises the file and keeps the undo journal pointing at it when it is moved.
+pub fn organise_file(path: &Path, song_metadata: &SongMetadata, options: &OrganiseOptions, settings: &Settings, journal: Option<&mut Journal>) {
+    let organiser = FileOrganiser::new(
+        PathTemplate::parse(&settings.naming.template),
+        options.destination.clone(),
+        settings.naming.mode,
+        options.dry_run,
+    );
+    // Resolved up front, the original path no longer exists once the file has been moved.
+    let original_path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let organised_path = organiser.organise(path, song_metadata);
+
+    if let Some(journal) = journal {
+        if settings.naming.mode == OrganiseMode::Move && !options.dry_run {
+            journal.record_move(&original_path, &organised_path);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::app_config::ProvidersAction;
+use crate::metadata::itunes_metadata_extractor::{test_provider, KNOWN_PROVIDERS};
+use crate::settings::Settings;
+
+pub fn run(action: &ProvidersAction, settings: &Settings) {
+    match action {
+        ProvidersAction::List => {
+            for provider in KNOWN_PROVIDERS {
+                match settings.providers.order.iter().position(|name| name == provider) {
+                    Some(index) => println!("{}. {}", index + 1, provider),
+                    None => println!("-  {} (disabled)", provider),
+                }
+            }
+            for provider in settings.providers.order.iter().filter(|name| !KNOWN_PROVIDERS.contains(&name.as_str())) {
+                println!("?  {} (unknown)", provider);
+            }
+            println!("Storefronts: {}", settings.providers.storefronts.join(", "));
+        },
+        ProvidersAction::Test(name) => {
+            let providers = match name {
+                Some(name) => vec![name.clone()],
+                None => settings.providers.order.clone(),
+            };
+            for provider in providers {
+                match test_provider(&provider, settings) {
+                    Ok(result_count) => println!("{}: OK ({} results)", provider, result_count),
+                    Err(e) => println!("{}: FAILED ({})", provider, e),
+                }
+            }
+        },
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use crate::app_config::QueryOptions;
+use crate::library::index::IndexedFile;
+use crate::library::query::{compare_by_field, field_text, OutputFormat, Query, QUERY_FIELDS};
+use crate::settings::Settings;
+use super::open_library_index;
+
+/// Widest a table cell gets before it is cut short.
+const MAX_COLUMN_WIDTH: usize = 40;
+
+pub fn run(options: &QueryOptions, settings: &Settings) {
+    let query = Query::parse(&options.filters).unwrap_or_else(|e| panic!("ERROR: {}", e));
+    for column in &options.columns {
+        if !QUERY_FIELDS.contains(&column.as_str()) {
+            panic!("ERROR: Unknown column {:?}, known fields are {}", column, QUERY_FIELDS.join(", "));
+        }
+    }
+
+    let mut files: Vec<IndexedFile> = open_library_index(settings).files()
+        .into_iter()
+        .filter(|file| query.matches(file))
+        .collect();
+    if let Some(sort) = &options.sort {
+        let (field, descending) = match sort.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (sort.as_str(), false),
+        };
+        if !QUERY_FIELDS.contains(&field) {
+            panic!("ERROR: Unknown sort field {:?}, known fields are {}", field, QUERY_FIELDS.join(", "));
+        }
+        files.sort_by(|a, b| if descending { compare_by_field(b, a, field) } else { compare_by_field(a, b, field) });
+    }
+    if let Some(limit) = options.limit {
+        files.truncate(limit);
+    }
+
+    let rows: Vec<Vec<String>> = files.iter()
+        .map(|file| options.columns.iter().map(|column| field_text(file, column)).collect())
+        .collect();
+    match options.format {
+        OutputFormat::Table => print_table(&options.columns, &rows),
+        OutputFormat::Csv => print_csv(&options.columns, &rows),
+        OutputFormat::Json => print_json(&options.columns, &rows),
+    }
+}
+
+fn print_table(columns: &[String], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = columns.iter().enumerate()
+        .map(|(index, column)| rows.iter()
+            .map(|row| row[index].chars().count())
+            .chain([column.len()])
+            .max()
+            .unwrap_or_default()
+            .min(MAX_COLUMN_WIDTH))
+        .collect();
+    let print_row = |cells: &[String]| {
+        let line: Vec<String> = cells.iter().zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", truncate(cell, *width), width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+
+    print_row(columns);
+    print_row(&widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<String>>());
+    for row in rows {
+        print_row(row);
+    }
+    println!("{} file(s)", rows.len());
+}
+
+fn print_csv(columns: &[String], rows: &[Vec<String>]) {
+    for row in [columns.to_vec()].iter().chain(rows) {
+        println!("{}", row.iter().map(|cell| csv_field(cell)).collect::<Vec<String>>().join(","));
+    }
+}
+
+fn print_json(columns: &[String], rows: &[Vec<String>]) {
+    let objects: Vec<BTreeMap<&str, &str>> = rows.iter()
+        .map(|row| columns.iter().map(|column| column.as_str()).zip(row.iter().map(|cell| cell.as_str())).collect())
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&objects).expect("rows serialize to JSON"));
+}
+
+fn truncate(cell: &str, width: usize) -> String {
+    if cell.chars().count() <= width {
+        return cell.to_string();
+    }
+    return format!("{}…", cell.chars().take(width - 1).collect::<String>());
+}
+
+fn csv_field(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", cell.replace('"', "\"\""));
+    }
+    return cell.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cell_formatting() {
+        assert_eq!("plain", csv_field("plain"));
+        assert_eq!("\"Crosby, Stills & Nash\"", csv_field("Crosby, Stills & Nash"));
+        assert_eq!("\"12\"\" Mix\"", csv_field("12\" Mix"));
+        assert_eq!("Abc…", truncate("Abcdef", 4));
+        assert_eq!("Abc", truncate("Abc", 4));
+    }
+}
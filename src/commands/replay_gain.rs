@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+use crate::audio::decoder::decode_audio_file;
+use crate::audio::loudness::Loudness;
+use crate::history::journal::new_run_id;
+use crate::library::progress::BatchProgress;
+use crate::library::scan::find_audio_files;
+use crate::library::worker_pool::{run_parallel, worker_count};
+use crate::metadata::album_matcher::group_files;
+use crate::metadata::replay_gain::ReplayGain;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::{open_journal, write_tags};
+
+/// Measures the loudness of a file, or of every album below a directory, and writes ReplayGain
+/// tags when enabled. Album gains are only computed for directories. The files of an album are
+/// decoded and measured in parallel.
+pub fn run(path: &Path, settings: &Settings) {
+    let files = if path.is_dir() {
+        find_audio_files(path)
+    } else if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        panic!("ERROR: Provided path is not a file or directory!");
+    };
+
+    // There is no Opus decoder to measure them with.
+    let is_opus = |file: &Path| file.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("opus"));
+    if path.is_file() && is_opus(path) {
+        panic!("ERROR: Opus files can't be decoded, so imd can't measure their loudness");
+    }
+    let files: Vec<(PathBuf, SongMetadata)> = files.into_iter()
+        .filter(|file| {
+            if is_opus(file) {
+                eprintln!("WARN: Opus files can't be decoded, skipping {:?}", file);
+            }
+            return !is_opus(file);
+        })
+        .map(|file| {
+            let song_metadata = SongMetadata::read_metadata_from_audio_file(&file);
+            (file, song_metadata)
+        })
+        .collect();
+    let total_files = files.len();
+    let groups = group_files(files, settings.album.group_by);
+    let workers = worker_count(settings.batch.workers);
+    let progress = BatchProgress::new(total_files, settings.batch.progress && total_files > 1);
+
+    let run_id = new_run_id();
+    let mut journal = (settings.general.write && settings.journal.enabled).then(|| open_journal(settings));
+    let mut written = 0;
+    for group in &groups {
+        let measurements = run_parallel(
+            &group.files,
+            workers,
+            |(file, _)| decode_audio_file(file, None).map(|audio| Loudness::measure(&audio)),
+            |_, result| progress.finish_file(if matches!(result, Ok(Ok(_))) { "measured" } else { "failed" }),
+        );
+        let measured: Vec<(&PathBuf, &SongMetadata, Loudness)> = group.files.iter()
+            .zip(measurements)
+            .filter_map(|((file, song_metadata), measurement)| match measurement {
+                Ok(Ok(loudness)) => Some((file, song_metadata, loudness)),
+                Ok(Err(e)) | Err(e) => {
+                    progress.println(&format!("WARN: Failed to decode {:?}, skipping: {}", file, e));
+                    None
+                }
+            })
+            .collect();
+        let album = path.is_dir().then(|| Loudness::album(&measured.iter().map(|(_, _, loudness)| loudness.clone()).collect::<Vec<Loudness>>()));
+
+        let folder = group.files[0].0.parent().unwrap_or(Path::new(""));
+        progress.println(&format!("Album in {:?}:", folder));
+        if let Some(album_loudness) = album.as_ref().and_then(|album| album.integrated()) {
+            progress.println(&format!("  {:<40} {:>7.2} LUFS", "Album", album_loudness));
+        }
+        for (file, song_metadata, loudness) in measured {
+            let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+            let Some(replay_gain) = ReplayGain::from_loudness(&loudness, album.as_ref()) else {
+                progress.println(&format!("  {:<40} silent, skipping", file_name));
+                continue;
+            };
+            progress.println(&format!("  {:<40} {:>7.2} LUFS  {}", file_name, loudness.integrated().unwrap_or_default(), replay_gain.describe()));
+            if !settings.general.write {
+                continue;
+            }
+
+            let updated = SongMetadata {
+                replay_gain: Some(replay_gain),
+                ..song_metadata.clone()
+            };
+            write_tags(file, &updated, settings, &run_id, journal.as_mut());
+            written += 1;
+        }
+    }
+    progress.finish();
+
+    if settings.general.write {
+        println!("Wrote ReplayGain tags to {} file(s)", written);
+        if written > 0 && journal.is_some() {
+            println!("Run ID: {}", run_id);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::io;
+use std::path::Path;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use ratatui::DefaultTerminal;
+use crate::fingerprint::identify_by_fingerprint;
+use crate::history::journal::{new_run_id, Journal};
+use crate::library::scan::find_audio_files;
+use crate::library::worker_pool::catch_panic;
+use crate::metadata::itunes_metadata_extractor::find_matching_metadata;
+use crate::metadata::metadata_fixer::rank_candidates;
+use crate::metadata::path_inference::infer_from_path;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::review::view;
+use crate::review::{Action, Candidate, ItemStatus, ReviewApp, ReviewItem};
+use crate::settings::Settings;
+use super::{open_journal, require_file, write_tags};
+
+/// Opens the review screen for a file or every file below a directory. Accepted results are
+/// merged and written like `imd write` does, into the same undo journal.
+pub fn run(path: &Path, settings: &Settings) {
+    let files = if path.is_dir() {
+        find_audio_files(path)
+    } else {
+        require_file(path);
+        vec![path.to_path_buf()]
+    };
+    if files.is_empty() {
+        println!("No audio files found");
+        return;
+    }
+    println!("Reading {} file(s)", files.len());
+    // The tags are completed like a match would before searching, and accepting writes them too.
+    let items: Vec<ReviewItem> = files.into_iter()
+        .map(|file| match catch_panic(|| {
+            let song_metadata = identify_by_fingerprint(&file, SongMetadata::read_metadata_from_audio_file(&file), settings);
+            return infer_from_path(&file, song_metadata, &settings.path_inference);
+        }) {
+            Ok(metadata) => ReviewItem::new(file, metadata),
+            Err(e) => ReviewItem {
+                status: ItemStatus::Failed(e),
+                ..ReviewItem::new(file, SongMetadata::default())
+            },
+        })
+        .collect();
+
+    let mut app = ReviewApp::new(items, settings.thresholds.auto_accept_score);
+    let mut journal = settings.journal.enabled.then(|| open_journal(settings));
+    let run_id = new_run_id();
+    let mut terminal = ratatui::init();
+    let result = review(&mut terminal, &mut app, settings, &run_id, journal.as_mut());
+    ratatui::restore();
+    if let Err(e) = result {
+        panic!("ERROR: Review screen failed: {}", e);
+    }
+
+    let (pending, written, skipped, failed) = app.counts();
+    println!("{} written, {} skipped, {} failed, {} left to review", written, skipped, failed, pending);
+    if written > 0 && journal.is_some() {
+        println!("Run ID: {}", run_id);
+    }
+}
+
+fn review(terminal: &mut DefaultTerminal, app: &mut ReviewApp, settings: &Settings, run_id: &str, mut journal: Option<&mut Journal>) -> io::Result<()> {
+    loop {
+        terminal.draw(|frame| view::draw(frame, app, settings))?;
+        // The selected file is searched as soon as it is shown.
+        let action = match app.pending_search() {
+            Some(search) => Some(search),
+            None => match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => app.handle_key(key),
+                _ => None,
+            },
+        };
+        let Some(action) = action else {
+            continue;
+        };
+        match action {
+            Action::Quit => return Ok(()),
+            Action::Search(index, query) => search(app, index, &query, settings),
+            Action::Accept(indexes) => {
+                for index in indexes {
+                    accept(app, index, settings, run_id, journal.as_deref_mut());
+                }
+                app.select_next_pending();
+            },
+            Action::AcceptBest { items, minimum_score } => {
+                let mut accepted = 0;
+                for index in &items {
+                    if app.items[*index].candidates.is_none() {
+                        app.message = format!("Searching {} of {}...", accepted + 1, items.len());
+                        terminal.draw(|frame| view::draw(frame, app, settings))?;
+                        let query = app.items[*index].current.clone();
+                        search(app, *index, &query, settings);
+                    }
+                    if app.items[*index].best_score().is_some_and(|score| score >= minimum_score) {
+                        app.items[*index].selected_candidate = 0;
+                        accepted += accept(app, *index, settings, run_id, journal.as_deref_mut()) as usize;
+                    }
+                }
+                app.message = format!("Accepted {} of {} file(s)", accepted, items.len());
+            },
+        }
+        // Providers and writers print warnings straight to the terminal, drawing everything
+        // again wipes them.
+        terminal.clear()?;
+    }
+}
+
+/// Searches the providers and ranks the results like a match would.
+fn search(app: &mut ReviewApp, index: usize, query: &SongMetadata, settings: &Settings) {
+    let item = &mut app.items[index];
+    match catch_panic(|| rank_candidates(query, find_matching_metadata(query, settings), settings)) {
+        Ok(ranked) => {
+            app.message = format!("{} candidate(s) for {}", ranked.len(), item.path.display());
+            item.candidates = Some(Candidate::rank(query, ranked, settings));
+        },
+        Err(e) => {
+            app.message = e;
+            item.candidates = Some(Vec::new());
+        },
+    }
+    item.selected_candidate = 0;
+}
+
+/// Writes the item's result, returning whether it was written.
+fn accept(app: &mut ReviewApp, index: usize, settings: &Settings, run_id: &str, journal: Option<&mut Journal>) -> bool {
+    let item = &mut app.items[index];
+    let metadata = item.result(settings);
+    match catch_panic(|| write_tags(&item.path, &metadata, settings, run_id, journal)) {
+        Ok(_) => {
+            app.message = format!("Wrote {}", item.path.display());
+            item.current = metadata;
+            item.edits.clear();
+            item.status = ItemStatus::Written;
+            return true;
+        },
+        Err(e) => {
+            app.message = format!("Failed to write {}: {}", item.path.display(), e);
+            item.status = ItemStatus::Failed(e);
+            return false;
+        },
+    }
+}
@@ -0,0 +1,253 @@
+use std::net::IpAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+use crate::history::journal::{new_run_id, Journal};
+use crate::library::worker_pool::{panic_message, worker_count};
+use crate::metadata::itunes_metadata_extractor::find_matching_metadata;
+use crate::metadata::metadata_comparator::MetadataComparator;
+use crate::metadata::metadata_fixer::combine_metadata;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+use super::{open_journal, write_tags_shared};
+
+/// A candidate with its score against the metadata it was compared to.
+#[derive(Serialize)]
+struct ScoredCandidate {
+    score: f64,
+    candidate: SongMetadata,
+}
+
+#[derive(Deserialize)]
+struct ScoreRequest {
+    metadata: SongMetadata,
+    candidates: Vec<SongMetadata>,
+}
+
+#[derive(Deserialize)]
+struct WriteRequest {
+    path: PathBuf,
+    candidate: SongMetadata,
+}
+
+/// A response status and JSON body.
+type Reply = (u16, Value);
+
+/// Serves the tag reading, matching and writing over a local JSON HTTP API until interrupted.
+pub fn run(settings: &Settings) {
+    let server = Server::http(&settings.server.bind)
+        .unwrap_or_else(|e| panic!("ERROR: Failed to listen on {}: {}", settings.server.bind, e));
+    let journal = Mutex::new(settings.journal.enabled.then(|| open_journal(settings)));
+    println!("Listening on http://{}", settings.server.bind);
+
+    thread::scope(|scope| {
+        for _ in 0..worker_count(settings.batch.workers) {
+            scope.spawn(|| loop {
+                match server.recv() {
+                    Ok(request) => respond(request, settings, &journal),
+                    Err(e) => eprintln!("WARN: Failed to receive a request: {}", e),
+                }
+            });
+        }
+    });
+}
+
+fn respond(mut request: Request, settings: &Settings, journal: &Mutex<Option<Journal>>) {
+    let mut body = String::new();
+    let (status, value) = match request.as_reader().read_to_string(&mut body) {
+        Ok(_) => {
+            let header = |name: &'static str| request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str());
+            let is_json = header("Content-Type").is_some_and(|content_type| content_type.starts_with("application/json"));
+            handle(request.method(), request.url(), header("Host"), &body, is_json, settings, journal)
+        },
+        Err(e) => error(400, format!("Failed to read the request body: {}", e)),
+    };
+    println!("{} {} {}", request.method(), request.url(), status);
+    let response = Response::from_string(value.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").expect("static header is valid"));
+    if let Err(e) = request.respond(response) {
+        eprintln!("WARN: Failed to send the response: {}", e);
+    }
+}
+
+/// Routes a request. Reading and writing files panic on failure like everywhere else, which
+/// becomes an error response instead of taking the server down.
+fn handle(method: &Method, url: &str, host: Option<&str>, body: &str, is_json: bool, settings: &Settings, journal: &Mutex<Option<Journal>>) -> Reply {
+    let Ok(url) = Url::parse("http://localhost").and_then(|base| base.join(url)) else {
+        return error(400, format!("Invalid URL {:?}", url));
+    };
+    if let Some(host) = host.filter(|host| !is_allowed_host(host, &settings.server.bind)) {
+        return error(403, format!("Host {:?} is not allowed", host));
+    }
+    // Requiring JSON makes browsers ask before sending cross-site requests, which are refused.
+    if *method == Method::Post && !is_json {
+        return error(415, "POST requests need Content-Type: application/json".to_string());
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| match (method, url.path()) {
+        (Method::Get, "/tags") => match url.query_pairs().find(|(key, _)| key == "path") {
+            Some((_, path)) => read_tags(Path::new(path.as_ref())),
+            None => error(400, "Missing the path query parameter".to_string()),
+        },
+        (Method::Post, "/candidates") => parse_body(body, |metadata: SongMetadata| search_candidates(&metadata, settings)),
+        (Method::Post, "/score") => parse_body(body, |request: ScoreRequest| {
+            (200, json!(score_candidates(&request.metadata, request.candidates, settings)))
+        }),
+        (Method::Post, "/write") => parse_body(body, |request: WriteRequest| write_candidate(&request, settings, journal)),
+        (_, "/tags" | "/candidates" | "/score" | "/write") => error(405, format!("{} is not allowed here", method)),
+        (_, path) => error(404, format!("No endpoint {}", path)),
+    }));
+    return result.unwrap_or_else(|payload| error(500, panic_message(payload)));
+}
+
+fn read_tags(path: &Path) -> Reply {
+    if !path.is_file() {
+        return error(404, format!("{:?} is not a file", path));
+    }
+    return (200, json!({
+        "path": path,
+        "metadata": SongMetadata::read_metadata_from_audio_file(path),
+    }));
+}
+
+/// Whether the request was addressed to this server by IP address, as localhost or by the bound
+/// name. A page whose own host name was rebound to 127.0.0.1 still sends that name, so it is
+/// refused like any other cross-site request.
+fn is_allowed_host(host: &str, bind: &str) -> bool {
+    let (name, port) = split_port(host);
+    let (bind_name, bind_port) = split_port(bind);
+    let is_local = name.eq_ignore_ascii_case("localhost") || name.eq_ignore_ascii_case(bind_name)
+        || name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok();
+    return is_local && (port.is_none() || port == bind_port);
+}
+
+fn split_port(address: &str) -> (&str, Option<&str>) {
+    return match address.rsplit_once(':') {
+        // The colons of a bracketed IPv6 address without a port.
+        Some((name, port)) if !port.contains(']') && !name.ends_with(':') => (name, Some(port)),
+        _ => (address, None),
+    };
+}
+
+/// Searches the providers and scores every result, best first.
+fn search_candidates(metadata: &SongMetadata, settings: &Settings) -> Reply {
+    if metadata.title.is_none() || metadata.artists.is_none() {
+        return error(400, "Searching needs a title and artists".to_string());
+    }
+    let mut scored = score_candidates(metadata, find_matching_metadata(metadata, settings), settings);
+    scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+    return (200, json!(scored));
+}
+
+/// Scores the candidates against the metadata, in the given order.
+fn score_candidates(metadata: &SongMetadata, candidates: Vec<SongMetadata>, settings: &Settings) -> Vec<ScoredCandidate> {
+    return candidates.into_iter()
+        .map(|candidate| ScoredCandidate {
+            score: MetadataComparator::new(metadata.clone(), candidate.clone(), settings.scoring.clone()).get_overall_score(),
+            candidate,
+        })
+        .collect();
+}
+
+/// Merges the candidate into the file's tags like a match, writes them and records the write
+/// in the undo journal.
+fn write_candidate(request: &WriteRequest, settings: &Settings, journal: &Mutex<Option<Journal>>) -> Reply {
+    let path = request.path.as_path();
+    if !path.is_file() {
+        return error(404, format!("{:?} is not a file", path));
+    }
+    let metadata = combine_metadata(&SongMetadata::read_metadata_from_audio_file(path), &request.candidate, &settings.merge);
+    let run_id = new_run_id();
+    write_tags_shared(path, &metadata, settings, &run_id, journal);
+    return (200, json!({
+        "path": path,
+        "run_id": settings.journal.enabled.then_some(run_id),
+        "metadata": metadata,
+    }));
+}
+
+fn parse_body<T, F>(body: &str, handler: F) -> Reply
+where
+    T: for<'de> Deserialize<'de>,
+    F: FnOnce(T) -> Reply,
+{
+    return match serde_json::from_str(body) {
+        Ok(request) => handler(request),
+        Err(e) => error(400, format!("Invalid request body: {}", e)),
+    };
+}
+
+fn error(status: u16, message: String) -> Reply {
+    return (status, json!({ "error": message }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAudioFile;
+
+    fn get(url: &str) -> Reply {
+        return handle(&Method::Get, url, Some("127.0.0.1:7878"), "", false, &Settings::default(), &Mutex::new(None));
+    }
+
+    fn post(url: &str, body: &str) -> Reply {
+        return handle(&Method::Post, url, Some("localhost:7878"), body, true, &Settings::default(), &Mutex::new(None));
+    }
+
+    #[test]
+    fn test_read_and_write_tags() {
+        let audio_file = TestAudioFile::mp3("serve");
+        let path = audio_file.path().to_string_lossy().to_string();
+        let body = json!({ "path": path, "candidate": { "title": "Title", "artists": ["Artist"], "year": 1969 } });
+        let (status, written) = post("/write", &body.to_string());
+        assert_eq!(200, status);
+        assert_eq!(json!("Title"), written["metadata"]["title"]);
+
+        let (status, read) = get(&format!("/tags?path={}", url::form_urlencoded::byte_serialize(path.as_bytes()).collect::<String>()));
+        assert_eq!(200, status);
+        assert_eq!(json!(["Artist"]), read["metadata"]["artists"]);
+        assert_eq!(json!(1969), read["metadata"]["year"]);
+    }
+
+    #[test]
+    fn test_score_candidates_in_order() {
+        let body = json!({
+            "metadata": { "title": "Let It Be", "artists": ["The Beatles"] },
+            "candidates": [{ "title": "Something", "artists": ["Other"] }, { "title": "Let It Be", "artists": ["The Beatles"] }],
+        });
+        let (status, scores) = post("/score", &body.to_string());
+        assert_eq!(200, status);
+        assert_eq!(json!("Something"), scores[0]["candidate"]["title"]);
+        assert!(scores[1]["score"].as_f64().unwrap() > scores[0]["score"].as_f64().unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(404, get("/tags?path=/no/such/file.mp3").0);
+        assert_eq!(400, get("/tags").0);
+        assert_eq!(404, get("/nothing").0);
+        assert_eq!(405, get("/write").0);
+        assert_eq!(400, post("/score", "not json").0);
+        assert_eq!(400, post("/candidates", "{}").0);
+        assert_eq!(415, handle(&Method::Post, "/score", None, "{}", false, &Settings::default(), &Mutex::new(None)).0);
+        assert_eq!(403, handle(&Method::Get, "/tags", Some("attacker.example:7878"), "", false, &Settings::default(), &Mutex::new(None)).0);
+    }
+
+    #[test]
+    fn test_allowed_hosts() {
+        let bind = "127.0.0.1:7878";
+        assert!(is_allowed_host("127.0.0.1:7878", bind));
+        assert!(is_allowed_host("LOCALHOST:7878", bind));
+        assert!(is_allowed_host("[::1]:7878", bind));
+        assert!(is_allowed_host("[::1]", bind));
+        assert!(is_allowed_host("music.local:7878", "music.local:7878"));
+        assert!(!is_allowed_host("localhost:8080", bind));
+        assert!(!is_allowed_host("rebound.example.com:7878", bind));
+    }
+}
@@ -0,0 +1,15 @@
+use std::path::Path;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::metadata::tag_target::tag_type_name;
+use super::require_file;
+
+pub fn run(path: &Path) {
+    require_file(path);
+    let (song_metadata, sources) = SongMetadata::read_metadata_with_sources(path);
+    song_metadata.pretty_print();
+
+    println!("Read from:");
+    for (field, tag_type) in sources {
+        println!("{:<20}{}", format!("{}:", field), tag_type_name(tag_type));
+    }
+}
@@ -0,0 +1,19 @@
+use crate::history::journal::{Journal, UndoTarget};
+use crate::settings::Settings;
+use super::open_journal;
+
+pub fn run(target: &UndoTarget, force: bool, list: bool, settings: &Settings) {
+    let mut journal = open_journal(settings);
+    if list {
+        print_journal(&journal);
+        return;
+    }
+    journal.undo(target, force, &settings.writer);
+}
+
+fn print_journal(journal: &Journal) {
+    for entry in journal.entries() {
+        let status = if entry.undone { "undone" } else { "" };
+        println!("{}  {}  {:?}  {}", entry.run_id, entry.timestamp, entry.path, status);
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use crate::app_config::OrganiseOptions;
+use crate::history::journal::{new_run_id, Journal};
+use crate::history::state_db::{default_state_path, FileOutcome, SkipMode, StateDb};
+use crate::library::scan::{find_audio_files, is_audio_file};
+use crate::library::settle::SettlingFiles;
+use crate::library::worker_pool::{run_parallel, worker_count};
+use crate::metadata::atomic_write::is_temp_file;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::organise::file_organiser::{move_file, resolve_collision};
+use crate::settings::Settings;
+use super::match_command::{match_and_write, WRITTEN_OUTCOME};
+use super::open_journal;
+use super::organise::organise_file;
+
+const WATCH_TASK: &str = "watch";
+const REVIEW_DIR_NAME: &str = "review";
+const REVIEW_OUTCOME: &str = "review";
+/// How often settling files are checked when no events arrive.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the files of a watched folder go once they are processed.
+struct Inbox<'a> {
+    dir: PathBuf,
+    review_dir: PathBuf,
+    organise: Option<OrganiseOptions>,
+    settings: &'a Settings,
+    state: StateDb,
+    journal: Mutex<Option<Journal>>,
+}
+
+/// Watches a folder and matches every audio file that lands in it once it stops changing. Files
+/// reaching the auto accept score are written and organised, the others are moved to the review
+/// folder. Runs until interrupted.
+pub fn run(path: &Path, organise: Option<&OrganiseOptions>, settings: &Settings) {
+    if !path.is_dir() {
+        panic!("ERROR: Provided path is not a directory!");
+    }
+    // Without a threshold every match would be written, and nothing would be left to review.
+    if settings.thresholds.auto_accept_score <= 0.0 {
+        panic!("ERROR: imd watch needs an auto_accept_score above 0");
+    }
+    let dir = fs::canonicalize(path).unwrap_or_else(|e| panic!("ERROR: Failed to resolve {:?}: {}", path, e));
+    let review_dir = settings.watch.review_dir.clone().unwrap_or_else(|| dir.join(REVIEW_DIR_NAME));
+    fs::create_dir_all(&review_dir).unwrap_or_else(|e| panic!("ERROR: Failed to create {:?}: {}", review_dir, e));
+    let organise = organise.cloned().or_else(|| settings.watch.organise_dir.clone().map(|destination| OrganiseOptions {
+        destination,
+        dry_run: false,
+    }));
+    let inbox = Inbox {
+        review_dir: fs::canonicalize(&review_dir).unwrap_or(review_dir),
+        // Resolved like the watched paths, to recognise organised files inside the inbox.
+        organise: organise.map(|organise| OrganiseOptions {
+            destination: fs::canonicalize(&organise.destination).unwrap_or(organise.destination),
+            ..organise
+        }),
+        settings,
+        state: StateDb::open(&settings.batch.state_path.clone().unwrap_or_else(default_state_path)),
+        journal: Mutex::new(settings.journal.enabled.then(|| open_journal(settings))),
+        dir,
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .unwrap_or_else(|e| panic!("ERROR: Failed to watch {:?}: {}", inbox.dir, e));
+    watcher.watch(&inbox.dir, RecursiveMode::Recursive)
+        .unwrap_or_else(|e| panic!("ERROR: Failed to watch {:?}: {}", inbox.dir, e));
+    println!("Watching {:?}, files below the auto accept score are moved to {:?}", inbox.dir, inbox.review_dir);
+
+    let mut settling = SettlingFiles::new(Duration::from_secs(settings.watch.settle_secs));
+    // Files that arrived while imd wasn't watching, the state database skips those already done.
+    for file in find_audio_files(&inbox.dir) {
+        if inbox.accepts(&file) {
+            settling.touch(file, Instant::now());
+        }
+    }
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                let changed = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Access(AccessKind::Close(AccessMode::Write)));
+                if changed {
+                    for path in event.paths {
+                        // A folder moved in at once only reports the folder itself.
+                        let files = if path.is_dir() { find_audio_files(&path) } else { vec![path] };
+                        for file in files.into_iter().filter(|file| inbox.accepts(file)) {
+                            settling.touch(file, Instant::now());
+                        }
+                    }
+                }
+            },
+            Ok(Err(e)) => eprintln!("WARN: Watching {:?} failed: {}", inbox.dir, e),
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => panic!("ERROR: Stopped receiving changes to {:?}", inbox.dir),
+        }
+        let settled = settling.take_settled(Instant::now());
+        if !settled.is_empty() {
+            inbox.process(settled);
+        }
+    }
+}
+
+impl Inbox<'_> {
+    /// Whether a changed path is an audio file to process, files imd moved aside and the
+    /// temporary copies made while writing tags are left alone.
+    fn accepts(&self, path: &Path) -> bool {
+        let organised = self.organise.as_ref().is_some_and(|organise| path.starts_with(&organise.destination));
+        return is_audio_file(path) && path.is_file() && !is_temp_file(path) && !path.starts_with(&self.review_dir) && !organised;
+    }
+
+    /// Processes settled files with the worker pool. Files left in the inbox are recorded, so
+    /// the change events of writing their tags don't get them processed again.
+    fn process(&self, files: Vec<PathBuf>) {
+        let files = self.state.pending(files, WATCH_TASK, SkipMode::Unchanged);
+        if files.is_empty() {
+            return;
+        }
+        let run_id = new_run_id();
+        run_parallel(
+            &files,
+            worker_count(self.settings.batch.workers),
+            |file| self.process_file(file, &run_id),
+            |file, result| {
+                let failed;
+                let outcome = match result {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        failed = self.move_to_review(file, FileOutcome::failed(e.clone()));
+                        &failed
+                    },
+                };
+                println!("{}: {}", file.display(), outcome.summary);
+                if file.exists() {
+                    self.state.record(file, WATCH_TASK, &run_id, outcome);
+                }
+            },
+        );
+    }
+
+    fn process_file(&self, file: &Path, run_id: &str) -> FileOutcome {
+        let (outcome, final_metadata) = match_and_write(file, self.settings, run_id, &self.journal);
+        return self.place(file, outcome, &final_metadata);
+    }
+
+    /// Organises a written file, anything else is moved to the review folder.
+    fn place(&self, file: &Path, outcome: FileOutcome, final_metadata: &SongMetadata) -> FileOutcome {
+        if outcome.state != WRITTEN_OUTCOME {
+            return self.move_to_review(file, outcome);
+        }
+        if let Some(organise) = &self.organise {
+            let mut journal = self.journal.lock().expect("ERROR: Journal lock poisoned");
+            organise_file(file, final_metadata, organise, self.settings, journal.as_mut());
+        }
+        return outcome;
+    }
+
+    /// Moves the file below the review folder, keeping its path relative to the inbox.
+    fn move_to_review(&self, file: &Path, outcome: FileOutcome) -> FileOutcome {
+        let relative_path = file.strip_prefix(&self.dir).unwrap_or(file);
+        let target = resolve_collision(&self.review_dir.join(relative_path));
+        let moved = target.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| move_file(file, &target));
+        let summary = match moved {
+            Ok(_) => format!("{}, moved to {:?}", outcome.summary, target),
+            Err(e) => format!("{}, failed to move to {:?}: {}", outcome.summary, target, e),
+        };
+        return FileOutcome {
+            score: outcome.score,
+            ..FileOutcome::new(REVIEW_OUTCOME, summary)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_below_auto_accept_moves_to_review() {
+        let audio_file = TestAudioFile::mp3("watch-review");
+        let settings = Settings::default();
+        let inbox = Inbox {
+            dir: audio_file.dir().to_path_buf(),
+            review_dir: audio_file.dir().join(REVIEW_DIR_NAME),
+            organise: None,
+            settings: &settings,
+            state: StateDb::open(&audio_file.dir().join("state.sqlite3")),
+            journal: Mutex::new(None),
+        };
+        let matched = FileOutcome {
+            score: Some(0.6),
+            ..FileOutcome::new("below auto accept", "Artist - Title (score 0.60)".to_string())
+        };
+        let outcome = inbox.place(audio_file.path(), matched, &SongMetadata::default());
+        assert_eq!(REVIEW_OUTCOME, outcome.state);
+        assert_eq!(Some(0.6), outcome.score);
+        assert!(!audio_file.path().exists());
+        assert!(audio_file.dir().join(REVIEW_DIR_NAME).join("watch-review.mp3").is_file());
+    }
+
+    #[test]
+    fn test_ignores_temporary_copies() {
+        let audio_file = TestAudioFile::mp3("watch-temp");
+        let settings = Settings::default();
+        let inbox = Inbox {
+            dir: audio_file.dir().to_path_buf(),
+            review_dir: audio_file.dir().join(REVIEW_DIR_NAME),
+            organise: None,
+            settings: &settings,
+            state: StateDb::open(&audio_file.dir().join("state.sqlite3")),
+            journal: Mutex::new(None),
+        };
+        let temp_copy = audio_file.dir().join(".watch-temp.imd-tmp.mp3");
+        fs::copy(audio_file.path(), &temp_copy).unwrap();
+        assert!(inbox.accepts(audio_file.path()));
+        assert!(!inbox.accepts(&temp_copy));
+    }
+
+    #[test]
+    #[should_panic(expected = "auto_accept_score above 0")]
+    fn test_refuses_to_watch_without_auto_accept_score() {
+        let audio_file = TestAudioFile::mp3("watch-threshold");
+        let mut settings = Settings::default();
+        settings.thresholds.auto_accept_score = 0.0;
+        run(audio_file.dir(), None, &settings);
+    }
+}
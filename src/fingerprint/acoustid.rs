@@ -0,0 +1,181 @@
+use serde::Deserialize;
+use url::Url;
+use crate::metadata::rate_limiter::RateLimiter;
+use crate::metadata::response_cache::ResponseCache;
+use crate::settings::FingerprintSettings;
+use super::Fingerprint;
+
+const ACOUSTID_SERVICE: &str = "acoustid";
+const LOOKUP_PATH: &str = "/v2/lookup";
+const LOOKUP_META: &str = "recordings releasegroups compress";
+
+#[derive(Debug, Deserialize)]
+struct LookupResponse {
+    status: String,
+    #[serde(default)]
+    results: Vec<LookupResult>,
+    error: Option<LookupError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupResult {
+    score: f64,
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recording {
+    id: String,
+    title: Option<String>,
+    duration: Option<f64>,
+    #[serde(default)]
+    artists: Vec<Artist>,
+    #[serde(default)]
+    releasegroups: Vec<ReleaseGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseGroup {
+    title: Option<String>,
+}
+
+/// A recording identified by its fingerprint, `score` being how well the fingerprints match.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordingMatch {
+    pub score: f64,
+    pub recording_id: String,
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub duration_secs: Option<f64>,
+}
+
+/// Looks up recordings matching a fingerprint, best match first.
+pub fn lookup(fingerprint: &Fingerprint, settings: &FingerprintSettings, cache: Option<&ResponseCache>) -> Result<Vec<RecordingMatch>, String> {
+    let url = build_lookup_url(fingerprint, settings)?;
+    let body = match cache.and_then(|cache| cache.get(url.as_str())) {
+        Some(cached_body) => cached_body,
+        None => {
+            RateLimiter::shared(ACOUSTID_SERVICE, settings.requests_per_minute).wait();
+            let body = reqwest::blocking::get(url.as_str())
+                .and_then(|response| response.text())
+                .map_err(|e| format!("AcoustID request failed: {}", e))?;
+            parse_lookup_response(&body)?;
+            if let Some(cache) = cache {
+                cache.put(url.as_str(), &body);
+            }
+            body
+        }
+    };
+    return parse_lookup_response(&body);
+}
+
+fn build_lookup_url(fingerprint: &Fingerprint, settings: &FingerprintSettings) -> Result<Url, String> {
+    let api_key = settings.api_key.as_deref()
+        .ok_or("No AcoustID API key, set fingerprint.api_key in the config file")?;
+    let base_url = format!("{}{}", settings.base_url.trim_end_matches('/'), LOOKUP_PATH);
+    return Url::parse_with_params(&base_url, &[
+        ("client", api_key),
+        ("duration", &fingerprint.duration.as_secs().to_string()),
+        ("fingerprint", &fingerprint.encoded),
+        ("meta", LOOKUP_META),
+    ]).map_err(|e| format!("Invalid AcoustID URL {:?}: {}", base_url, e));
+}
+
+fn parse_lookup_response(body: &str) -> Result<Vec<RecordingMatch>, String> {
+    let response: LookupResponse = serde_json::from_str(body)
+        .map_err(|e| format!("Failed to parse AcoustID response: {}", e))?;
+    if response.status != "ok" {
+        let message = response.error.map(|error| error.message).unwrap_or(response.status);
+        return Err(format!("AcoustID lookup failed: {}", message));
+    }
+
+    let mut matches: Vec<RecordingMatch> = response.results.into_iter()
+        .flat_map(|result| {
+            let score = result.score;
+            result.recordings.into_iter().map(move |recording| RecordingMatch {
+                score,
+                recording_id: recording.id,
+                title: recording.title,
+                artists: recording.artists.into_iter().map(|artist| artist.name).collect(),
+                album: recording.releasegroups.into_iter().find_map(|release_group| release_group.title),
+                duration_secs: recording.duration,
+            })
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    return Ok(matches);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    const RESPONSE: &str = r#"{"status": "ok", "results": [
+        {"id": "a", "score": 0.42, "recordings": [{"id": "r1", "title": "Other"}]},
+        {"id": "b", "score": 0.97, "recordings": [{"id": "r2", "title": "Song", "duration": 201,
+            "artists": [{"name": "First"}, {"name": "Second"}],
+            "releasegroups": [{"title": "Album", "type": "Album"}]}]}
+    ]}"#;
+
+    /// Answers a single request with `RESPONSE`, returning the request line it received.
+    fn serve_once(listener: TcpListener) -> thread::JoinHandle<String> {
+        return thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            BufReader::new(&stream).read_line(&mut request_line).unwrap();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", RESPONSE.len(), RESPONSE).unwrap();
+            request_line
+        });
+    }
+
+    #[test]
+    fn test_lookup_against_local_service() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let settings = FingerprintSettings {
+            base_url: format!("http://{}", listener.local_addr().unwrap()),
+            api_key: Some("key".to_string()),
+            ..FingerprintSettings::default()
+        };
+        let server = serve_once(listener);
+        let fingerprint = Fingerprint {
+            duration: Duration::from_secs(201),
+            encoded: "AQAAAA".to_string(),
+            raw: Vec::new(),
+        };
+
+        let matches = lookup(&fingerprint, &settings, None).unwrap();
+        let request_line = server.join().unwrap();
+        assert!(request_line.starts_with("GET /v2/lookup?client=key&duration=201&fingerprint=AQAAAA&meta=recordings+releasegroups+compress "));
+        assert_eq!(2, matches.len());
+        assert_eq!(RecordingMatch {
+            score: 0.97,
+            recording_id: "r2".to_string(),
+            title: Some("Song".to_string()),
+            artists: vec!["First".to_string(), "Second".to_string()],
+            album: Some("Album".to_string()),
+            duration_secs: Some(201.0),
+        }, matches[0]);
+    }
+
+    #[test]
+    fn test_lookup_error() {
+        let error = parse_lookup_response(r#"{"status": "error", "error": {"code": 4, "message": "invalid API key"}}"#);
+        assert_eq!(Err("AcoustID lookup failed: invalid API key".to_string()), error);
+    }
+}
@@ -0,0 +1,291 @@
+//! A Rust port of Chromaprint's default fingerprint algorithm (TEST2), producing fingerprints
+//! that AcoustID compares against the ones computed by `fpcalc`.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+pub const SAMPLE_RATE: u32 = 11025;
+const ALGORITHM_ID: u8 = 1;
+const FRAME_SIZE: usize = 4096;
+const FRAME_OVERLAP: usize = FRAME_SIZE - FRAME_SIZE / 3;
+const MIN_FREQUENCY: f64 = 28.0;
+const MAX_FREQUENCY: f64 = 3520.0;
+const CHROMA_BANDS: usize = 12;
+const CHROMA_FILTER: [f64; 5] = [0.25, 0.75, 1.0, 0.75, 0.25];
+const NORMALIZE_THRESHOLD: f64 = 0.01;
+const GRAY_CODE: [u32; 4] = [0, 1, 3, 2];
+
+struct Classifier {
+    filter_type: u8,
+    band: usize,
+    height: usize,
+    width: usize,
+    thresholds: [f64; 3],
+}
+
+const fn classifier(filter_type: u8, band: usize, height: usize, width: usize, thresholds: [f64; 3]) -> Classifier {
+    return Classifier {
+        filter_type,
+        band,
+        height,
+        width,
+        thresholds,
+    };
+}
+
+const CLASSIFIERS: [Classifier; 16] = [
+    classifier(0, 4, 3, 15, [1.98215, 2.35817, 2.63523]),
+    classifier(4, 4, 6, 15, [-1.03809, -0.651211, -0.282167]),
+    classifier(1, 0, 4, 16, [-0.298702, 0.119262, 0.558497]),
+    classifier(3, 8, 2, 12, [-0.105439, 0.0153946, 0.135898]),
+    classifier(3, 4, 4, 8, [-0.142891, 0.0258736, 0.200632]),
+    classifier(4, 0, 3, 5, [-0.826319, -0.590612, -0.368214]),
+    classifier(1, 2, 2, 9, [-0.557409, -0.233035, 0.0534525]),
+    classifier(2, 7, 3, 4, [-0.0646826, 0.00620476, 0.0784847]),
+    classifier(2, 6, 2, 16, [-0.192387, -0.029699, 0.215855]),
+    classifier(2, 1, 3, 2, [-0.0397818, -0.00568076, 0.0292026]),
+    classifier(5, 10, 1, 15, [-0.53823, -0.369934, -0.190235]),
+    classifier(3, 6, 2, 10, [-0.124877, 0.0296483, 0.139239]),
+    classifier(2, 1, 1, 14, [-0.101475, 0.0225617, 0.231971]),
+    classifier(3, 5, 6, 4, [-0.0799915, -0.00729616, 0.063262]),
+    classifier(1, 9, 2, 12, [-0.272556, 0.019424, 0.302559]),
+    classifier(3, 4, 2, 14, [-0.164292, -0.0321188, 0.0846339]),
+];
+
+/// Calculates the raw sub-fingerprints of mono samples at `SAMPLE_RATE`.
+pub fn calculate_fingerprint(samples: &[f32]) -> Vec<u32> {
+    let chroma = normalize(&filter_chroma(&chroma_features(samples)));
+    let image = IntegralImage::new(&chroma);
+    let max_width = CLASSIFIERS.iter().map(|classifier| classifier.width).max().unwrap();
+    if chroma.len() < max_width {
+        return Vec::new();
+    }
+
+    return (0..=chroma.len() - max_width)
+        .map(|offset| {
+            CLASSIFIERS.iter().fold(0u32, |bits, classifier| {
+                (bits << 2) | GRAY_CODE[classifier.classify(&image, offset)]
+            })
+        })
+        .collect();
+}
+
+fn chroma_features(samples: &[f32]) -> Vec<[f64; CHROMA_BANDS]> {
+    let fft = FftPlanner::<f64>::new().plan_fft_forward(FRAME_SIZE);
+    let window: Vec<f64> = (0..FRAME_SIZE)
+        .map(|i| (0.54 - 0.46 * (2.0 * std::f64::consts::PI * i as f64 / (FRAME_SIZE - 1) as f64).cos()) / 32767.0)
+        .collect();
+    let (min_index, max_index) = (frequency_to_index(MIN_FREQUENCY).max(1), frequency_to_index(MAX_FREQUENCY));
+    let notes: Vec<usize> = (0..max_index)
+        .map(|index| {
+            let octave = (index_to_frequency(index) / (440.0 / 16.0)).log2();
+            (CHROMA_BANDS as f64 * (octave - octave.floor())) as usize
+        })
+        .collect();
+
+    let step = FRAME_SIZE - FRAME_OVERLAP;
+    let mut features = Vec::new();
+    let mut start = 0;
+    while start + FRAME_SIZE <= samples.len() {
+        // Chromaprint works on 16 bit samples, which the window scale above undoes.
+        let mut frame: Vec<Complex<f64>> = samples[start..start + FRAME_SIZE].iter().zip(&window)
+            .map(|(sample, weight)| Complex::new(*sample as f64 * 32767.0 * weight, 0.0))
+            .collect();
+        fft.process(&mut frame);
+
+        let mut bands = [0.0; CHROMA_BANDS];
+        for index in min_index..max_index {
+            bands[notes[index]] += frame[index].norm_sqr();
+        }
+        features.push(bands);
+        start += step;
+    }
+    return features;
+}
+
+fn frequency_to_index(frequency: f64) -> usize {
+    return (FRAME_SIZE as f64 * frequency / SAMPLE_RATE as f64).round() as usize;
+}
+
+fn index_to_frequency(index: usize) -> f64 {
+    return index as f64 * SAMPLE_RATE as f64 / FRAME_SIZE as f64;
+}
+
+fn filter_chroma(features: &[[f64; CHROMA_BANDS]]) -> Vec<[f64; CHROMA_BANDS]> {
+    return features.windows(CHROMA_FILTER.len())
+        .map(|window| {
+            let mut bands = [0.0; CHROMA_BANDS];
+            for (feature, coefficient) in window.iter().zip(CHROMA_FILTER) {
+                for band in 0..CHROMA_BANDS {
+                    bands[band] += feature[band] * coefficient;
+                }
+            }
+            bands
+        })
+        .collect();
+}
+
+fn normalize(features: &[[f64; CHROMA_BANDS]]) -> Vec<[f64; CHROMA_BANDS]> {
+    return features.iter()
+        .map(|bands| {
+            let norm = bands.iter().map(|value| value * value).sum::<f64>().sqrt();
+            if norm < NORMALIZE_THRESHOLD {
+                [0.0; CHROMA_BANDS]
+            } else {
+                bands.map(|value| value / norm)
+            }
+        })
+        .collect();
+}
+
+/// Summed-area table over time (rows) and chroma bands (columns).
+struct IntegralImage {
+    sums: Vec<[f64; CHROMA_BANDS + 1]>,
+}
+
+impl IntegralImage {
+    fn new(features: &[[f64; CHROMA_BANDS]]) -> IntegralImage {
+        let mut sums = vec![[0.0; CHROMA_BANDS + 1]];
+        for bands in features {
+            let previous = *sums.last().unwrap();
+            let mut row = [0.0; CHROMA_BANDS + 1];
+            let mut row_sum = 0.0;
+            for band in 0..CHROMA_BANDS {
+                row_sum += bands[band];
+                row[band + 1] = previous[band + 1] + row_sum;
+            }
+            sums.push(row);
+        }
+        return IntegralImage { sums };
+    }
+
+    /// Sum of rows `[x1, x2)` and bands `[y1, y2)`.
+    fn area(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> f64 {
+        if x2 <= x1 || y2 <= y1 {
+            return 0.0;
+        }
+        return self.sums[x2][y2] - self.sums[x1][y2] - self.sums[x2][y1] + self.sums[x1][y1];
+    }
+}
+
+fn subtract_log(a: f64, b: f64) -> f64 {
+    return (1.0 + a).ln() - (1.0 + b).ln();
+}
+
+impl Classifier {
+    fn classify(&self, image: &IntegralImage, x: usize) -> usize {
+        let value = self.filter(image, x);
+        return self.thresholds.iter().take_while(|threshold| value >= **threshold).count();
+    }
+
+    fn filter(&self, image: &IntegralImage, x: usize) -> f64 {
+        let (y, w, h) = (self.band, self.width, self.height);
+        let area = |x1: usize, y1: usize, x2: usize, y2: usize| image.area(x1, y1, x2, y2);
+        return match self.filter_type {
+            0 => subtract_log(area(x, y, x + w, y + h), 0.0),
+            1 => {
+                let h_2 = h / 2;
+                subtract_log(area(x, y + h_2, x + w, y + h), area(x, y, x + w, y + h_2))
+            }
+            2 => {
+                let w_2 = w / 2;
+                subtract_log(area(x + w_2, y, x + w, y + h), area(x, y, x + w_2, y + h))
+            }
+            3 => {
+                let (w_2, h_2) = (w / 2, h / 2);
+                let a = area(x, y + h_2, x + w_2, y + h) + area(x + w_2, y, x + w, y + h_2);
+                let b = area(x, y, x + w_2, y + h_2) + area(x + w_2, y + h_2, x + w, y + h);
+                subtract_log(a, b)
+            }
+            4 => {
+                let h_3 = h / 3;
+                let a = area(x, y, x + w, y + h_3) + area(x, y + 2 * h_3, x + w, y + h);
+                subtract_log(a, area(x, y + h_3, x + w, y + 2 * h_3))
+            }
+            5 => {
+                let w_3 = w / 3;
+                let a = area(x, y, x + w_3, y + h) + area(x + 2 * w_3, y, x + w, y + h);
+                subtract_log(a, area(x + w_3, y, x + 2 * w_3, y + h))
+            }
+            _ => panic!("ERROR: Unknown filter type {}", self.filter_type),
+        };
+    }
+}
+
+/// Compresses and base64 encodes raw sub-fingerprints the way `fpcalc` prints them.
+pub fn encode_fingerprint(fingerprint: &[u32]) -> String {
+    let mut bit_deltas: Vec<u8> = Vec::new();
+    let mut previous = 0u32;
+    for sub_fingerprint in fingerprint {
+        let mut changed = sub_fingerprint ^ previous;
+        let mut last_bit = 0;
+        let mut bit = 1;
+        while changed != 0 {
+            if changed & 1 != 0 {
+                bit_deltas.push(bit - last_bit);
+                last_bit = bit;
+            }
+            changed >>= 1;
+            bit += 1;
+        }
+        bit_deltas.push(0);
+        previous = *sub_fingerprint;
+    }
+
+    let mut bytes = vec![
+        ALGORITHM_ID,
+        (fingerprint.len() >> 16) as u8,
+        (fingerprint.len() >> 8) as u8,
+        fingerprint.len() as u8,
+    ];
+    bytes.extend(pack_bits(bit_deltas.iter().map(|delta| (*delta).min(7) as u32), 3));
+    bytes.extend(pack_bits(bit_deltas.iter().filter(|delta| **delta >= 7).map(|delta| (*delta - 7) as u32), 5));
+    return URL_SAFE_NO_PAD.encode(bytes);
+}
+
+/// Packs values of `width` bits each, least significant bit first.
+fn pack_bits(values: impl Iterator<Item = u32>, width: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut buffered_bits = 0;
+    for value in values {
+        buffer |= value << buffered_bits;
+        buffered_bits += width;
+        while buffered_bits >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            buffered_bits -= 8;
+        }
+    }
+    if buffered_bits > 0 {
+        bytes.push(buffer as u8);
+    }
+    return bytes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_fingerprint() {
+        // Deltas 1, 0 | 2, 0 | 1, 7, 0: the last one overflows into the exceptional bits.
+        let encoded = encode_fingerprint(&[0b1, 0b11, 0b1000_0010]);
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).unwrap();
+        assert_eq!(vec![1, 0, 0, 3, 0b1000_0001, 0b1001_0000, 0b0000_0011, 0], bytes);
+    }
+
+    #[test]
+    fn test_fingerprint_of_tone() {
+        let samples: Vec<f32> = (0..SAMPLE_RATE as usize * 10)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / SAMPLE_RATE as f32).sin() * 0.5)
+            .collect();
+        let fingerprint = calculate_fingerprint(&samples);
+        // 78 frames, 74 after the chroma filter, 59 once the widest classifier fits.
+        assert_eq!(59, fingerprint.len());
+        assert!(fingerprint.windows(2).all(|pair| pair[0] == pair[1]));
+        assert_eq!(fingerprint, calculate_fingerprint(&samples));
+    }
+}
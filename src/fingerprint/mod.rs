@@ -0,0 +1,141 @@
+pub mod acoustid;
+mod chromaprint;
+
+use std::path::Path;
+use std::time::Duration;
+use lofty::file::AudioFile;
+use crate::audio::decoder::{decode_audio_file, resample};
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+
+/// Items two fingerprints may be shifted against each other when compared, about 10 seconds.
+const MAX_ALIGNMENT_OFFSET: usize = 80;
+/// Fewest overlapping items a comparison needs, about 6 seconds.
+const MIN_OVERLAP: usize = 50;
+
+/// A Chromaprint fingerprint of the start of a file, with the duration of the whole file.
+pub struct Fingerprint {
+    pub duration: Duration,
+    pub encoded: String,
+    pub raw: Vec<u32>,
+}
+
+impl Fingerprint {
+    /// Fingerprints the first `max_length` of the file's audio.
+    pub fn calculate(file_path: &Path, max_length: Duration) -> Result<Fingerprint, String> {
+        let audio = decode_audio_file(file_path, Some(max_length))?;
+        let samples = resample(&audio.to_mono(), audio.sample_rate, chromaprint::SAMPLE_RATE);
+        let raw_fingerprint = chromaprint::calculate_fingerprint(&samples);
+        if raw_fingerprint.is_empty() {
+            return Err("Audio too short to fingerprint".to_string());
+        }
+
+        let duration = lofty::read_from_path(file_path)
+            .map(|tagged_file| tagged_file.properties().duration())
+            .unwrap_or_else(|_| audio.duration());
+        return Ok(Fingerprint {
+            duration,
+            encoded: chromaprint::encode_fingerprint(&raw_fingerprint),
+            raw: raw_fingerprint,
+        });
+    }
+
+    /// The fraction of equal bits at the best alignment of the two fingerprints, around 0.5 for
+    /// unrelated audio and close to 1 for the same recording, even in another format or bitrate.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let mut best: f64 = 0.0;
+        for offset in 0..=MAX_ALIGNMENT_OFFSET {
+            for (a, b) in [(&self.raw, &other.raw), (&other.raw, &self.raw)] {
+                let Some(shifted) = a.get(offset..) else {
+                    continue;
+                };
+                let overlap = shifted.len().min(b.len());
+                if overlap < MIN_OVERLAP {
+                    continue;
+                }
+                let differing_bits: u32 = shifted.iter().zip(b.iter()).map(|(x, y)| (x ^ y).count_ones()).sum();
+                best = best.max(1.0 - differing_bits as f64 / (overlap * 32) as f64);
+            }
+        }
+        return best;
+    }
+}
+
+/// Fills in a missing title, artist or album from the recording the file's fingerprint matches,
+/// so files without usable tags can still be searched for. Failures leave the metadata as it was.
+pub fn identify_by_fingerprint(file_path: &Path, song_metadata: SongMetadata, settings: &Settings) -> SongMetadata {
+    if !settings.fingerprint.enabled || (song_metadata.title.is_some() && song_metadata.artists.is_some()) {
+        return song_metadata;
+    }
+
+    println!("Title or artist missing, identifying the file by its fingerprint");
+    let max_length = Duration::from_secs(settings.fingerprint.max_length_secs);
+    let matches = Fingerprint::calculate(file_path, max_length)
+        .and_then(|fingerprint| acoustid::lookup(&fingerprint, &settings.fingerprint, settings.cache.open().as_ref()));
+    let best_match = match matches {
+        Ok(matches) => matches.into_iter()
+            .find(|recording| recording.score >= settings.fingerprint.minimum_score && recording.title.is_some()),
+        Err(e) => {
+            eprintln!("WARN: Failed to identify {:?} by fingerprint: {}", file_path, e);
+            return song_metadata;
+        }
+    };
+    let Some(recording) = best_match else {
+        println!("No recording matches the fingerprint");
+        return song_metadata;
+    };
+
+    println!("Fingerprint matches recording {} (score {:.2})", recording.recording_id, recording.score);
+    return SongMetadata {
+        title: song_metadata.title.or(recording.title),
+        artists: song_metadata.artists.or((!recording.artists.is_empty()).then_some(recording.artists)),
+        album: song_metadata.album.or(recording.album),
+        ..song_metadata
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TestAudioFile, SAMPLE_RATE};
+
+    #[test]
+    fn test_fingerprint_file() {
+        let samples: Vec<i16> = (0..SAMPLE_RATE as usize * 10)
+            .map(|i| ((i as f32 * 0.3).sin() * 8000.0 + (i as f32 * 0.05).sin() * 4000.0) as i16)
+            .collect();
+        let audio_file = TestAudioFile::wav_with_samples("fingerprint", &samples);
+
+        let fingerprint = Fingerprint::calculate(audio_file.path(), Duration::from_secs(120)).unwrap();
+        assert_eq!(10, fingerprint.duration.as_secs());
+        assert!(fingerprint.encoded.starts_with("AQAA"));
+
+        let shorter = Fingerprint::calculate(audio_file.path(), Duration::from_secs(5)).unwrap();
+        assert_eq!(10, shorter.duration.as_secs());
+        assert!(shorter.encoded.len() < fingerprint.encoded.len());
+    }
+
+    #[test]
+    fn test_fingerprint_similarity() {
+        // Half second notes, one melody quieter and with noise added, the other a different melody.
+        let melody = |notes: &[f32], gain: f32, noise: f32| -> Vec<i16> {
+            (0..SAMPLE_RATE as usize * 12)
+                .map(|i| {
+                    let note = notes[(i * 2 / SAMPLE_RATE as usize) % notes.len()];
+                    let tone = (2.0 * std::f32::consts::PI * note * i as f32 / SAMPLE_RATE as f32).sin();
+                    let hiss = ((i as f32 * 12.9898).sin() * 43758.547).fract();
+                    ((tone * gain + hiss * noise) * 8000.0) as i16
+                })
+                .collect()
+        };
+        let original = TestAudioFile::wav_with_samples("similarity-original", &melody(&[262.0, 330.0, 392.0, 523.0, 440.0, 349.0], 1.0, 0.0));
+        let copy = TestAudioFile::wav_with_samples("similarity-copy", &melody(&[262.0, 330.0, 392.0, 523.0, 440.0, 349.0], 0.7, 0.05));
+        let other = TestAudioFile::wav_with_samples("similarity-other", &melody(&[294.0, 247.0, 370.0, 415.0, 311.0, 494.0], 1.0, 0.0));
+        let fingerprint = |file: &TestAudioFile| Fingerprint::calculate(file.path(), Duration::from_secs(120)).unwrap();
+
+        let (original, copy, other) = (fingerprint(&original), fingerprint(&copy), fingerprint(&other));
+        assert_eq!(1.0, original.similarity(&original));
+        assert!(original.similarity(&copy) > 0.9, "copy similarity {}", original.similarity(&copy));
+        assert!(original.similarity(&other) < 0.8, "other similarity {}", original.similarity(&other));
+    }
+}
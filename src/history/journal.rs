@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use crate::settings::WriterSettings;
+use super::tag_snapshot::{hash_file, FileSnapshot};
+
+const JOURNAL_DIR_NAME: &str = "imd";
+const JOURNAL_FILE_NAME: &str = "journal.jsonl";
+
+/// One tag write, with everything needed to put the file's tags back the way they were.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub run_id: String,
+    pub timestamp: String,
+    pub path: PathBuf,
+    pub hash_after: String,
+    pub previous_tags: FileSnapshot,
+    #[serde(default)]
+    pub undone: bool,
+}
+
+pub enum UndoTarget {
+    LastRun,
+    Run(String),
+    File(PathBuf),
+}
+
+/// An append only log of tag writes stored as JSON lines, one entry per written file.
+pub struct Journal {
+    path: PathBuf,
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Journal {
+        let entries = match fs::read_to_string(path) {
+            Ok(contents) => contents.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str::<JournalEntry>(line)
+                    .unwrap_or_else(|e| panic!("ERROR: Corrupt journal entry in {:?}: {}", path, e)))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        return Journal {
+            path: path.to_path_buf(),
+            entries,
+        };
+    }
+
+    /// Records a write under `run_id`, with the tags the file had before it.
+    pub fn record(&mut self, run_id: &str, file_path: &Path, previous_tags: FileSnapshot) {
+        let entry = JournalEntry {
+            run_id: run_id.to_string(),
+            timestamp: Local::now().to_rfc3339(),
+            path: canonical_path(file_path),
+            hash_after: hash_file(file_path),
+            previous_tags,
+            undone: false,
+        };
+        self.append(&entry);
+        self.entries.push(entry);
+    }
+
+    /// Points existing entries for `from` at the file's new location after it has been moved.
+    pub fn record_move(&mut self, from: &Path, to: &Path) {
+        let from = canonical_path(from);
+        let to = canonical_path(to);
+        let mut changed = false;
+        for entry in self.entries.iter_mut().filter(|entry| entry.path == from) {
+            entry.path = to.clone();
+            changed = true;
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    /// Restores the tags recorded for `target`. Files that changed since imd wrote them are
+    /// skipped unless `force` is set.
+    pub fn undo(&mut self, target: &UndoTarget, force: bool, writer_settings: &WriterSettings) {
+        let selected = self.select(target);
+        if selected.is_empty() {
+            println!("Nothing to undo");
+            return;
+        }
+
+        // Restore each file to its state before the first selected write, after checking it
+        // still matches the state left by the last selected write.
+        let mut writes_by_path: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+        for index in selected {
+            writes_by_path.entry(self.entries[index].path.clone()).or_default().push(index);
+        }
+
+        for (path, indexes) in writes_by_path {
+            let first = &self.entries[indexes[0]];
+            let last = &self.entries[*indexes.last().unwrap()];
+
+            if !path.is_file() {
+                eprintln!("WARN: {:?} no longer exists, skipping", path);
+                continue;
+            }
+            if hash_file(&path) != last.hash_after && !force {
+                eprintln!("WARN: {:?} has changed since it was written on {}, skipping (use --force to restore anyway)", path, last.timestamp);
+                continue;
+            }
+
+            first.previous_tags.restore(&path, writer_settings);
+            println!("Restored tags of {:?} from run {}", path, first.run_id);
+            for index in &indexes {
+                self.entries[*index].undone = true;
+            }
+
+            // The file is now in the state left by the previous write, which may be undone next.
+            let restored_hash = hash_file(&path);
+            if let Some(previous) = self.entries[..indexes[0]].iter_mut().rev().find(|entry| entry.path == path && !entry.undone) {
+                previous.hash_after = restored_hash;
+            }
+        }
+
+        self.save();
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        return &self.entries;
+    }
+
+    fn select(&self, target: &UndoTarget) -> Vec<usize> {
+        let pending = self.entries.iter().enumerate().filter(|(_, entry)| !entry.undone);
+
+        return match target {
+            UndoTarget::LastRun => match self.entries.iter().rev().find(|entry| !entry.undone) {
+                Some(last) => pending.filter(|(_, entry)| entry.run_id == last.run_id).map(|(index, _)| index).collect(),
+                None => Vec::new(),
+            },
+            UndoTarget::Run(run_id) => pending.filter(|(_, entry)| &entry.run_id == run_id).map(|(index, _)| index).collect(),
+            UndoTarget::File(path) => {
+                let path = canonical_path(path);
+                // Only the most recent write, so repeated undos step back one write at a time.
+                pending.filter(|(_, entry)| entry.path == path).map(|(index, _)| index).next_back().into_iter().collect()
+            },
+        };
+    }
+
+    fn append(&self, entry: &JournalEntry) {
+        self.ensure_parent_dir();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .unwrap_or_else(|e| panic!("ERROR: Failed to open journal {:?}: {}", self.path, e));
+        let line = serde_json::to_string(entry).expect("journal entries serialize to JSON");
+        writeln!(file, "{}", line).unwrap_or_else(|e| panic!("ERROR: Failed to write journal {:?}: {}", self.path, e));
+    }
+
+    fn save(&self) {
+        self.ensure_parent_dir();
+        let contents = self.entries.iter()
+            .map(|entry| serde_json::to_string(entry).expect("journal entries serialize to JSON") + "\n")
+            .collect::<String>();
+        fs::write(&self.path, contents).unwrap_or_else(|e| panic!("ERROR: Failed to write journal {:?}: {}", self.path, e));
+    }
+
+    fn ensure_parent_dir(&self) {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|e| panic!("ERROR: Failed to create directory {:?}: {}", parent, e));
+        }
+    }
+}
+
+pub fn default_journal_path() -> PathBuf {
+    return dirs::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(JOURNAL_DIR_NAME)
+        .join(JOURNAL_FILE_NAME);
+}
+
+/// A run ID that sorts by start time, e.g. `20240612-183005-4711`.
+pub fn new_run_id() -> String {
+    return format!("{}-{}", Local::now().format("%Y%m%d-%H%M%S"), std::process::id());
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+    return fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::prelude::*;
+    use crate::metadata::song_metadata::SongMetadata;
+    use crate::test_support::TestAudioFile;
+
+    fn write_title(journal: &mut Journal, run_id: &str, path: &Path, title: &str) {
+        let metadata = SongMetadata {
+            title: Some(title.to_string()),
+            ..SongMetadata::default()
+        };
+        let previous_tags = FileSnapshot::take(path);
+        metadata.write_metadata_to_audio_file(path, &WriterSettings::default());
+        journal.record(run_id, path, previous_tags);
+    }
+
+    fn read_title(path: &Path) -> Option<String> {
+        let tagged_file = lofty::read_from_path(path).unwrap();
+        return tagged_file.first_tag().and_then(|tag| tag.title()).map(|title| title.to_string());
+    }
+
+    #[test]
+    fn test_undo_last_run_and_file() {
+        let audio_file = TestAudioFile::wav("journal");
+        let mut journal = Journal::open(&audio_file.dir().join("journal.jsonl"));
+
+        write_title(&mut journal, "run-1", audio_file.path(), "One");
+        write_title(&mut journal, "run-2", audio_file.path(), "Two");
+        write_title(&mut journal, "run-2", audio_file.path(), "Three");
+
+        journal.undo(&UndoTarget::LastRun, false, &WriterSettings::default());
+        assert_eq!(Some("One".to_string()), read_title(audio_file.path()));
+
+        let mut reopened = Journal::open(&audio_file.dir().join("journal.jsonl"));
+        assert_eq!(vec![false, true, true], reopened.entries().iter().map(|entry| entry.undone).collect::<Vec<bool>>());
+
+        reopened.undo(&UndoTarget::File(audio_file.path().to_path_buf()), false, &WriterSettings::default());
+        assert_eq!(None, read_title(audio_file.path()));
+    }
+
+    #[test]
+    fn test_undo_skips_changed_files() {
+        let audio_file = TestAudioFile::wav("journal-changed");
+        let mut journal = Journal::open(&audio_file.dir().join("journal.jsonl"));
+
+        write_title(&mut journal, "run-1", audio_file.path(), "One");
+        SongMetadata {
+            title: Some("Edited elsewhere".to_string()),
+            ..SongMetadata::default()
+        }.write_metadata_to_audio_file(audio_file.path(), &WriterSettings::default());
+
+        journal.undo(&UndoTarget::Run("run-1".to_string()), false, &WriterSettings::default());
+        assert_eq!(Some("Edited elsewhere".to_string()), read_title(audio_file.path()));
+
+        journal.undo(&UndoTarget::Run("run-1".to_string()), true, &WriterSettings::default());
+        assert_eq!(None, read_title(audio_file.path()));
+    }
+}
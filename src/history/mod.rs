@@ -0,0 +1,3 @@
+pub mod tag_snapshot;
+pub mod journal;
+pub mod state_db;
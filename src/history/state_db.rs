@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::Local;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::library::scan::{file_size_and_mtime, path_key};
+use super::tag_snapshot::hash_file;
+
+const STATE_DIR_NAME: &str = "imd";
+const STATE_FILE_NAME: &str = "state.sqlite3";
+const FAILED_OUTCOME: &str = "failed";
+
+/// Which previously processed files a directory run skips.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipMode {
+    /// Process every file.
+    None,
+    /// Skip files processed without failing that haven't changed since, e.g. to resume a run.
+    Done,
+    /// Skip every file that hasn't changed since it was last processed, including failed ones.
+    Unchanged,
+}
+
+/// How a batch run ended for one file, recorded in the state database.
+pub struct FileOutcome {
+    /// E.g. written, matched or failed.
+    pub state: &'static str,
+    /// One line describing the chosen match or analysis result.
+    pub summary: String,
+    pub score: Option<f64>,
+}
+
+impl FileOutcome {
+    pub fn new(state: &'static str, summary: String) -> FileOutcome {
+        FileOutcome {
+            state,
+            summary,
+            score: None,
+        }
+    }
+
+    pub fn failed(error: String) -> FileOutcome {
+        return FileOutcome::new(FAILED_OUTCOME, error);
+    }
+}
+
+/// A file as last seen by a task, to tell whether it changed since.
+struct FileState {
+    size: u64,
+    modified_secs: i64,
+    hash: String,
+    outcome: String,
+}
+
+/// A SQLite database recording each file processed by batch runs, per task (e.g. `write` or
+/// `analyse`), so re-runs can skip files that are done and unchanged.
+pub struct StateDb {
+    connection: Mutex<Connection>,
+}
+
+impl StateDb {
+    pub fn open(path: &Path) -> StateDb {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|e| panic!("ERROR: Failed to create {:?}: {}", parent, e));
+        }
+        let connection = Connection::open(path)
+            .unwrap_or_else(|e| panic!("ERROR: Failed to open the state database {:?}: {}", path, e));
+        // Write ahead logging keeps the per-file commits cheap, a crash loses at most the last few.
+        connection.execute_batch("
+            PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS processed_files (
+                path TEXT NOT NULL,
+                task TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified_secs INTEGER NOT NULL,
+                hash TEXT NOT NULL,
+                processed_at TEXT NOT NULL,
+                run_id TEXT NOT NULL,
+                outcome TEXT NOT NULL,
+                summary TEXT NOT NULL,
+                score REAL,
+                PRIMARY KEY (path, task)
+            );
+        ").unwrap_or_else(|e| panic!("ERROR: Failed to set up the state database {:?}: {}", path, e));
+
+        return StateDb {
+            connection: Mutex::new(connection),
+        };
+    }
+
+    /// Records the outcome of `task` for a file, together with the file's current size,
+    /// modification time and content hash.
+    pub fn record(&self, file_path: &Path, task: &str, run_id: &str, outcome: &FileOutcome) {
+        let Some((size, modified_secs)) = file_size_and_mtime(file_path) else {
+            eprintln!("WARN: {:?} no longer exists, not recording it in the state database", file_path);
+            return;
+        };
+        let hash = hash_file(file_path);
+        let result = self.connection.lock().expect("ERROR: State database lock poisoned").execute(
+            "INSERT OR REPLACE INTO processed_files (path, task, size, modified_secs, hash, processed_at, run_id, outcome, summary, score)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![path_key(file_path), task, size as i64, modified_secs, hash, Local::now().to_rfc3339(), run_id, outcome.state, outcome.summary, outcome.score],
+        );
+        if let Err(e) = result {
+            eprintln!("WARN: Failed to record {:?} in the state database: {}", file_path, e);
+        }
+    }
+
+    /// The files `task` still has to process under `skip`.
+    pub fn pending(&self, files: Vec<PathBuf>, task: &str, skip: SkipMode) -> Vec<PathBuf> {
+        if skip == SkipMode::None {
+            return files;
+        }
+        return files.into_iter()
+            .filter(|file| !self.can_skip(file, task, skip))
+            .collect();
+    }
+
+    fn can_skip(&self, file_path: &Path, task: &str, skip: SkipMode) -> bool {
+        let Some(previous) = self.file_state(file_path, task) else {
+            return false;
+        };
+        if skip == SkipMode::Done && previous.outcome == FAILED_OUTCOME {
+            return false;
+        }
+        // The hash is only needed when the cheap checks disagree, e.g. after a copy or a touch.
+        return match file_size_and_mtime(file_path) {
+            Some((size, modified_secs)) if size == previous.size && modified_secs == previous.modified_secs => true,
+            Some((size, _)) if size == previous.size => hash_file(file_path) == previous.hash,
+            _ => false,
+        };
+    }
+
+    fn file_state(&self, file_path: &Path, task: &str) -> Option<FileState> {
+        return self.connection.lock().expect("ERROR: State database lock poisoned")
+            .query_row(
+                "SELECT size, modified_secs, hash, outcome FROM processed_files WHERE path = ?1 AND task = ?2",
+                params![path_key(file_path), task],
+                |row| Ok(FileState {
+                    size: row.get::<_, i64>(0)? as u64,
+                    modified_secs: row.get(1)?,
+                    hash: row.get(2)?,
+                    outcome: row.get(3)?,
+                }),
+            )
+            .optional()
+            .unwrap_or_else(|e| panic!("ERROR: Failed to read the state database: {}", e));
+    }
+}
+
+pub fn default_state_path() -> PathBuf {
+    return dirs::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(STATE_DIR_NAME)
+        .join(STATE_FILE_NAME);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_skips_done_and_unchanged_files() {
+        let done = TestAudioFile::wav("state-done");
+        let failed = TestAudioFile::wav("state-failed");
+        let changed = TestAudioFile::wav("state-changed");
+        let new = TestAudioFile::wav("state-new");
+        let state = StateDb::open(&done.dir().join(STATE_FILE_NAME));
+        state.record(done.path(), "write", "run", &FileOutcome::new("written", "Artist - Title".to_string()));
+        state.record(failed.path(), "write", "run", &FileOutcome::failed("ERROR: Title is required!".to_string()));
+        state.record(changed.path(), "write", "run", &FileOutcome::new("written", "Artist - Title".to_string()));
+        fs::write(changed.path(), b"different contents").unwrap();
+
+        let files = || [&done, &failed, &changed, &new].iter().map(|file| file.path().to_path_buf()).collect::<Vec<PathBuf>>();
+        assert_eq!(4, state.pending(files(), "write", SkipMode::None).len());
+        assert_eq!(vec![failed.path(), changed.path(), new.path()], state.pending(files(), "write", SkipMode::Done));
+        assert_eq!(vec![changed.path(), new.path()], state.pending(files(), "write", SkipMode::Unchanged));
+        // Other tasks keep their own records.
+        assert_eq!(4, state.pending(files(), "analyse", SkipMode::Unchanged).len());
+    }
+}
@@ -0,0 +1,287 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use lofty::config::WriteOptions;
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use lofty::tag::{ItemValue, Tag, TagItem, TagType};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::metadata::atomic_write::write_atomically;
+use crate::metadata::id3v2_writer::read_id3v2;
+use crate::metadata::tag_target::{tag_type_from_name, tag_type_name};
+use crate::settings::WriterSettings;
+
+/// Every tag of an audio file, including pictures, in a form that can be stored and restored later.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileSnapshot {
+    pub tags: Vec<TagSnapshot>,
+    /// The encoded ID3v2 tag, base64 encoded. lofty's generic tag drops frames it has no item for
+    /// and always writes ID3v2.4, so the ID3v2 tag is restored from this as it was.
+    #[serde(default)]
+    id3v2: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TagSnapshot {
+    tag_type: String,
+    items: Vec<ItemSnapshot>,
+    pictures: Vec<PictureSnapshot>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ItemSnapshot {
+    /// The format specific key, e.g. `TIT2` for ID3v2 or `TITLE` for Vorbis comments.
+    key: String,
+    value: ValueSnapshot,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ValueSnapshot {
+    Text(String),
+    Locator(String),
+    /// Base64 encoded bytes.
+    Binary(String),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct PictureSnapshot {
+    pic_type: u8,
+    mime_type: Option<String>,
+    description: Option<String>,
+    /// Base64 encoded bytes.
+    data: String,
+}
+
+impl FileSnapshot {
+    pub fn take(file_path: &Path) -> FileSnapshot {
+        let tagged_file = Probe::open(file_path)
+            .expect("ERROR: Bad path provided!")
+            .read()
+            .expect("ERROR: Failed to read file!");
+
+        let id3v2 = match read_id3v2(file_path) {
+            Ok(tag) => tag.map(|tag| {
+                let mut encoded: Vec<u8> = Vec::new();
+                tag.write_to(&mut encoded, tag.version()).expect("ERROR: Failed to encode ID3v2 tag");
+                BASE64.encode(encoded)
+            }),
+            Err(e) => {
+                eprintln!("WARN: {}, backing up the ID3v2 tag through the generic tag", e);
+                None
+            },
+        };
+        return FileSnapshot {
+            tags: tagged_file.tags().iter().map(TagSnapshot::from_tag).collect(),
+            id3v2,
+        };
+    }
+
+    /// Replaces every tag in the file with the snapshotted tags. Tag types that were not present
+    /// when the snapshot was taken are removed.
+    pub fn restore(&self, file_path: &Path, writer_settings: &WriterSettings) {
+        let result = write_atomically(
+            file_path,
+            writer_settings.preserve_mtime,
+            |temp_path| self.restore_tags(temp_path),
+            |temp_path| self.verify_restored(temp_path),
+        );
+        if let Err(e) = result {
+            panic!("ERROR: Failed to restore tags of {:?}: {}", file_path, e);
+        }
+    }
+
+    fn restore_tags(&self, file_path: &Path) -> Result<(), String> {
+        let tagged_file = Probe::open(file_path)
+            .map_err(|e| format!("Bad path provided: {:?}", e))?
+            .read()
+            .map_err(|e| format!("Failed to read file: {:?}", e))?;
+        let snapshot_tag_types = self.tag_types();
+
+        for tag in tagged_file.tags() {
+            if !snapshot_tag_types.contains(&tag.tag_type()) {
+                tag.tag_type().remove_from_path(file_path)
+                    .map_err(|e| format!("Failed to remove {:?} tag: {:?}", tag.tag_type(), e))?;
+            }
+        }
+        for tag_snapshot in &self.tags {
+            if tag_snapshot.tag_type() == TagType::Id3v2 && self.id3v2.is_some() {
+                continue;
+            }
+            tag_snapshot.to_tag().save_to_path(file_path, WriteOptions::default())
+                .map_err(|e| format!("Failed to restore {:?} tag: {:?}", tag_snapshot.tag_type, e))?;
+        }
+        if let Some(id3v2) = &self.id3v2 {
+            let tag = id3::Tag::read_from2(Cursor::new(decode_base64(id3v2)))
+                .map_err(|e| format!("Corrupt ID3v2 tag in snapshot: {}", e))?;
+            tag.write_to_path(file_path, tag.version())
+                .map_err(|e| format!("Failed to restore Id3v2 tag: {}", e))?;
+        }
+
+        return Ok(());
+    }
+
+    fn verify_restored(&self, file_path: &Path) -> Result<(), String> {
+        let restored = FileSnapshot::take(file_path);
+        let mut expected_tag_types = self.tag_types();
+        let mut restored_tag_types = restored.tag_types();
+        expected_tag_types.sort_by_key(|tag_type| tag_type_name(*tag_type));
+        restored_tag_types.sort_by_key(|tag_type| tag_type_name(*tag_type));
+
+        if expected_tag_types != restored_tag_types {
+            return Err(format!("Restored tag types {:?} instead of {:?}", restored_tag_types, expected_tag_types));
+        }
+        return Ok(());
+    }
+
+    fn tag_types(&self) -> Vec<TagType> {
+        return self.tags.iter().map(|tag| tag.tag_type()).collect();
+    }
+}
+
+impl TagSnapshot {
+    fn from_tag(tag: &Tag) -> TagSnapshot {
+        let tag_type = tag.tag_type();
+        let items = tag.items()
+            .filter_map(|item| {
+                let key = match item.key().map_key(tag_type, true) {
+                    Some(key) => key.to_string(),
+                    None => {
+                        eprintln!("WARN: {:?} can not be stored in a {:?} tag, it will not be backed up", item.key(), tag_type);
+                        return None;
+                    },
+                };
+                let value = match item.value() {
+                    ItemValue::Text(text) => ValueSnapshot::Text(text.clone()),
+                    ItemValue::Locator(locator) => ValueSnapshot::Locator(locator.clone()),
+                    ItemValue::Binary(data) => ValueSnapshot::Binary(BASE64.encode(data)),
+                };
+                Some(ItemSnapshot { key, value })
+            })
+            .collect();
+        let pictures = tag.pictures().iter()
+            .map(|picture| PictureSnapshot {
+                pic_type: picture.pic_type().as_u8(),
+                mime_type: picture.mime_type().map(|mime_type| mime_type.as_str().to_string()),
+                description: picture.description().map(|description| description.to_string()),
+                data: BASE64.encode(picture.data()),
+            })
+            .collect();
+
+        return TagSnapshot {
+            tag_type: tag_type_name(tag_type).to_string(),
+            items,
+            pictures,
+        };
+    }
+
+    fn tag_type(&self) -> TagType {
+        return tag_type_from_name(&self.tag_type)
+            .unwrap_or_else(|| panic!("ERROR: Unknown tag type {:?} in snapshot", self.tag_type));
+    }
+
+    fn to_tag(&self) -> Tag {
+        let tag_type = self.tag_type();
+        let mut tag = Tag::new(tag_type);
+
+        for item in &self.items {
+            let value = match &item.value {
+                ValueSnapshot::Text(text) => ItemValue::Text(text.clone()),
+                ValueSnapshot::Locator(locator) => ItemValue::Locator(locator.clone()),
+                ValueSnapshot::Binary(data) => ItemValue::Binary(decode_base64(data)),
+            };
+            tag.push_unchecked(TagItem::new(ItemKey::from_key(tag_type, &item.key), value));
+        }
+        for picture in &self.pictures {
+            tag.push_picture(Picture::new_unchecked(
+                PictureType::from_u8(picture.pic_type),
+                picture.mime_type.as_deref().map(MimeType::from_str),
+                picture.description.clone(),
+                decode_base64(&picture.data),
+            ));
+        }
+
+        return tag;
+    }
+}
+
+/// SHA-256 of the whole file, used to detect changes made after imd wrote to it.
+pub fn hash_file(file_path: &Path) -> String {
+    let contents = fs::read(file_path)
+        .unwrap_or_else(|e| panic!("ERROR: Failed to read {:?}: {}", file_path, e));
+    return format!("{:x}", Sha256::digest(contents));
+}
+
+fn decode_base64(data: &str) -> Vec<u8> {
+    return BASE64.decode(data).expect("ERROR: Corrupt binary data in snapshot");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::frame::Private;
+    use id3::{TagLike, Version};
+    use crate::metadata::song_metadata::SongMetadata;
+    use crate::settings::Id3v2Settings;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_restore_snapshot() {
+        let audio_file = TestAudioFile::wav("snapshot");
+        let before = SongMetadata {
+            title: Some("Before".to_string()),
+            album: Some("Album".to_string()),
+            ..SongMetadata::default()
+        };
+        before.write_metadata_to_audio_file(audio_file.path(), &WriterSettings::default());
+        let snapshot = FileSnapshot::take(audio_file.path());
+
+        let after = SongMetadata {
+            title: Some("After".to_string()),
+            artists: Some(vec!["Artist".to_string()]),
+            ..SongMetadata::default()
+        };
+        after.write_metadata_to_audio_file(audio_file.path(), &WriterSettings::default());
+        snapshot.restore(audio_file.path(), &WriterSettings::default());
+
+        let restored = SongMetadata::read_metadata_from_audio_file(audio_file.path());
+        assert_eq!(Some("Before".to_string()), restored.title);
+        assert_eq!(Some("Album".to_string()), restored.album);
+        assert_eq!(None, restored.artists);
+    }
+
+    #[test]
+    fn test_restore_keeps_id3v2_frames_and_version() {
+        let audio_file = TestAudioFile::mp3("snapshot-id3v2");
+        let mut original = id3::Tag::new();
+        original.set_title("Before");
+        original.add_frame(Private {
+            owner_identifier: "imd-test".to_string(),
+            private_data: vec![1, 2, 3],
+        });
+        original.write_to_path(audio_file.path(), Version::Id3v23).unwrap();
+        let snapshot = FileSnapshot::take(audio_file.path());
+
+        let writer_settings = WriterSettings {
+            id3v2: Id3v2Settings {
+                preserve_unknown_frames: false,
+                ..Id3v2Settings::default()
+            },
+            ..WriterSettings::default()
+        };
+        SongMetadata {
+            title: Some("After".to_string()),
+            ..SongMetadata::default()
+        }.write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+        snapshot.restore(audio_file.path(), &WriterSettings::default());
+
+        let restored = read_id3v2(audio_file.path()).unwrap().unwrap();
+        assert_eq!(Version::Id3v23, restored.version());
+        assert_eq!(Some("Before"), restored.title());
+        assert_eq!(1, restored.frames().filter(|frame| frame.id() == "PRIV").count());
+    }
+}
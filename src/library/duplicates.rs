@@ -0,0 +1,294 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::fingerprint::Fingerprint;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::{DuplicateSettings, QualitySettings};
+
+/// Bracketed title suffixes that don't make a different recording, e.g. "(2009 Remaster)".
+const IGNORED_QUALIFIERS: [&str; 4] = ["remaster", "explicit", "album version", "lp version"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAction {
+    /// Only list the duplicate groups.
+    Report,
+    /// Move every file but the keeper into the quarantine folder.
+    Quarantine,
+    /// Delete every file but the keeper, after confirmation.
+    Delete,
+}
+
+impl DuplicateAction {
+    pub fn from_name(name: &str) -> Option<DuplicateAction> {
+        return match name {
+            "report" => Some(DuplicateAction::Report),
+            "quarantine" => Some(DuplicateAction::Quarantine),
+            "delete" => Some(DuplicateAction::Delete),
+            _ => None,
+        };
+    }
+}
+
+/// A file considered for duplicate detection.
+pub struct Candidate {
+    pub path: PathBuf,
+    pub metadata: SongMetadata,
+    /// The lower case file extension, e.g. `flac`.
+    pub format: String,
+    pub fingerprint: Option<Fingerprint>,
+}
+
+/// Candidates holding the same song, by index, the recommended keeper first.
+pub struct DuplicateGroup {
+    pub files: Vec<usize>,
+}
+
+/// Groups candidates with the same normalised title and artist and a similar duration, and, when
+/// fingerprints were calculated, candidates whose fingerprints match. A file without a duration
+/// only counts as a copy when their fingerprints match. A file only joins a group when it is a
+/// copy of every file in it, so a 183 s copy can't chain a 180 s studio take and a 186 s live take
+/// into one group. Only groups with more than one file are returned, ordered by their keeper's path.
+pub fn find_duplicates(candidates: &[Candidate], settings: &DuplicateSettings, quality: &QualitySettings) -> Vec<DuplicateGroup> {
+    let tolerance = settings.duration_tolerance_secs as f64;
+    let duration_secs = |index: usize| candidates[index].metadata.duration.map(|duration| duration.as_secs_f64());
+    let fingerprints_match = |a: usize, b: usize| match (&candidates[a].fingerprint, &candidates[b].fingerprint) {
+        (Some(fingerprint_a), Some(fingerprint_b)) => fingerprint_a.similarity(fingerprint_b) >= settings.fingerprint_similarity,
+        _ => false,
+    };
+    let mut copies: BTreeSet<(usize, usize)> = BTreeSet::new();
+
+    let mut by_song: BTreeMap<(String, String), Vec<usize>> = BTreeMap::new();
+    for (index, candidate) in candidates.iter().enumerate() {
+        if let (Some(title), Some(artist)) = (&candidate.metadata.title, candidate.metadata.artists.as_ref().and_then(|artists| artists.first())) {
+            by_song.entry((normalise_title(title), normalise_artist(artist))).or_default().push(index);
+        }
+    }
+    for indexes in by_song.values() {
+        for (position, a) in indexes.iter().enumerate() {
+            for b in &indexes[position + 1..] {
+                let same_recording = match (duration_secs(*a), duration_secs(*b)) {
+                    (Some(a), Some(b)) => (a - b).abs() <= tolerance,
+                    _ => fingerprints_match(*a, *b),
+                };
+                if same_recording {
+                    copies.insert((*a, *b));
+                }
+            }
+        }
+    }
+
+    // Only files of similar length can hold the same recording, so fingerprints are compared
+    // within a sliding duration window instead of pair by pair. Files without a duration could be
+    // any length and are compared with every fingerprinted file.
+    let (mut timed, untimed): (Vec<usize>, Vec<usize>) = (0..candidates.len())
+        .filter(|index| candidates[*index].fingerprint.is_some())
+        .partition(|index| duration_secs(*index).is_some());
+    timed.sort_by(|a, b| duration_secs(*a).unwrap().total_cmp(&duration_secs(*b).unwrap()));
+    for (position, a) in timed.iter().enumerate() {
+        for b in &timed[position + 1..] {
+            if duration_secs(*b).unwrap() - duration_secs(*a).unwrap() > tolerance {
+                break;
+            }
+            if fingerprints_match(*a, *b) {
+                copies.insert((*a.min(b), *a.max(b)));
+            }
+        }
+    }
+    for a in &untimed {
+        for b in timed.iter().chain(&untimed) {
+            if a != b && fingerprints_match(*a, *b) {
+                copies.insert((*a.min(b), *a.max(b)));
+            }
+        }
+    }
+
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for index in 0..candidates.len() {
+        let is_copy = |other: &usize| copies.contains(&(index.min(*other), index.max(*other)));
+        match members.iter_mut().find(|files| files.iter().all(is_copy)) {
+            Some(files) => files.push(index),
+            None => members.push(vec![index]),
+        }
+    }
+    let mut duplicate_groups: Vec<DuplicateGroup> = members.into_iter()
+        .filter(|files| files.len() > 1)
+        .map(|mut files| {
+            files.sort_by(|a, b| compare_keepers(&candidates[*a], &candidates[*b], settings, quality));
+            DuplicateGroup { files }
+        })
+        .collect();
+    duplicate_groups.sort_by(|a, b| candidates[a.files[0]].path.cmp(&candidates[b.files[0]].path));
+    return duplicate_groups;
+}
+
+/// Orders the better file to keep first: meeting the quality settings, then preferred format,
+/// then higher bitrate, then more complete tags, then path.
+fn compare_keepers(a: &Candidate, b: &Candidate, settings: &DuplicateSettings, quality: &QualitySettings) -> Ordering {
+    let below_quality = |candidate: &Candidate| candidate.metadata.audio.as_ref()
+        .is_some_and(|audio| audio.quality_shortfall(quality).is_some());
+    let bitrate = |candidate: &Candidate| candidate.metadata.audio.as_ref().and_then(|audio| audio.bitrate);
+    let format_rank = |candidate: &Candidate| settings.format_preference.iter()
+        .position(|format| format.eq_ignore_ascii_case(&candidate.format))
+        .unwrap_or(settings.format_preference.len());
+    let tagged_fields = |candidate: &Candidate| candidate.metadata.display_fields().iter().filter(|(_, value)| !value.is_empty()).count();
+    return below_quality(a).cmp(&below_quality(b))
+        .then(format_rank(a).cmp(&format_rank(b)))
+        .then(bitrate(b).cmp(&bitrate(a)))
+        .then(tagged_fields(b).cmp(&tagged_fields(a)))
+        .then(a.path.cmp(&b.path));
+}
+
+/// Lower case words without punctuation, dropping qualifiers like "(Remastered 2009)".
+pub fn normalise_title(title: &str) -> String {
+    let mut kept = String::new();
+    let mut bracketed = String::new();
+    let mut depth = 0;
+    for character in title.to_lowercase().chars() {
+        match character {
+            '(' | '[' => {
+                depth += 1;
+                bracketed.push(' ');
+            },
+            ')' | ']' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    if !IGNORED_QUALIFIERS.iter().any(|qualifier| bracketed.contains(qualifier)) {
+                        kept.push_str(&bracketed);
+                    }
+                    bracketed.clear();
+                }
+            },
+            character if depth > 0 => bracketed.push(character),
+            character => kept.push(character),
+        }
+    }
+    kept.push_str(&bracketed);
+    return normalise_words(&kept);
+}
+
+/// Lower case words without punctuation or a leading "the", with "&" read as "and".
+pub fn normalise_artist(artist: &str) -> String {
+    let words = normalise_words(&artist.to_lowercase().replace('&', " and "));
+    return words.strip_prefix("the ").map(String::from).unwrap_or(words);
+}
+
+fn normalise_words(text: &str) -> String {
+    return text.chars()
+        .map(|character| if character.is_alphanumeric() { character } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::metadata::audio_properties::AudioProperties;
+
+    fn candidate(path: &str, title: &str, artist: &str, secs: u64, bitrate: u32) -> Candidate {
+        let format = path.rsplit('.').next().unwrap().to_string();
+        return Candidate {
+            path: PathBuf::from(path),
+            metadata: SongMetadata {
+                title: Some(title.to_string()),
+                artists: Some(vec![artist.to_string()]),
+                duration: Some(Duration::from_secs(secs)),
+                audio: Some(AudioProperties {
+                    codec: Some(format.to_uppercase()),
+                    lossless: format == "flac",
+                    bitrate: Some(bitrate),
+                    ..AudioProperties::default()
+                }),
+                ..SongMetadata::default()
+            },
+            format,
+            fingerprint: None,
+        };
+    }
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!("here comes the sun", normalise_title("Here Comes The Sun (2019 Remaster)"));
+        assert_eq!("help live", normalise_title("Help! (Live)"));
+        assert_eq!("beatles", normalise_artist("The Beatles"));
+        assert_eq!("simon and garfunkel", normalise_artist("Simon & Garfunkel"));
+    }
+
+    #[test]
+    fn test_find_duplicates_recommends_keeper() {
+        let candidates = [
+            candidate("/a/sun.mp3", "Here Comes the Sun", "The Beatles", 185, 320),
+            candidate("/b/sun.flac", "Here Comes The Sun (2019 Remaster)", "Beatles", 186, 900),
+            candidate("/c/sun.mp3", "Here Comes the Sun", "The Beatles", 185, 128),
+            candidate("/d/sun-live.mp3", "Here Comes the Sun", "The Beatles", 240, 320),
+            candidate("/e/other.mp3", "Something", "The Beatles", 182, 320),
+        ];
+        let groups = find_duplicates(&candidates, &DuplicateSettings::default(), &QualitySettings::default());
+
+        assert_eq!(1, groups.len());
+        assert_eq!(vec![1, 0, 2], groups[0].files);
+    }
+
+    #[test]
+    fn test_untimed_file_does_not_join_groups() {
+        let mut untimed = candidate("/c/song.mp3", "Song", "Artist", 0, 320);
+        untimed.metadata.duration = None;
+        let candidates = [
+            candidate("/a/song.mp3", "Song", "Artist", 180, 320),
+            candidate("/b/song-live.mp3", "Song", "Artist", 420, 320),
+            untimed,
+        ];
+        assert!(find_duplicates(&candidates, &DuplicateSettings::default(), &QualitySettings::default()).is_empty());
+    }
+
+    #[test]
+    fn test_groups_do_not_chain() {
+        let candidates = [
+            candidate("/a/song.mp3", "Song", "Artist", 180, 320),
+            candidate("/b/song.mp3", "Song", "Artist", 183, 320),
+            candidate("/c/song-live.mp3", "Song", "Artist", 186, 320),
+        ];
+        let settings = DuplicateSettings {
+            duration_tolerance_secs: 4,
+            ..DuplicateSettings::default()
+        };
+        let groups = find_duplicates(&candidates, &settings, &QualitySettings::default());
+        assert_eq!(1, groups.len());
+        assert_eq!(vec![0, 1], groups[0].files);
+    }
+
+    #[test]
+    fn test_untimed_file_is_fingerprint_compared() {
+        let fingerprint = || Some(Fingerprint {
+            duration: Duration::ZERO,
+            encoded: String::new(),
+            raw: (0..120u32).map(|i| i.wrapping_mul(2654435761)).collect(),
+        });
+        let mut untimed = candidate("/b/track01.mp3", "Track 1", "Unknown", 0, 320);
+        untimed.metadata.duration = None;
+        untimed.fingerprint = fingerprint();
+        let mut timed = candidate("/a/song.flac", "Song", "Artist", 200, 900);
+        timed.fingerprint = fingerprint();
+        let groups = find_duplicates(&[timed, untimed], &DuplicateSettings::default(), &QualitySettings::default());
+        assert_eq!(1, groups.len());
+        assert_eq!(vec![0, 1], groups[0].files);
+    }
+
+    #[test]
+    fn test_keeper_meets_quality() {
+        let candidates = [
+            candidate("/a/song.m4a", "Song", "Artist", 200, 128),
+            candidate("/b/song.mp3", "Song", "Artist", 200, 320),
+        ];
+        let preferring_format = find_duplicates(&candidates, &DuplicateSettings::default(), &QualitySettings::default());
+        assert_eq!(vec![0, 1], preferring_format[0].files);
+
+        let quality = QualitySettings { min_bitrate: Some(256), lossless_only: false };
+        let meeting_quality = find_duplicates(&candidates, &DuplicateSettings::default(), &quality);
+        assert_eq!(vec![1, 0], meeting_quality[0].files);
+    }
+}
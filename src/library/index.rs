@@ -0,0 +1,157 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::Local;
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::metadata::song_metadata::SongMetadata;
+use super::scan::{file_size_and_mtime, path_key};
+
+const INDEX_DIR_NAME: &str = "imd";
+const INDEX_FILE_NAME: &str = "library.sqlite3";
+
+/// A file's tags as last read into the index.
+pub struct IndexedFile {
+    pub path: PathBuf,
+    pub metadata: SongMetadata,
+}
+
+/// A SQLite database holding the `SongMetadata` of every indexed file, so the library can be
+/// queried without reading the files again.
+pub struct LibraryIndex {
+    connection: Mutex<Connection>,
+}
+
+impl LibraryIndex {
+    pub fn open(path: &Path) -> LibraryIndex {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|e| panic!("ERROR: Failed to create {:?}: {}", parent, e));
+        }
+        let connection = Connection::open(path)
+            .unwrap_or_else(|e| panic!("ERROR: Failed to open the library index {:?}: {}", path, e));
+        connection.execute_batch("
+            PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS files (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                modified_secs INTEGER NOT NULL,
+                indexed_at TEXT NOT NULL,
+                metadata TEXT NOT NULL
+            );
+        ").unwrap_or_else(|e| panic!("ERROR: Failed to set up the library index {:?}: {}", path, e));
+
+        return LibraryIndex {
+            connection: Mutex::new(connection),
+        };
+    }
+
+    /// Whether the file is indexed with its current size and modification time. Files indexed
+    /// before audio properties were read count as outdated.
+    pub fn is_current(&self, file_path: &Path) -> bool {
+        let Some((size, modified_secs)) = file_size_and_mtime(file_path) else {
+            return false;
+        };
+        let indexed: Option<(i64, i64)> = self.connection.lock().expect("ERROR: Library index lock poisoned")
+            .query_row(
+                "SELECT size, modified_secs FROM files WHERE path = ?1 AND json_extract(metadata, '$.audio') IS NOT NULL",
+                params![path_key(file_path)],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .unwrap_or_else(|e| panic!("ERROR: Failed to read the library index: {}", e));
+        return indexed == Some((size as i64, modified_secs));
+    }
+
+    pub fn update(&self, file_path: &Path, metadata: &SongMetadata) {
+        let (size, modified_secs) = file_size_and_mtime(file_path).unwrap_or_default();
+        let metadata = serde_json::to_string(metadata).expect("song metadata serializes to JSON");
+        let result = self.connection.lock().expect("ERROR: Library index lock poisoned").execute(
+            "INSERT OR REPLACE INTO files (path, size, modified_secs, indexed_at, metadata) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![path_key(file_path), size as i64, modified_secs, Local::now().to_rfc3339(), metadata],
+        );
+        if let Err(e) = result {
+            eprintln!("WARN: Failed to index {:?}: {}", file_path, e);
+        }
+    }
+
+    /// Removes the entries below `dir` whose file no longer exists, returning how many.
+    pub fn remove_missing(&self, dir: &Path) -> usize {
+        let dir = path_key(dir);
+        let missing: Vec<PathBuf> = self.files()
+            .into_iter()
+            .map(|file| file.path)
+            .filter(|path| path.starts_with(&dir) && !path.is_file())
+            .collect();
+        let connection = self.connection.lock().expect("ERROR: Library index lock poisoned");
+        for path in &missing {
+            if let Err(e) = connection.execute("DELETE FROM files WHERE path = ?1", params![path.to_string_lossy()]) {
+                eprintln!("WARN: Failed to remove {:?} from the library index: {}", path, e);
+            }
+        }
+        return missing.len();
+    }
+
+    /// Every indexed file, ordered by path.
+    pub fn files(&self) -> Vec<IndexedFile> {
+        let connection = self.connection.lock().expect("ERROR: Library index lock poisoned");
+        let mut statement = connection.prepare("SELECT path, metadata FROM files ORDER BY path")
+            .unwrap_or_else(|e| panic!("ERROR: Failed to read the library index: {}", e));
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .unwrap_or_else(|e| panic!("ERROR: Failed to read the library index: {}", e));
+
+        let mut files = Vec::new();
+        for row in rows {
+            let (path, metadata) = row.unwrap_or_else(|e| panic!("ERROR: Failed to read the library index: {}", e));
+            match serde_json::from_str::<SongMetadata>(&metadata) {
+                Ok(metadata) => files.push(IndexedFile { path: PathBuf::from(path), metadata }),
+                Err(e) => eprintln!("WARN: Corrupt library index entry for {:?}, skipping: {}", path, e),
+            }
+        }
+        return files;
+    }
+}
+
+pub fn default_index_path() -> PathBuf {
+    return dirs::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(INDEX_DIR_NAME)
+        .join(INDEX_FILE_NAME);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::audio_properties::AudioProperties;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_index_round_trip() {
+        let audio_file = TestAudioFile::wav("library-index");
+        let index = LibraryIndex::open(&audio_file.dir().join(INDEX_FILE_NAME));
+        assert!(!index.is_current(audio_file.path()));
+
+        let metadata = SongMetadata {
+            title: Some("Title".to_string()),
+            artists: Some(vec!["First".to_string(), "Second".to_string()]),
+            year: Some(1969),
+            ..SongMetadata::default()
+        };
+        // Entries without audio properties are from before they were read.
+        index.update(audio_file.path(), &metadata);
+        assert!(!index.is_current(audio_file.path()));
+
+        let metadata = SongMetadata {
+            audio: Some(AudioProperties { codec: Some("WAV".to_string()), lossless: true, ..AudioProperties::default() }),
+            ..metadata
+        };
+        index.update(audio_file.path(), &metadata);
+        assert!(index.is_current(audio_file.path()));
+        let files = index.files();
+        assert_eq!(1, files.len());
+        assert_eq!(metadata.display_fields(), files[0].metadata.display_fields());
+
+        fs::remove_file(audio_file.path()).unwrap();
+        assert_eq!(1, index.remove_missing(audio_file.dir()));
+        assert!(index.files().is_empty());
+    }
+}
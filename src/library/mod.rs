@@ -0,0 +1,7 @@
+pub mod duplicates;
+pub mod index;
+pub mod progress;
+pub mod query;
+pub mod scan;
+pub mod settle;
+pub mod worker_pool;
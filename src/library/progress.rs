@@ -0,0 +1,82 @@
+use std::sync::Mutex;
+use std::time::Instant;
+use indicatif::{ProgressBar, ProgressStyle};
+
+const PROGRESS_TEMPLATE: &str = "{bar:40} {pos}/{len} files  {per_sec}  ETA {eta}  {msg}";
+
+/// A progress bar for a batch run, counting how many files ended in each state, e.g. written,
+/// skipped or failed. Hidden when disabled or when not drawing to a terminal.
+pub struct BatchProgress {
+    bar: ProgressBar,
+    started: Instant,
+    counts: Mutex<Vec<(&'static str, usize)>>,
+}
+
+impl BatchProgress {
+    pub fn new(total: usize, enabled: bool) -> BatchProgress {
+        let bar = if enabled { ProgressBar::new(total as u64) } else { ProgressBar::hidden() };
+        bar.set_style(ProgressStyle::with_template(PROGRESS_TEMPLATE).expect("hardcoded template is valid"));
+        BatchProgress {
+            bar,
+            started: Instant::now(),
+            counts: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Counts a finished file under `state`.
+    pub fn finish_file(&self, state: &'static str) {
+        let message = {
+            let mut counts = self.counts.lock().expect("ERROR: Progress counts poisoned");
+            match counts.iter_mut().find(|(counted_state, _)| *counted_state == state) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((state, 1)),
+            }
+            format_counts(&counts)
+        };
+        self.bar.set_message(message);
+        self.bar.inc(1);
+    }
+
+    /// Prints a line above the bar.
+    pub fn println(&self, line: &str) {
+        if self.bar.is_hidden() {
+            println!("{}", line);
+        } else {
+            self.bar.println(line);
+        }
+    }
+
+    /// Removes the bar and prints the totals.
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+        let counts = self.counts.lock().expect("ERROR: Progress counts poisoned");
+        println!(
+            "Processed {} file(s) in {:.1}s: {}",
+            self.bar.position(),
+            self.started.elapsed().as_secs_f64(),
+            format_counts(&counts),
+        );
+    }
+}
+
+fn format_counts(counts: &[(&'static str, usize)]) -> String {
+    return counts.iter()
+        .map(|(state, count)| format!("{} {}", count, state))
+        .collect::<Vec<String>>()
+        .join(", ");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_in_first_seen_order() {
+        let progress = BatchProgress::new(4, false);
+        for state in ["written", "failed", "written", "skipped"] {
+            progress.finish_file(state);
+        }
+        assert_eq!("2 written, 1 failed, 1 skipped", format_counts(&progress.counts.lock().unwrap()));
+        assert_eq!(4, progress.bar.position());
+    }
+}
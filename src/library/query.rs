@@ -0,0 +1,295 @@
+use std::cmp::Ordering;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use super::index::IndexedFile;
+
+/// Fields a query can filter, sort and print. `missing` counts the `ESSENTIAL_FIELDS` a file lacks.
+pub const QUERY_FIELDS: [&str; 35] = [
+    "path", "title", "artist", "album", "album_artist", "composer", "genre", "track", "disc", "year",
+    "comment", "duration", "codec", "bitrate", "sample_rate", "bit_depth", "channels", "total_tracks", "total_discs", "compilation", "release_date", "isrc", "label",
+    "copyright", "catalogue_number", "barcode", "sort_title", "sort_artist", "sort_album_artist", "bpm",
+    "initial_key", "lyrics", "track_gain", "album_gain", "missing",
+];
+/// Fields a well tagged file is expected to have.
+const ESSENTIAL_FIELDS: [&str; 8] = ["title", "artist", "album", "album_artist", "track", "total_tracks", "year", "genre"];
+/// Longest first, so `<=` isn't read as `<` followed by a value starting with `=`.
+const OPERATORS: [(&str, Operator); 8] = [
+    ("=~", Operator::Matches),
+    ("!~", Operator::NotMatches),
+    ("!=", Operator::NotEquals),
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("=", Operator::Equals),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        return match name {
+            "table" => Some(OutputFormat::Table),
+            "csv" => Some(OutputFormat::Csv),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Equals,
+    NotEquals,
+    Matches,
+    NotMatches,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug)]
+enum Filter {
+    Compare {
+        field: String,
+        operator: Operator,
+        value: String,
+        regex: Option<Regex>,
+    },
+    Missing(String),
+    Present(String),
+}
+
+/// Filters that must all match, e.g. `artist=~Beatles year<1970 missing:album_artist`. Text is
+/// compared case-insensitively, numbers numerically. A file matches a comparison when any of its
+/// values does, e.g. one of several artists, and negated comparisons also match missing fields.
+#[derive(Debug)]
+pub struct Query {
+    filters: Vec<Filter>,
+}
+
+impl Query {
+    /// Parses the filters, each argument may hold several separated by spaces. Double quotes keep
+    /// spaces in a value, e.g. `'title="Let It Be"'`.
+    pub fn parse(arguments: &[String]) -> Result<Query, String> {
+        let mut filters = Vec::new();
+        for term in arguments.iter().flat_map(|argument| split_terms(argument)) {
+            filters.push(parse_filter(&term)?);
+        }
+        return Ok(Query { filters });
+    }
+
+    pub fn matches(&self, file: &IndexedFile) -> bool {
+        return self.filters.iter().all(|filter| filter_matches(filter, file));
+    }
+}
+
+/// The file's values for a query field, empty when the field is not set.
+pub fn field_values(file: &IndexedFile, field: &str) -> Vec<String> {
+    fn text<T: ToString>(value: &Option<T>) -> Vec<String> {
+        return value.iter().map(|value| value.to_string()).collect();
+    }
+
+    let metadata = &file.metadata;
+    return match field {
+        "path" => vec![file.path.to_string_lossy().to_string()],
+        "title" => text(&metadata.title),
+        "artist" => metadata.artists.clone().unwrap_or_default(),
+        "album" => text(&metadata.album),
+        "album_artist" => text(&metadata.album_artist),
+        "composer" => text(&metadata.composer),
+        "genre" => metadata.genres.clone().unwrap_or_default(),
+        "track" => text(&metadata.track_number),
+        "disc" => text(&metadata.disc_number),
+        "year" => text(&metadata.year),
+        "comment" => text(&metadata.comment),
+        "duration" => text(&metadata.duration.map(|duration| duration.as_secs())),
+        "codec" => text(&metadata.audio.as_ref().and_then(|audio| audio.codec.clone())),
+        "bitrate" => text(&metadata.audio.as_ref().and_then(|audio| audio.bitrate)),
+        "sample_rate" => text(&metadata.audio.as_ref().and_then(|audio| audio.sample_rate)),
+        "bit_depth" => text(&metadata.audio.as_ref().and_then(|audio| audio.bit_depth)),
+        "channels" => text(&metadata.audio.as_ref().and_then(|audio| audio.channels)),
+        "total_tracks" => text(&metadata.total_tracks),
+        "total_discs" => text(&metadata.total_discs),
+        "compilation" => text(&metadata.is_compilation),
+        "release_date" => text(&metadata.release_date),
+        "isrc" => text(&metadata.isrc),
+        "label" => text(&metadata.label),
+        "copyright" => text(&metadata.copyright),
+        "catalogue_number" => text(&metadata.catalogue_number),
+        "barcode" => text(&metadata.barcode),
+        "sort_title" => text(&metadata.sort_title),
+        "sort_artist" => text(&metadata.sort_artist),
+        "sort_album_artist" => text(&metadata.sort_album_artist),
+        "bpm" => text(&metadata.bpm),
+        "initial_key" => text(&metadata.initial_key),
+        "lyrics" => text(&metadata.lyrics),
+        "track_gain" => text(&metadata.replay_gain.map(|replay_gain| format!("{:.2}", replay_gain.track_gain))),
+        "album_gain" => text(&metadata.replay_gain.and_then(|replay_gain| replay_gain.album_gain).map(|gain| format!("{:.2}", gain))),
+        "missing" => vec![missing_count(file).to_string()],
+        _ => Vec::new(),
+    };
+}
+
+/// The values joined for display in one cell.
+pub fn field_text(file: &IndexedFile, field: &str) -> String {
+    return field_values(file, field).join("; ");
+}
+
+/// Orders by the field's first value, numerically when both are numbers. Missing values sort last.
+pub fn compare_by_field(a: &IndexedFile, b: &IndexedFile, field: &str) -> Ordering {
+    return match (field_values(a, field).first(), field_values(b, field).first()) {
+        (Some(a), Some(b)) => compare_values(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+}
+
+fn missing_count(file: &IndexedFile) -> usize {
+    return ESSENTIAL_FIELDS.iter().filter(|field| field_values(file, field).is_empty()).count();
+}
+
+fn split_terms(argument: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut term = String::new();
+    let mut quoted = false;
+    for character in argument.chars() {
+        match character {
+            '"' => quoted = !quoted,
+            character if character.is_whitespace() && !quoted => {
+                if !term.is_empty() {
+                    terms.push(std::mem::take(&mut term));
+                }
+            },
+            character => term.push(character),
+        }
+    }
+    if !term.is_empty() {
+        terms.push(term);
+    }
+    return terms;
+}
+
+fn parse_filter(term: &str) -> Result<Filter, String> {
+    if let Some(field) = term.strip_prefix("missing:") {
+        return Ok(Filter::Missing(known_field(field)?));
+    }
+    if let Some(field) = term.strip_prefix("has:") {
+        return Ok(Filter::Present(known_field(field)?));
+    }
+
+    let field_length = term.find(|character: char| !(character.is_ascii_alphanumeric() || character == '_')).unwrap_or(term.len());
+    let (field, rest) = term.split_at(field_length);
+    let (symbol, operator) = OPERATORS.iter()
+        .find(|(symbol, _)| rest.starts_with(symbol))
+        .ok_or_else(|| format!("Invalid filter {:?}, expected e.g. artist=Name, year<1970 or missing:album", term))?;
+    let value = rest[symbol.len()..].to_string();
+    let regex = match operator {
+        Operator::Matches | Operator::NotMatches => Some(RegexBuilder::new(&value)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid regular expression in {:?}: {}", term, e))?),
+        _ => None,
+    };
+    return Ok(Filter::Compare {
+        field: known_field(field)?,
+        operator: *operator,
+        value,
+        regex,
+    });
+}
+
+fn known_field(field: &str) -> Result<String, String> {
+    if QUERY_FIELDS.contains(&field) {
+        return Ok(field.to_string());
+    }
+    return Err(format!("Unknown field {:?}, known fields are {}", field, QUERY_FIELDS.join(", ")));
+}
+
+fn filter_matches(filter: &Filter, file: &IndexedFile) -> bool {
+    return match filter {
+        Filter::Missing(field) => field_values(file, field).is_empty(),
+        Filter::Present(field) => !field_values(file, field).is_empty(),
+        Filter::Compare { field, operator, value, regex } => {
+            let values = field_values(file, field);
+            let any = |predicate: &dyn Fn(&str) -> bool| values.iter().any(|actual| predicate(actual));
+            match operator {
+                Operator::Equals => any(&|actual| compare_values(actual, value) == Ordering::Equal),
+                Operator::NotEquals => !any(&|actual| compare_values(actual, value) == Ordering::Equal),
+                Operator::Matches => any(&|actual| regex.as_ref().is_some_and(|regex| regex.is_match(actual))),
+                Operator::NotMatches => !any(&|actual| regex.as_ref().is_some_and(|regex| regex.is_match(actual))),
+                Operator::Less => any(&|actual| compare_values(actual, value) == Ordering::Less),
+                Operator::LessOrEqual => any(&|actual| compare_values(actual, value) != Ordering::Greater),
+                Operator::Greater => any(&|actual| compare_values(actual, value) == Ordering::Greater),
+                Operator::GreaterOrEqual => any(&|actual| compare_values(actual, value) != Ordering::Less),
+            }
+        },
+    };
+}
+
+fn compare_values(a: &str, b: &str) -> Ordering {
+    return match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::metadata::song_metadata::SongMetadata;
+
+    fn file(title: &str, artists: &[&str], year: Option<u16>, album_artist: Option<&str>) -> IndexedFile {
+        return IndexedFile {
+            path: PathBuf::from(format!("/music/{}.mp3", title)),
+            metadata: SongMetadata {
+                title: Some(title.to_string()),
+                artists: Some(artists.iter().map(|artist| artist.to_string()).collect()),
+                year,
+                album_artist: album_artist.map(String::from),
+                ..SongMetadata::default()
+            },
+        };
+    }
+
+    fn matching(query: &str, files: &[IndexedFile]) -> Vec<String> {
+        let query = Query::parse(&[query.to_string()]).unwrap();
+        return files.iter()
+            .filter(|file| query.matches(file))
+            .map(|file| field_text(file, "title"))
+            .collect();
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let files = [
+            file("Help", &["The Beatles"], Some(1965), None),
+            file("Imagine", &["John Lennon"], Some(1971), Some("John Lennon")),
+            file("Ebony and Ivory", &["Paul McCartney", "Stevie Wonder"], Some(1982), Some("Paul McCartney")),
+            file("Untitled", &["Unknown"], None, None),
+        ];
+
+        assert_eq!(vec!["Help"], matching("artist=~beatles year<1970 missing:album_artist", &files));
+        assert_eq!(vec!["Ebony and Ivory"], matching("artist=\"stevie wonder\"", &files));
+        assert_eq!(vec!["Imagine", "Ebony and Ivory"], matching("year>=1971", &files));
+        assert_eq!(vec!["Help", "Imagine", "Untitled"], matching("year!=1982", &files));
+        assert_eq!(vec!["Untitled"], matching("missing>=6", &files));
+        assert_eq!(vec!["Help", "Imagine", "Ebony and Ivory"], matching("has:year artist!~^unknown$", &files));
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        assert!(Query::parse(&["mood=calm".to_string()]).unwrap_err().starts_with("Unknown field \"mood\""));
+        assert!(Query::parse(&["artist".to_string()]).unwrap_err().starts_with("Invalid filter"));
+        assert!(Query::parse(&["title=~(".to_string()]).unwrap_err().starts_with("Invalid regular expression"));
+    }
+}
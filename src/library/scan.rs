@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Extensions of the audio formats imd can read tags from.
+const AUDIO_EXTENSIONS: [&str; 16] = [
+    "aac", "aif", "aifc", "aiff", "ape", "flac", "m4a", "m4b", "mp3", "mp4", "mpc", "oga", "ogg", "opus", "wav", "wv",
+];
+
+pub fn is_audio_file(path: &Path) -> bool {
+    return path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+}
+
+/// Every audio file below `dir`, sorted by path. Unreadable folders are reported and skipped.
+pub fn find_audio_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut pending: Vec<PathBuf> = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("WARN: Failed to read {:?}: {}", dir, e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if is_audio_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    return files;
+}
+
+/// The file's size in bytes and modification time in seconds since the epoch.
+pub fn file_size_and_mtime(file_path: &Path) -> Option<(u64, i64)> {
+    let metadata = fs::metadata(file_path).ok()?;
+    let modified_secs = metadata.modified().ok()?
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .unwrap_or_default();
+    return Some((metadata.len(), modified_secs));
+}
+
+/// The canonical path as stored in the library databases.
+pub fn path_key(file_path: &Path) -> String {
+    return fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf()).to_string_lossy().to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_find_audio_files() {
+        let audio_file = TestAudioFile::wav("scan");
+        let nested = audio_file.dir().join("disc 2");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("01.FLAC"), b"").unwrap();
+        fs::write(nested.join("cover.jpg"), b"").unwrap();
+
+        assert_eq!(vec![nested.join("01.FLAC"), audio_file.path().to_path_buf()], find_audio_files(audio_file.dir()));
+    }
+}
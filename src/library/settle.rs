@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use super::scan::file_size_and_mtime;
+
+/// Files that are still being written, e.g. by a download or a copy, and when they were last seen
+/// changing. A file has settled once neither events nor its size or modification time changed
+/// for the settle time.
+pub struct SettlingFiles {
+    settle_time: Duration,
+    files: HashMap<PathBuf, (Instant, Option<(u64, i64)>)>,
+}
+
+impl SettlingFiles {
+    pub fn new(settle_time: Duration) -> SettlingFiles {
+        SettlingFiles {
+            settle_time,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Notes that the file changed at `now`.
+    pub fn touch(&mut self, path: PathBuf, now: Instant) {
+        let size_and_mtime = file_size_and_mtime(&path);
+        self.files.insert(path, (now, size_and_mtime));
+    }
+
+    /// Removes and returns the files that have settled by `now`, sorted by path. Files that
+    /// changed without an event wait another settle time, files that are gone are dropped.
+    pub fn take_settled(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut settled: Vec<PathBuf> = Vec::new();
+        self.files.retain(|path, (last_change, last_size_and_mtime)| {
+            if now.duration_since(*last_change) < self.settle_time {
+                return true;
+            }
+            let size_and_mtime = file_size_and_mtime(path);
+            if size_and_mtime.is_none() {
+                return false;
+            }
+            if size_and_mtime != *last_size_and_mtime {
+                *last_change = now;
+                *last_size_and_mtime = size_and_mtime;
+                return true;
+            }
+            settled.push(path.clone());
+            return false;
+        });
+        settled.sort();
+        return settled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_files_settle_once_unchanged() {
+        let audio_file = TestAudioFile::wav("settle");
+        let settle_time = Duration::from_secs(5);
+        let start = Instant::now();
+        let mut settling = SettlingFiles::new(settle_time);
+        settling.touch(audio_file.path().to_path_buf(), start);
+        assert!(settling.take_settled(start + Duration::from_secs(1)).is_empty());
+
+        // Still being written without a new event.
+        OpenOptions::new().append(true).open(audio_file.path()).unwrap().write_all(&[0; 16]).unwrap();
+        assert!(settling.take_settled(start + settle_time).is_empty());
+
+        assert_eq!(vec![audio_file.path().to_path_buf()], settling.take_settled(start + settle_time * 2));
+        assert!(settling.take_settled(start + settle_time * 3).is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+
+/// The number of worker threads to use, `configured` or one per CPU core when it is 0.
+pub fn worker_count(configured: usize) -> usize {
+    if configured > 0 {
+        return configured;
+    }
+    return thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+}
+
+/// Runs `job` on every item with up to `workers` threads and returns the results in item order.
+/// A job that panics, e.g. on an unreadable file, only fails its own item with the panic message.
+/// `finished` is called from the worker as soon as an item is done, e.g. to report progress.
+pub fn run_parallel<T, R, F, D>(items: &[T], workers: usize, job: F, finished: D) -> Vec<Result<R, String>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+    D: Fn(&T, &Result<R, String>) + Sync,
+{
+    let next_item = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<R, String>>>> = Mutex::new((0..items.len()).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next_item.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = catch_panic(|| job(item));
+                finished(item, &result);
+                results.lock().expect("ERROR: Worker results poisoned")[index] = Some(result);
+            });
+        }
+    });
+
+    return results.into_inner()
+        .expect("ERROR: Worker results poisoned")
+        .into_iter()
+        .map(|result| result.expect("every item is processed once the workers finish"))
+        .collect();
+}
+
+thread_local! {
+    /// Whether panics on this thread are caught and returned, so the hook shouldn't print them.
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+static INSTALL_PANIC_HOOK: Once = Once::new();
+
+/// Runs `job` on this thread, returning the panic message instead of printing it when it panics.
+/// The panic hook is process wide, so it is installed once and only stays quiet on threads that
+/// are inside `catch_panic`, other threads keep printing their panics.
+pub fn catch_panic<R, F: FnOnce() -> R>(job: F) -> Result<R, String> {
+    INSTALL_PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING_PANICS.with(Cell::get) {
+                default_hook(info);
+            }
+        }));
+    });
+    let was_catching = CATCHING_PANICS.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(job)).map_err(panic_message);
+    CATCHING_PANICS.with(|catching| catching.set(was_catching));
+    return result;
+}
+
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    return "unknown error".to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_keep_item_order_and_failures_stay_isolated() {
+        let items: Vec<u32> = (0..20).collect();
+        let finished = AtomicUsize::new(0);
+        let results = run_parallel(
+            &items,
+            4,
+            |item| {
+                if *item == 7 {
+                    panic!("ERROR: Item {} failed", item);
+                }
+                item * 2
+            },
+            |_, _| {
+                finished.fetch_add(1, Ordering::Relaxed);
+            },
+        );
+
+        assert_eq!(20, results.len());
+        assert_eq!(20, finished.into_inner());
+        assert_eq!(Err("ERROR: Item 7 failed".to_string()), results[7]);
+        for (item, result) in items.iter().zip(&results).filter(|(item, _)| **item != 7) {
+            assert_eq!(Ok(item * 2), *result);
+        }
+    }
+
+    #[test]
+    fn test_nested_catch_panic_keeps_catching() {
+        let outer = catch_panic(|| {
+            let inner = catch_panic(|| panic!("ERROR: Inner"));
+            assert_eq!(Err("ERROR: Inner".to_string()), inner);
+            assert!(CATCHING_PANICS.with(Cell::get));
+            panic!("ERROR: Outer");
+        });
+        assert_eq!(Err::<(), String>("ERROR: Outer".to_string()), outer);
+        assert!(!CATCHING_PANICS.with(Cell::get));
+    }
+}
@@ -1,37 +1,51 @@
+#![allow(clippy::needless_return)]
+
 mod app_config;
+mod audio;
+mod commands;
+mod fingerprint;
+mod history;
+mod library;
 mod metadata;
-use std::path::Path;
-use app_config::AppConfig;
-use metadata::song_metadata::SongMetadata;
-use metadata::metadata_fixer;
+mod organise;
+mod review;
+mod settings;
+#[cfg(test)]
+mod test_support;
+use app_config::{AppCommand, AppConfig};
 
 fn main() {
     let command_options = AppConfig::from_command_args();
+    if let AppCommand::Completions(_) | AppCommand::Config | AppCommand::Query(_) = command_options.command {
+        // The completion script, config and query results go to stdout, nothing else may be printed.
+        commands::run(&command_options);
+        return;
+    }
     print_title();
     print_command_options(&command_options);
 
-    let path: &Path = Path::new(&command_options.path);
-    if !path.is_file() {
-		panic!("ERROR: Provided path is not a file!");
-	}
-
-    let song_metadata: SongMetadata = SongMetadata::read_metadata_from_audio_file(&command_options.path);
-    let fixed_metadata: SongMetadata = metadata_fixer::get_fixed_metadata(&song_metadata);
-    println!("Fixed metadata:");
-    fixed_metadata.pretty_print();
-
-
-    if command_options.write {
-        println!("Writing metadata to file...");
-        fixed_metadata.write_metadata_to_audio_file(&command_options.path);
-    }
+    commands::run(&command_options);
     println!("Done");
 }
 
 fn print_command_options(command_options: &AppConfig) {
-    println!("File name: {:?}", command_options.path);
-    println!("Debug: {:?}", command_options.debug);
-    println!("Write: {:?}", command_options.write);
+    match &command_options.command {
+        AppCommand::Match { path, organise } => {
+            println!("File name: {:?}", path);
+            if let Some(organise) = organise {
+                println!("Organise into: {:?}", organise.destination);
+            }
+            println!("Write: {:?}", command_options.settings.general.write);
+        },
+        AppCommand::Show { path } | AppCommand::Diff { path } | AppCommand::Organise { path, .. } | AppCommand::Review { path } => {
+            println!("File name: {:?}", path);
+        },
+        _ => {},
+    }
+    println!("Debug: {:?}", command_options.settings.general.debug);
+    if command_options.settings.general.debug {
+        println!("Effective config:\n{}", toml::to_string(&command_options.settings).expect("settings serialize to TOML"));
+    }
 }
 
 fn print_title() {
@@ -41,7 +55,6 @@ fn print_title() {
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
     #[test]
     fn tests_work() {
         assert_eq!(2 + 2, 4);
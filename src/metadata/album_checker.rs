@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::settings::PathInferenceSettings;
+use super::album_matcher::AlbumGroup;
+use super::path_inference::infer_path_fields;
+use super::song_metadata::SongMetadata;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    MissingTrackNumber,
+    DuplicateTrackNumber,
+    MixedAlbumArtist,
+    WrongTotalTracks,
+    MixedYear,
+}
+
+impl IssueKind {
+    pub fn description(&self) -> &'static str {
+        return match self {
+            IssueKind::MissingTrackNumber => "Missing track number",
+            IssueKind::DuplicateTrackNumber => "Duplicate track number",
+            IssueKind::MixedAlbumArtist => "Mixed album artist",
+            IssueKind::WrongTotalTracks => "Wrong total tracks",
+            IssueKind::MixedYear => "Mixed year",
+        };
+    }
+}
+
+/// A change that resolves an issue without any lookup.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldFix {
+    TrackNumber(u16),
+    AlbumArtist(String),
+    TotalTracks(u16),
+    Year(u16),
+}
+
+impl FieldFix {
+    pub fn apply(&self, song_metadata: &mut SongMetadata) {
+        match self {
+            FieldFix::TrackNumber(track_number) => song_metadata.track_number = Some(*track_number),
+            FieldFix::AlbumArtist(album_artist) => song_metadata.album_artist = Some(album_artist.clone()),
+            FieldFix::TotalTracks(total_tracks) => song_metadata.total_tracks = Some(*total_tracks),
+            FieldFix::Year(year) => song_metadata.year = Some(*year),
+        }
+    }
+}
+
+/// An inconsistency within an album. `fixes` holds the index of each file to change and how, and
+/// is empty when the issue needs a human.
+#[derive(Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub details: String,
+    pub fixes: Vec<(usize, FieldFix)>,
+}
+
+/// Checks the tags of an album's files against each other.
+pub fn check_album(group: &AlbumGroup, path_inference: &PathInferenceSettings) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+    issues.extend(check_missing_track_numbers(group, path_inference));
+    issues.extend(check_duplicate_track_numbers(group));
+    issues.extend(check_consistent_value(group, IssueKind::MixedAlbumArtist, |song| song.album_artist.clone(), FieldFix::AlbumArtist));
+    issues.extend(check_consistent_value(group, IssueKind::MixedYear, |song| song.year, FieldFix::Year));
+    issues.extend(check_total_tracks(group));
+    return issues;
+}
+
+/// Missing track numbers are taken from the file name when path inference is enabled and a path
+/// pattern finds one there, or from the file order when no file of the album has a track number at all. A guess that is
+/// already on another file of the disc, or that two files share, is left to a human.
+fn check_missing_track_numbers(group: &AlbumGroup, path_inference: &PathInferenceSettings) -> Option<Issue> {
+    let missing: Vec<usize> = (0..group.files.len()).filter(|index| group.files[*index].1.track_number.is_none()).collect();
+    if missing.is_empty() {
+        return None;
+    }
+
+    let numbered_by_order = missing.len() == group.files.len();
+    let guesses: Vec<(usize, u16, u16)> = missing.iter()
+        .filter_map(|index| {
+            let path = &group.files[*index].0;
+            let inferred = path_inference.enabled
+                .then(|| infer_path_fields(path, path_inference))
+                .flatten()
+                .and_then(|fields| fields.track_number);
+            let track_number = inferred.or(numbered_by_order.then_some(*index as u16 + 1));
+            let disc = group.files[*index].1.disc_number.unwrap_or(1);
+            track_number.map(|track_number| (*index, disc, track_number))
+        })
+        .collect();
+    let mut taken: BTreeMap<(u16, u16), usize> = BTreeMap::new();
+    for (_, song_metadata) in &group.files {
+        if let Some(track_number) = song_metadata.track_number {
+            *taken.entry((song_metadata.disc_number.unwrap_or(1), track_number)).or_insert(0) += 1;
+        }
+    }
+    for (_, disc, track_number) in &guesses {
+        *taken.entry((*disc, *track_number)).or_insert(0) += 1;
+    }
+    let fixes: Vec<(usize, FieldFix)> = guesses.into_iter()
+        .filter(|(_, disc, track_number)| taken[&(*disc, *track_number)] == 1)
+        .map(|(index, _, track_number)| (index, FieldFix::TrackNumber(track_number)))
+        .collect();
+    return Some(Issue {
+        kind: IssueKind::MissingTrackNumber,
+        details: file_names(group, &missing),
+        fixes,
+    });
+}
+
+fn check_duplicate_track_numbers(group: &AlbumGroup) -> Vec<Issue> {
+    let mut positions: BTreeMap<(u16, u16), Vec<usize>> = BTreeMap::new();
+    for (index, (_, song_metadata)) in group.files.iter().enumerate() {
+        if let Some(track_number) = song_metadata.track_number {
+            positions.entry((song_metadata.disc_number.unwrap_or(1), track_number)).or_default().push(index);
+        }
+    }
+    return positions.into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|((disc, track), files)| Issue {
+            kind: IssueKind::DuplicateTrackNumber,
+            details: format!("{}-{:02} on {}", disc, track, file_names(group, &files)),
+            fixes: Vec::new(),
+        })
+        .collect();
+}
+
+/// A field every track of an album should share. Files with a different or missing value get the
+/// most common one, unless two values are equally common.
+fn check_consistent_value<T, F>(group: &AlbumGroup, kind: IssueKind, value_of: F, fix: fn(T) -> FieldFix) -> Option<Issue>
+where
+    T: Clone + Ord + std::fmt::Debug,
+    F: Fn(&SongMetadata) -> Option<T>,
+{
+    let values: Vec<Option<T>> = group.files.iter().map(|(_, song_metadata)| value_of(song_metadata)).collect();
+    let mut counts: BTreeMap<&T, usize> = BTreeMap::new();
+    for value in values.iter().flatten() {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    let missing = values.iter().filter(|value| value.is_none()).count();
+    if counts.is_empty() || (counts.len() == 1 && missing == 0) {
+        return None;
+    }
+
+    let highest = *counts.values().max().unwrap();
+    let mut leaders = counts.iter().filter(|(_, count)| **count == highest).map(|(value, _)| (*value).clone());
+    let most_common = leaders.next().unwrap();
+    // With a tie there is no value to prefer, so a human has to pick one.
+    let tied = leaders.next().is_some();
+    let mut summary: Vec<String> = counts.iter().map(|(value, count)| format!("{:?} ({})", value, count)).collect();
+    if missing > 0 {
+        summary.push(format!("missing ({})", missing));
+    }
+    let fixes = if tied {
+        Vec::new()
+    } else {
+        values.iter().enumerate()
+            .filter(|(_, value)| value.as_ref() != Some(&most_common))
+            .map(|(index, _)| (index, fix(most_common.clone())))
+            .collect()
+    };
+    return Some(Issue {
+        kind,
+        details: summary.join(", "),
+        fixes,
+    });
+}
+
+/// Every track of a disc should carry the same total, and it can't be lower than the highest
+/// track number or the number of files on the disc.
+fn check_total_tracks(group: &AlbumGroup) -> Vec<Issue> {
+    let mut discs: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+    for (index, (_, song_metadata)) in group.files.iter().enumerate() {
+        discs.entry(song_metadata.disc_number.unwrap_or(1)).or_default().push(index);
+    }
+
+    let mut issues: Vec<Issue> = Vec::new();
+    for (disc, files) in discs {
+        let songs: Vec<&SongMetadata> = files.iter().map(|index| &group.files[*index].1).collect();
+        let minimum = songs.iter().filter_map(|song| song.track_number).max().unwrap_or(0).max(files.len() as u16);
+        let mut totals: Vec<u16> = songs.iter().filter_map(|song| song.total_tracks).collect();
+        totals.sort();
+        totals.dedup();
+        let all_set = songs.iter().all(|song| song.total_tracks.is_some());
+        if all_set && totals.len() == 1 && totals[0] >= minimum {
+            continue;
+        }
+
+        let expected = totals.iter().copied().filter(|total| *total >= minimum).max().unwrap_or(minimum);
+        let found = if totals.is_empty() { "none".to_string() } else { format!("{:?}", totals) };
+        issues.push(Issue {
+            kind: IssueKind::WrongTotalTracks,
+            details: format!("disc {} has {}, expected {}", disc, found, expected),
+            fixes: files.iter().copied()
+                .filter(|index| group.files[*index].1.total_tracks != Some(expected))
+                .map(|index| (index, FieldFix::TotalTracks(expected)))
+                .collect(),
+        });
+    }
+    return issues;
+}
+
+fn file_names(group: &AlbumGroup, files: &[usize]) -> String {
+    return files.iter()
+        .map(|index| group.files[*index].0.file_name().map(Path::new).unwrap_or(Path::new("")).display().to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn song(track_number: Option<u16>, album_artist: &str, year: u16, total_tracks: Option<u16>) -> SongMetadata {
+        return SongMetadata {
+            track_number,
+            album_artist: Some(album_artist.to_string()),
+            year: Some(year),
+            total_tracks,
+            ..SongMetadata::default()
+        };
+    }
+
+    #[test]
+    fn test_check_album() {
+        let group = AlbumGroup {
+            files: vec![
+                (PathBuf::from("/music/album/01 - One.mp3"), song(Some(1), "Artist", 2001, Some(3))),
+                (PathBuf::from("/music/album/02 - Two.mp3"), song(None, "Artist", 2001, Some(3))),
+                (PathBuf::from("/music/album/03 - Three.mp3"), song(Some(3), "Artist feat. Guest", 2002, Some(3))),
+                (PathBuf::from("/music/album/04 - Bonus.mp3"), song(Some(3), "Artist", 2001, None)),
+            ],
+        };
+        let issues = check_album(&group, &PathInferenceSettings::default());
+        let summary: Vec<(IssueKind, &Vec<(usize, FieldFix)>)> = issues.iter().map(|issue| (issue.kind, &issue.fixes)).collect();
+        assert_eq!(vec![
+            (IssueKind::MissingTrackNumber, &vec![(1, FieldFix::TrackNumber(2))]),
+            (IssueKind::DuplicateTrackNumber, &vec![]),
+            (IssueKind::MixedAlbumArtist, &vec![(2, FieldFix::AlbumArtist("Artist".to_string()))]),
+            (IssueKind::MixedYear, &vec![(2, FieldFix::Year(2001))]),
+            (IssueKind::WrongTotalTracks, &(0..4).map(|index| (index, FieldFix::TotalTracks(4))).collect()),
+        ], summary);
+        assert_eq!("1-03 on 03 - Three.mp3, 04 - Bonus.mp3", issues[1].details);
+    }
+
+    #[test]
+    fn test_consistent_album_has_no_issues() {
+        let group = AlbumGroup {
+            files: vec![
+                (PathBuf::from("a.flac"), song(Some(1), "Artist", 1999, Some(2))),
+                (PathBuf::from("b.flac"), song(Some(2), "Artist", 1999, Some(2))),
+            ],
+        };
+        assert!(check_album(&group, &PathInferenceSettings::default()).is_empty());
+    }
+
+    #[test]
+    fn test_colliding_track_numbers_are_not_fixed() {
+        let group = AlbumGroup {
+            files: vec![
+                (PathBuf::from("/music/album/01 - One.mp3"), song(Some(1), "Artist", 2001, Some(4))),
+                (PathBuf::from("/music/album/01 - One (Live).mp3"), song(None, "Artist", 2001, Some(4))),
+                (PathBuf::from("/music/album/03 - Three.mp3"), song(None, "Artist", 2001, Some(4))),
+                (PathBuf::from("/music/album/03 - Three (Demo).mp3"), song(None, "Artist", 2001, Some(4))),
+                (PathBuf::from("/music/album/04 - Four.mp3"), song(None, "Artist", 2001, Some(4))),
+            ],
+        };
+        let issues = check_album(&group, &PathInferenceSettings::default());
+        assert_eq!(IssueKind::MissingTrackNumber, issues[0].kind);
+        assert_eq!(vec![(4, FieldFix::TrackNumber(4))], issues[0].fixes);
+    }
+
+    #[test]
+    fn test_disabled_path_inference_leaves_track_numbers_alone() {
+        let group = AlbumGroup {
+            files: vec![
+                (PathBuf::from("/music/album/01 - One.mp3"), song(Some(1), "Artist", 2001, Some(2))),
+                (PathBuf::from("/music/album/02 - Two.mp3"), song(None, "Artist", 2001, Some(2))),
+            ],
+        };
+        let path_inference = PathInferenceSettings {
+            enabled: false,
+            ..PathInferenceSettings::default()
+        };
+        let issues = check_album(&group, &path_inference);
+        assert_eq!(IssueKind::MissingTrackNumber, issues[0].kind);
+        assert!(issues[0].fixes.is_empty());
+    }
+
+    #[test]
+    fn test_tied_values_are_not_fixed() {
+        let group = AlbumGroup {
+            files: vec![
+                (PathBuf::from("a.flac"), song(Some(1), "Artist", 1999, Some(2))),
+                (PathBuf::from("b.flac"), song(Some(2), "Other Artist", 2000, Some(2))),
+            ],
+        };
+        let issues = check_album(&group, &PathInferenceSettings::default());
+        let summary: Vec<(IssueKind, usize)> = issues.iter().map(|issue| (issue.kind, issue.fixes.len())).collect();
+        assert_eq!(vec![(IssueKind::MixedAlbumArtist, 0), (IssueKind::MixedYear, 0)], summary);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use strsim::jaro_winkler;
+use crate::settings::{AlbumSettings, ScoringSettings};
+use super::assignment::assign;
+use super::song_metadata::SongMetadata;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlbumGrouping {
+    /// Every folder is one album.
+    Folder,
+    /// Files sharing an album artist (or artist) and album tag are one album, wherever they are.
+    /// Files without an album tag are grouped by folder.
+    AlbumTag,
+}
+
+impl AlbumGrouping {
+    pub fn from_name(name: &str) -> Option<AlbumGrouping> {
+        return match name {
+            "folder" => Some(AlbumGrouping::Folder),
+            "album_tag" => Some(AlbumGrouping::AlbumTag),
+            _ => None,
+        };
+    }
+}
+
+/// A release found by the album search, with its tracks in disc and track order.
+#[derive(Clone, Debug)]
+pub struct AlbumRelease {
+    pub collection_id: u32,
+    pub album: String,
+    pub artist: Option<String>,
+    pub tracks: Vec<SongMetadata>,
+}
+
+/// Files believed to belong to one album, sorted by path.
+pub struct AlbumGroup {
+    pub files: Vec<(PathBuf, SongMetadata)>,
+}
+
+/// The best release for a group, `assignment` holding the index of the release track assigned to
+/// each file of the group and `similarities` how well each file fits each track.
+pub struct AlbumMatch<'a> {
+    pub release: &'a AlbumRelease,
+    pub score: f64,
+    pub assignment: Vec<Option<usize>>,
+    pub similarities: Vec<Vec<f64>>,
+}
+
+impl AlbumMatch<'_> {
+    /// The track assigned to the file when it fits at least `minimum_score`. Every file gets a
+    /// track while the release has enough of them, so a bonus track or a stray file would
+    /// otherwise take an unrelated song.
+    pub fn track_for(&self, file: usize, minimum_score: f64) -> Option<usize> {
+        return self.assignment[file].filter(|track| self.similarities[file][*track] >= minimum_score);
+    }
+}
+
+pub fn group_files(files: Vec<(PathBuf, SongMetadata)>, grouping: AlbumGrouping) -> Vec<AlbumGroup> {
+    let mut groups: BTreeMap<String, Vec<(PathBuf, SongMetadata)>> = BTreeMap::new();
+    for (path, song_metadata) in files {
+        let folder = path.parent().map(|parent| parent.to_string_lossy().to_string()).unwrap_or_default();
+        let key = match (grouping, &song_metadata.album) {
+            (AlbumGrouping::AlbumTag, Some(album)) => {
+                let artist = song_metadata.album_artist.clone().or(song_metadata.artist()).unwrap_or_default();
+                format!("tag:{}\0{}", artist.to_lowercase(), album.to_lowercase())
+            },
+            _ => format!("folder:{}", folder),
+        };
+        groups.entry(key).or_default().push((path, song_metadata));
+    }
+
+    return groups.into_values()
+        .map(|mut files| {
+            files.sort_by(|(a, _), (b, _)| a.cmp(b));
+            AlbumGroup { files }
+        })
+        .collect();
+}
+
+impl AlbumGroup {
+    /// Search term made of the most common album artist (or artist) and album of the files,
+    /// falling back to the folder name when no file has an album tag.
+    pub fn search_term(&self) -> Option<String> {
+        let artist = most_common(self.files.iter().filter_map(|(_, song)| song.album_artist.clone()))
+            .or_else(|| most_common(self.files.iter().filter_map(|(_, song)| song.artist())));
+        let album = most_common(self.files.iter().filter_map(|(_, song)| song.album.clone()))
+            .or_else(|| self.files.first()
+                .and_then(|(path, _)| path.parent()?.file_name())
+                .map(|folder| folder.to_string_lossy().to_string()));
+
+        let term = [artist, album].into_iter().flatten().collect::<Vec<String>>().join(" ");
+        return (!term.is_empty()).then_some(term);
+    }
+}
+
+fn most_common(values: impl Iterator<Item = String>) -> Option<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+    for value in values {
+        let count = counts.entry(value.clone()).or_insert(0);
+        if *count == 0 {
+            order.push(value);
+        }
+        *count += 1;
+    }
+    // Ties go to the value seen first, so the result doesn't depend on hashing.
+    return order.into_iter().max_by(|a, b| counts[a].cmp(&counts[b]).then(std::cmp::Ordering::Greater));
+}
+
+/// Aligns the files with the tracks of every release and returns the release that fits the group
+/// as a whole best. Each file is scored against each track on title, duration and position, the
+/// assignment with the highest total is chosen, and the total is divided by the larger of the
+/// file and track counts, so releases with missing or extra tracks score lower.
+pub fn match_album<'a>(files: &[SongMetadata], releases: &'a [AlbumRelease], scoring: &ScoringSettings, album_settings: &AlbumSettings) -> Option<AlbumMatch<'a>> {
+    return releases.iter()
+        .filter(|release| !release.tracks.is_empty())
+        .map(|release| {
+            let similarities: Vec<Vec<f64>> = files.iter().enumerate()
+                .map(|(index, file)| release.tracks.iter()
+                    .map(|track| track_similarity(file, index, track, scoring, album_settings))
+                    .collect())
+                .collect();
+            let costs: Vec<Vec<f64>> = similarities.iter().map(|row| row.iter().map(|similarity| 1.0 - similarity).collect()).collect();
+            let assignment = assign(&costs);
+            let total: f64 = assignment.iter().enumerate()
+                .filter_map(|(file, track)| track.map(|track| similarities[file][track]))
+                .sum();
+            let score = total / files.len().max(release.tracks.len()) as f64;
+            AlbumMatch { release, score, assignment, similarities }
+        })
+        .max_by(|a, b| a.score.total_cmp(&b.score));
+}
+
+/// How well a file fits a release track, from 0 to 1. Files without a track number are expected
+/// at the position of their file name within the group.
+fn track_similarity(file: &SongMetadata, index: usize, track: &SongMetadata, scoring: &ScoringSettings, album_settings: &AlbumSettings) -> f64 {
+    let mut parts: Vec<(f64, f64)> = Vec::new();
+    if let (Some(file_title), Some(track_title)) = (&file.title, &track.title) {
+        parts.push((jaro_winkler(&file_title.to_lowercase(), &track_title.to_lowercase()), scoring.title_weight));
+    }
+    if let (Some(file_duration), Some(track_duration)) = (file.duration, track.duration) {
+        parts.push((duration_similarity(file_duration, track_duration, scoring.duration_tolerance_secs), scoring.duration_weight));
+    }
+    let position = match file.track_number {
+        Some(track_number) => (file.disc_number.unwrap_or(1), track_number),
+        None => (1, index as u16 + 1),
+    };
+    let track_position = (track.disc_number.unwrap_or(1), track.track_number.unwrap_or(0));
+    parts.push((if position == track_position { 1.0 } else { 0.0 }, album_settings.track_order_weight));
+
+    let total_weight: f64 = parts.iter().map(|(_, weight)| weight).sum();
+    if total_weight == 0.0 {
+        return 0.0;
+    }
+    return parts.iter().map(|(value, weight)| value * weight).sum::<f64>() / total_weight;
+}
+
+/// 1 within the tolerance, falling linearly to 0 at twice the tolerance.
+fn duration_similarity(a: Duration, b: Duration, tolerance_secs: u64) -> f64 {
+    let difference = a.abs_diff(b).as_secs_f64();
+    let tolerance = tolerance_secs.max(1) as f64;
+    return (1.0 - (difference - tolerance).max(0.0) / tolerance).max(0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, track_number: u16, secs: u64) -> SongMetadata {
+        return SongMetadata {
+            title: Some(title.to_string()),
+            track_number: Some(track_number),
+            duration: Some(Duration::from_secs(secs)),
+            ..SongMetadata::default()
+        };
+    }
+
+    fn release(collection_id: u32, tracks: Vec<SongMetadata>) -> AlbumRelease {
+        return AlbumRelease {
+            collection_id,
+            album: "Album".to_string(),
+            artist: Some("Artist".to_string()),
+            tracks,
+        };
+    }
+
+    #[test]
+    fn test_match_album_prefers_release_with_matching_tracks() {
+        let standard = release(1, vec![track("Intro", 1, 60), track("Song", 2, 200), track("Outro", 3, 90)]);
+        let deluxe = release(2, vec![
+            track("Intro", 1, 60), track("Song", 2, 200), track("Outro", 3, 90),
+            track("Song (Demo)", 4, 210), track("Song (Live)", 5, 230),
+        ]);
+        let single = release(3, vec![track("Song", 1, 200)]);
+        let releases = vec![deluxe, single, standard];
+
+        // Untitled files in the wrong order, only their durations and names give them away.
+        let files = vec![
+            SongMetadata { duration: Some(Duration::from_secs(201)), ..SongMetadata::default() },
+            SongMetadata { duration: Some(Duration::from_secs(91)), ..SongMetadata::default() },
+            track("intro", 1, 59),
+        ];
+        let album_match = match_album(&files, &releases, &ScoringSettings::default(), &AlbumSettings::default()).unwrap();
+        assert_eq!(1, album_match.release.collection_id);
+        assert_eq!(vec![Some(1), Some(2), Some(0)], album_match.assignment);
+    }
+
+    #[test]
+    fn test_file_missing_from_release_gets_no_track() {
+        let releases = vec![release(1, vec![track("Intro", 1, 60), track("Song", 2, 200), track("Outro", 3, 90)])];
+        let files = vec![track("Intro", 1, 61), track("Song", 2, 199), track("Hidden Track", 9, 400)];
+        let album_match = match_album(&files, &releases, &ScoringSettings::default(), &AlbumSettings::default()).unwrap();
+        assert_eq!(Some(2), album_match.assignment[2]);
+        assert_eq!(Some(0), album_match.track_for(0, 0.5));
+        assert_eq!(Some(1), album_match.track_for(1, 0.5));
+        assert_eq!(None, album_match.track_for(2, 0.5));
+    }
+
+    #[test]
+    fn test_group_files() {
+        let tagged = |album: &str| SongMetadata {
+            album: Some(album.to_string()),
+            artists: Some(vec!["Artist".to_string()]),
+            ..SongMetadata::default()
+        };
+        let files = vec![
+            (PathBuf::from("/music/a/1.mp3"), tagged("One")),
+            (PathBuf::from("/music/b/1.mp3"), tagged("One")),
+            (PathBuf::from("/music/b/2.mp3"), SongMetadata::default()),
+        ];
+
+        let by_folder = group_files(files.clone(), AlbumGrouping::Folder);
+        assert_eq!(vec![1, 2], by_folder.iter().map(|group| group.files.len()).collect::<Vec<_>>());
+
+        let by_tag = group_files(files, AlbumGrouping::AlbumTag);
+        assert_eq!(vec![PathBuf::from("/music/b/2.mp3")], by_tag[0].files.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>());
+        assert_eq!(Some("Artist One".to_string()), by_tag[1].search_term());
+        assert_eq!(Some("b".to_string()), by_tag[0].search_term());
+    }
+}
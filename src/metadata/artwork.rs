@@ -0,0 +1,31 @@
+use lofty::picture::Picture;
+use regex::Regex;
+
+/// iTunes artwork URLs end in the image size, e.g. `.../100x100bb.jpg`, and serve any other size
+/// when it is changed.
+pub fn sized_artwork_url(url: &str, size: u32) -> String {
+    let re = Regex::new(r"/\d+x\d+bb\.").unwrap();
+    return re.replace(url, format!("/{}x{}bb.", size, size)).to_string();
+}
+
+/// Downloads the artwork at `size` pixels and checks that it is an image tags can hold.
+pub fn fetch_front_cover(url: &str, size: u32) -> Result<Vec<u8>, String> {
+    let bytes = reqwest::blocking::get(sized_artwork_url(url, size))
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .map_err(|e| e.to_string())?;
+    Picture::from_reader(&mut bytes.as_ref()).map_err(|e| format!("Not an image: {}", e))?;
+    return Ok(bytes.to_vec());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sized_artwork_url() {
+        let url = "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/a1/b2/source/100x100bb.jpg";
+        assert_eq!("https://is1-ssl.mzstatic.com/image/thumb/Music/v4/a1/b2/source/600x600bb.jpg", sized_artwork_url(url, 600));
+        assert_eq!("https://example.com/cover.jpg", sized_artwork_url("https://example.com/cover.jpg", 600));
+    }
+}
@@ -0,0 +1,107 @@
+/// Solves the assignment problem with the Hungarian algorithm: pairs rows with columns so the
+/// total cost is minimal. Every row gets a column when there are at least as many columns as
+/// rows, otherwise the rows left over are `None`.
+pub fn assign(costs: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let rows = costs.len();
+    let columns = costs.first().map(|row| row.len()).unwrap_or(0);
+    if rows == 0 || columns == 0 {
+        return vec![None; rows];
+    }
+    if rows <= columns {
+        return hungarian(costs).into_iter().map(Some).collect();
+    }
+
+    let transposed: Vec<Vec<f64>> = (0..columns).map(|column| costs.iter().map(|row| row[column]).collect()).collect();
+    let mut assignment = vec![None; rows];
+    for (column, row) in hungarian(&transposed).into_iter().enumerate() {
+        assignment[row] = Some(column);
+    }
+    return assignment;
+}
+
+/// The O(n²m) potentials formulation for `n <= m`, using 1-based indices internally with row
+/// and column 0 as sentinels. Returns the column assigned to each row.
+fn hungarian(costs: &[Vec<f64>]) -> Vec<usize> {
+    let (n, m) = (costs.len(), costs[0].len());
+    let mut row_potential = vec![0.0; n + 1];
+    let mut column_potential = vec![0.0; m + 1];
+    let mut column_owner = vec![0usize; m + 1];
+    let mut previous_column = vec![0usize; m + 1];
+
+    for row in 1..=n {
+        column_owner[0] = row;
+        let mut current_column = 0;
+        let mut min_reduced_cost = vec![f64::INFINITY; m + 1];
+        let mut visited = vec![false; m + 1];
+        loop {
+            visited[current_column] = true;
+            let current_row = column_owner[current_column];
+            let mut delta = f64::INFINITY;
+            let mut next_column = 0;
+            for column in 1..=m {
+                if visited[column] {
+                    continue;
+                }
+                let reduced_cost = costs[current_row - 1][column - 1] - row_potential[current_row] - column_potential[column];
+                if reduced_cost < min_reduced_cost[column] {
+                    min_reduced_cost[column] = reduced_cost;
+                    previous_column[column] = current_column;
+                }
+                if min_reduced_cost[column] < delta {
+                    delta = min_reduced_cost[column];
+                    next_column = column;
+                }
+            }
+            for column in 0..=m {
+                if visited[column] {
+                    row_potential[column_owner[column]] += delta;
+                    column_potential[column] -= delta;
+                } else {
+                    min_reduced_cost[column] -= delta;
+                }
+            }
+            current_column = next_column;
+            if column_owner[current_column] == 0 {
+                break;
+            }
+        }
+        while current_column != 0 {
+            let column = previous_column[current_column];
+            column_owner[current_column] = column_owner[column];
+            current_column = column;
+        }
+    }
+
+    let mut assignment = vec![0; n];
+    for column in 1..=m {
+        if column_owner[column] != 0 {
+            assignment[column_owner[column] - 1] = column - 1;
+        }
+    }
+    return assignment;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign() {
+        // Greedily taking the cheapest pair (0, 0) forces the expensive (1, 1).
+        let costs = vec![
+            vec![1.0, 2.0, 9.0],
+            vec![1.5, 9.0, 9.0],
+            vec![9.0, 9.0, 3.0],
+        ];
+        assert_eq!(vec![Some(1), Some(0), Some(2)], assign(&costs));
+    }
+
+    #[test]
+    fn test_assign_unbalanced() {
+        let wide = vec![vec![5.0, 1.0, 3.0], vec![1.0, 5.0, 2.0]];
+        assert_eq!(vec![Some(1), Some(0)], assign(&wide));
+
+        let tall = vec![vec![5.0, 1.0], vec![1.0, 5.0], vec![0.5, 4.0]];
+        assert_eq!(vec![Some(1), None, Some(0)], assign(&tall));
+    }
+}
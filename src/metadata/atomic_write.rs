@@ -0,0 +1,135 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use filetime::FileTime;
+
+const TEMP_FILE_MARKER: &str = "imd-tmp";
+
+/// A temporary copy of an audio file, next to the original so the final rename stays on the same
+/// filesystem. The copy is removed on drop unless it has replaced the original.
+struct TempCopy {
+    path: PathBuf,
+    committed: bool,
+}
+
+impl Drop for TempCopy {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Applies `write` to a temporary copy of `file_path`, checks the copy with `verify`, and only
+/// then renames it over the original. The original is left untouched if either step fails.
+pub fn write_atomically<W, V>(file_path: &Path, preserve_mtime: bool, write: W, verify: V) -> Result<(), String>
+where
+    W: FnOnce(&Path) -> Result<(), String>,
+    V: FnOnce(&Path) -> Result<(), String>,
+{
+    let original_metadata = fs::metadata(file_path)
+        .map_err(|e| format!("Failed to read {:?}: {}", file_path, e))?;
+    let mut temp_copy = TempCopy {
+        path: temp_path_for(file_path),
+        committed: false,
+    };
+    fs::copy(file_path, &temp_copy.path)
+        .map_err(|e| format!("Failed to copy {:?} to {:?}: {}", file_path, temp_copy.path, e))?;
+
+    write(&temp_copy.path)?;
+    verify(&temp_copy.path)?;
+
+    if preserve_mtime {
+        let mtime = FileTime::from_last_modification_time(&original_metadata);
+        filetime::set_file_mtime(&temp_copy.path, mtime)
+            .map_err(|e| format!("Failed to set modification time of {:?}: {}", temp_copy.path, e))?;
+    }
+
+    // The data has to be on disk before the rename is, or a crash could leave a renamed but empty file.
+    File::open(&temp_copy.path)
+        .and_then(|file| file.sync_all())
+        .map_err(|e| format!("Failed to sync {:?}: {}", temp_copy.path, e))?;
+    fs::rename(&temp_copy.path, file_path)
+        .map_err(|e| format!("Failed to replace {:?}: {}", file_path, e))?;
+    temp_copy.committed = true;
+    sync_parent_dir(file_path)?;
+
+    return Ok(());
+}
+
+/// Makes the rename itself durable.
+#[cfg(unix)]
+fn sync_parent_dir(file_path: &Path) -> Result<(), String> {
+    let parent = match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    return File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| format!("Failed to sync {:?}: {}", parent, e));
+}
+
+/// Directories can only be opened to sync them on Unix.
+#[cfg(not(unix))]
+fn sync_parent_dir(_file_path: &Path) -> Result<(), String> {
+    return Ok(());
+}
+
+/// Whether a path is a temporary copy made while writing, e.g. `.song.imd-tmp.mp3`.
+pub fn is_temp_file(path: &Path) -> bool {
+    return path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name.contains(&format!(".{}.", TEMP_FILE_MARKER)) || name.ends_with(&format!(".{}", TEMP_FILE_MARKER)));
+}
+
+/// Keeps the original extension, lofty uses it to detect the file type.
+fn temp_path_for(file_path: &Path) -> PathBuf {
+    let stem = file_path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = file_path.extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    return file_path.with_file_name(format!(".{}.{}{}", stem, TEMP_FILE_MARKER, extension));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_failed_verification_keeps_original() {
+        let audio_file = TestAudioFile::wav("atomic-verify");
+        let original = fs::read(audio_file.path()).unwrap();
+
+        let result = write_atomically(
+            audio_file.path(),
+            false,
+            |temp_path| fs::write(temp_path, b"corrupt").map_err(|e| e.to_string()),
+            |_| Err("mismatch".to_string()),
+        );
+
+        assert_eq!(Err("mismatch".to_string()), result);
+        assert_eq!(original, fs::read(audio_file.path()).unwrap());
+        assert!(!temp_path_for(audio_file.path()).exists());
+    }
+
+    #[test]
+    fn test_preserve_mtime() {
+        let audio_file = TestAudioFile::wav("atomic-mtime");
+        let old_mtime = FileTime::from_system_time(SystemTime::now() - Duration::from_secs(86400));
+        filetime::set_file_mtime(audio_file.path(), old_mtime).unwrap();
+
+        write_atomically(
+            audio_file.path(),
+            true,
+            |temp_path| fs::write(temp_path, b"new contents").map_err(|e| e.to_string()),
+            |_| Ok(()),
+        ).unwrap();
+
+        let metadata = fs::metadata(audio_file.path()).unwrap();
+        assert_eq!(b"new contents".to_vec(), fs::read(audio_file.path()).unwrap());
+        assert_eq!(old_mtime, FileTime::from_last_modification_time(&metadata));
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::Path;
+use lofty::file::{FileType, TaggedFile};
+use lofty::prelude::*;
+use lofty::probe::Probe;
+use serde::{Deserialize, Serialize};
+use crate::settings::QualitySettings;
+
+/// Properties of the audio stream itself, as opposed to its tags.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AudioProperties {
+    /// e.g. `FLAC`, `MP3` or `AAC`.
+    pub codec: Option<String>,
+    pub lossless: bool,
+    /// Audio bitrate in kbps.
+    pub bitrate: Option<u32>,
+    /// Sample rate in Hz.
+    pub sample_rate: Option<u32>,
+    /// Bits per sample, only known for uncompressed and lossless formats.
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+}
+
+impl AudioProperties {
+    pub fn from_tagged_file(tagged_file: &TaggedFile) -> AudioProperties {
+        let properties = tagged_file.properties();
+        // MP4 files only report a bit depth for ALAC, AAC has none.
+        let lossless_mp4 = properties.bit_depth().is_some();
+        let (codec, lossless) = match tagged_file.file_type() {
+            FileType::Aac => (Some("AAC"), false),
+            FileType::Aiff => (Some("AIFF"), true),
+            FileType::Ape => (Some("APE"), true),
+            FileType::Flac => (Some("FLAC"), true),
+            FileType::Mpeg => (Some("MP3"), false),
+            FileType::Mp4 if lossless_mp4 => (Some("ALAC"), true),
+            FileType::Mp4 => (Some("AAC"), false),
+            FileType::Mpc => (Some("Musepack"), false),
+            FileType::Opus => (Some("Opus"), false),
+            FileType::Vorbis => (Some("Vorbis"), false),
+            FileType::Speex => (Some("Speex"), false),
+            FileType::Wav => (Some("WAV"), true),
+            FileType::WavPack => (Some("WavPack"), true),
+            _ => (None, false),
+        };
+        return AudioProperties {
+            codec: codec.map(String::from),
+            lossless,
+            bitrate: properties.audio_bitrate().or(properties.overall_bitrate()).filter(|bitrate| *bitrate > 0),
+            sample_rate: properties.sample_rate(),
+            bit_depth: properties.bit_depth(),
+            channels: properties.channels(),
+        };
+    }
+
+    pub fn read(file_path: &Path) -> Result<AudioProperties, String> {
+        let tagged_file = Probe::open(file_path)
+            .map_err(|e| format!("Bad path provided: {:?}", e))?
+            .read()
+            .map_err(|e| format!("Failed to read file: {:?}", e))?;
+        return Ok(AudioProperties::from_tagged_file(&tagged_file));
+    }
+
+    /// Why the audio falls short of the quality settings, `None` when it passes. An unknown
+    /// bitrate passes `min_bitrate`, lossless files always do.
+    pub fn quality_shortfall(&self, quality: &QualitySettings) -> Option<String> {
+        if quality.lossless_only && !self.lossless {
+            return Some(format!("{} is not lossless", self.codec.as_deref().unwrap_or("Unknown format")));
+        }
+        if let (Some(min_bitrate), Some(bitrate), false) = (quality.min_bitrate, self.bitrate, self.lossless) {
+            if bitrate < min_bitrate {
+                return Some(format!("{} kbps is below {} kbps", bitrate, min_bitrate));
+            }
+        }
+        return None;
+    }
+
+    /// e.g. `FLAC, 1017 kbps, 44.1 kHz, 16 bit, stereo`.
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        parts.extend(self.codec.clone());
+        parts.extend(self.bitrate.map(|bitrate| format!("{} kbps", bitrate)));
+        parts.extend(self.sample_rate.map(|sample_rate| format!("{} kHz", sample_rate as f64 / 1000.0)));
+        parts.extend(self.bit_depth.map(|bit_depth| format!("{} bit", bit_depth)));
+        parts.extend(self.channels.map(|channels| match channels {
+            1 => "mono".to_string(),
+            2 => "stereo".to_string(),
+            channels => format!("{} channels", channels),
+        }));
+        return parts.join(", ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAudioFile;
+
+    fn mp3(bitrate: u32) -> AudioProperties {
+        return AudioProperties {
+            codec: Some("MP3".to_string()),
+            lossless: false,
+            bitrate: Some(bitrate),
+            sample_rate: Some(44100),
+            bit_depth: None,
+            channels: Some(2),
+        };
+    }
+
+    #[test]
+    fn test_read_properties() {
+        let audio_file = TestAudioFile::mp3("audio-properties");
+        let properties = AudioProperties::read(audio_file.path()).unwrap();
+        assert_eq!(Some("MP3".to_string()), properties.codec);
+        assert!(!properties.lossless);
+        assert!(properties.sample_rate.is_some());
+    }
+
+    #[test]
+    fn test_quality_shortfall() {
+        let flac = AudioProperties {
+            codec: Some("FLAC".to_string()),
+            lossless: true,
+            bitrate: Some(90),
+            bit_depth: Some(16),
+            ..mp3(0)
+        };
+        let min_bitrate = QualitySettings { min_bitrate: Some(256), lossless_only: false };
+        assert_eq!(Some("192 kbps is below 256 kbps".to_string()), mp3(192).quality_shortfall(&min_bitrate));
+        assert_eq!(None, mp3(320).quality_shortfall(&min_bitrate));
+        assert_eq!(None, flac.quality_shortfall(&min_bitrate));
+
+        let lossless_only = QualitySettings { min_bitrate: None, lossless_only: true };
+        assert_eq!(Some("MP3 is not lossless".to_string()), mp3(320).quality_shortfall(&lossless_only));
+        assert_eq!(None, flac.quality_shortfall(&lossless_only));
+        assert_eq!("MP3, 192 kbps, 44.1 kHz, stereo", mp3(192).describe());
+    }
+}
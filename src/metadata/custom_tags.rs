@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use lofty::prelude::*;
+use lofty::tag::{ItemValue, Tag, TagItem, TagType};
+use crate::settings::CustomTagSettings;
+use super::replay_gain::{R128_ALBUM_GAIN_KEY, R128_TRACK_GAIN_KEY};
+
+/// Keys with a `SongMetadata` field, every other text item is a custom tag.
+const MODELLED_KEYS: [ItemKey; 31] = [
+    ItemKey::TrackTitle,
+    ItemKey::TrackArtist,
+    ItemKey::AlbumTitle,
+    ItemKey::AlbumArtist,
+    ItemKey::Composer,
+    ItemKey::Genre,
+    ItemKey::TrackNumber,
+    ItemKey::DiscNumber,
+    ItemKey::Year,
+    ItemKey::RecordingDate,
+    ItemKey::Comment,
+    ItemKey::TrackTotal,
+    ItemKey::DiscTotal,
+    ItemKey::FlagCompilation,
+    ItemKey::ReleaseDate,
+    ItemKey::Isrc,
+    ItemKey::Label,
+    ItemKey::CopyrightMessage,
+    ItemKey::CatalogNumber,
+    ItemKey::Barcode,
+    ItemKey::TrackTitleSortOrder,
+    ItemKey::TrackArtistSortOrder,
+    ItemKey::AlbumArtistSortOrder,
+    ItemKey::Bpm,
+    ItemKey::IntegerBpm,
+    ItemKey::InitialKey,
+    ItemKey::Lyrics,
+    ItemKey::ReplayGainTrackGain,
+    ItemKey::ReplayGainTrackPeak,
+    ItemKey::ReplayGainAlbumGain,
+    ItemKey::ReplayGainAlbumPeak,
+];
+
+/// The tag's custom text items keyed by their format specific key, e.g. the TXXX description for
+/// ID3v2 or the field name for Vorbis comments.
+pub fn read_custom_items(tag: &Tag) -> BTreeMap<String, Vec<String>> {
+    let mut custom: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for item in tag.items() {
+        if let (Some(key), ItemValue::Text(value)) = (custom_key(tag.tag_type(), item.key()), item.value()) {
+            custom.entry(key).or_default().push(value.clone());
+        }
+    }
+    return custom;
+}
+
+/// Adds the custom items to a tag that doesn't have them yet, skipping keys the tag type has no
+/// place for.
+pub fn copy_custom_items(tag: &mut Tag, custom: &BTreeMap<String, Vec<String>>) {
+    let tag_type = tag.tag_type();
+    for (key, values) in custom {
+        let item_key = ItemKey::from_key(tag_type, key);
+        if tag.get_items(&item_key).next().is_some() || !accepts_key(tag_type, &item_key) {
+            continue;
+        }
+        for value in values {
+            tag.push_unchecked(TagItem::new(item_key.clone(), ItemValue::Text(value.clone())));
+        }
+    }
+}
+
+/// Deletes and renames the tag's custom items according to the rules.
+pub fn apply_custom_tag_rules(tag: &mut Tag, rules: &CustomTagSettings) {
+    let tag_type = tag.tag_type();
+    let mut matched: Vec<(ItemKey, String)> = Vec::new();
+    for item in tag.items() {
+        if let Some(key) = custom_key(tag_type, item.key()) {
+            if !matched.iter().any(|(item_key, _)| item_key == item.key()) {
+                matched.push((item.key().clone(), key));
+            }
+        }
+    }
+
+    for (item_key, key) in matched {
+        if rules.is_deleted(&key) {
+            tag.remove_key(&item_key);
+        } else if let Some(new_key) = rules.renamed(&key) {
+            let new_item_key = ItemKey::from_key(tag_type, new_key);
+            if !accepts_key(tag_type, &new_item_key) {
+                eprintln!("WARN: {:?} tags can't hold {:?}, not renaming {:?}", tag_type, new_key, key);
+                continue;
+            }
+            let values: Vec<String> = tag.take_strings(&item_key).collect();
+            for value in values {
+                tag.push_unchecked(TagItem::new(new_item_key.clone(), ItemValue::Text(value)));
+            }
+        }
+    }
+}
+
+fn custom_key(tag_type: TagType, item_key: &ItemKey) -> Option<String> {
+    if MODELLED_KEYS.contains(item_key) {
+        return None;
+    }
+    if let ItemKey::Unknown(key) = item_key {
+        if [R128_TRACK_GAIN_KEY, R128_ALBUM_GAIN_KEY].iter().any(|r128_key| r128_key.eq_ignore_ascii_case(key)) {
+            return None;
+        }
+    }
+    return item_key.map_key(tag_type, true).map(|key| key.to_string());
+}
+
+/// Whether a tag of `tag_type` can store `item_key`. Free-form keys are only valid in some formats,
+/// ID3v2 stores them as TXXX frames and MP4 as `----` atoms.
+fn accepts_key(tag_type: TagType, item_key: &ItemKey) -> bool {
+    if item_key.map_key(tag_type, false).is_some() {
+        return true;
+    }
+    return match item_key {
+        ItemKey::Unknown(key) => match tag_type {
+            TagType::Id3v2 | TagType::VorbisComments | TagType::Ape => true,
+            TagType::Mp4Ilst => key.starts_with("----:"),
+            _ => false,
+        },
+        _ => false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::TagLike;
+    use crate::metadata::song_metadata::SongMetadata;
+    use crate::settings::WriterSettings;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_custom_tags_survive_writes_and_follow_rules() {
+        let audio_file = TestAudioFile::mp3("custom-tags");
+        let mut original = id3::Tag::new();
+        original.set_title("Old");
+        original.add_frame(id3::frame::ExtendedText { description: "DJ Crate".to_string(), value: "House".to_string() });
+        original.add_frame(id3::frame::ExtendedText { description: "Old Key".to_string(), value: "8A".to_string() });
+        original.add_frame(id3::frame::ExtendedText { description: "Keep Me".to_string(), value: "Yes".to_string() });
+        original.write_to_path(audio_file.path(), id3::Version::Id3v24).unwrap();
+
+        let read = SongMetadata::read_metadata_from_audio_file(audio_file.path());
+        assert_eq!(Some(&vec!["House".to_string()]), read.custom.get("DJ Crate"));
+
+        let writer_settings = WriterSettings {
+            custom_tags: CustomTagSettings {
+                delete: vec!["dj *".to_string()],
+                rename: BTreeMap::from([("old key".to_string(), "New Key".to_string())]),
+            },
+            ..WriterSettings::default()
+        };
+        SongMetadata {
+            title: Some("New".to_string()),
+            ..SongMetadata::default()
+        }.write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+
+        let written = SongMetadata::read_metadata_from_audio_file(audio_file.path());
+        assert_eq!(Some("New".to_string()), written.title);
+        assert_eq!(BTreeMap::from([
+            ("Keep Me".to_string(), vec!["Yes".to_string()]),
+            ("New Key".to_string(), vec!["8A".to_string()]),
+        ]), written.custom);
+    }
+}
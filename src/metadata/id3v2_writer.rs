@@ -0,0 +1,312 @@
+use std::collections::HashSet;
+use std::path::Path;
+use id3::frame::{Content, ExtendedText};
+use id3::{Encoder, Frame, TagLike, Version};
+use serde::{Deserialize, Serialize};
+use crate::settings::{CustomTagSettings, Id3v2Settings, WriterSettings};
+
+/// ID3v2 version written to files. lofty always writes ID3v2.4, other versions are produced by
+/// re-encoding its output.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Id3v2Version {
+    #[serde(rename = "2.3")]
+    V23,
+    #[serde(rename = "2.4")]
+    V24,
+}
+
+impl Id3v2Version {
+    pub fn from_name(name: &str) -> Option<Id3v2Version> {
+        return match name {
+            "2.3" => Some(Id3v2Version::V23),
+            "2.4" => Some(Id3v2Version::V24),
+            _ => None,
+        };
+    }
+
+    fn to_id3(self) -> Version {
+        return match self {
+            Id3v2Version::V23 => Version::Id3v23,
+            Id3v2Version::V24 => Version::Id3v24,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextEncoding {
+    Latin1,
+    Utf16,
+    /// Only valid in ID3v2.4.
+    Utf8,
+}
+
+impl TextEncoding {
+    pub fn from_name(name: &str) -> Option<TextEncoding> {
+        return match name {
+            "latin1" => Some(TextEncoding::Latin1),
+            "utf16" => Some(TextEncoding::Utf16),
+            "utf8" => Some(TextEncoding::Utf8),
+            _ => None,
+        };
+    }
+
+    fn to_id3(self) -> id3::Encoding {
+        return match self {
+            TextEncoding::Latin1 => id3::Encoding::Latin1,
+            TextEncoding::Utf16 => id3::Encoding::UTF16,
+            TextEncoding::Utf8 => id3::Encoding::UTF8,
+        };
+    }
+}
+
+/// Timestamp frames of ID3v2.4, with the ID3v2.3 year frame they are written as.
+const V24_TO_V23_YEAR_FRAMES: [(&str, &str); 2] = [
+    ("TDRC", "TYER"),
+    ("TDOR", "TORY"),
+];
+
+/// Frames that were renamed in ID3v2.4, with the ID3v2.3 frame they are written as. The sort order
+/// frames are kept as they are, ID3v2.3 readers understand them even though only ID3v2.4 defines them.
+const V24_TO_V23_FRAMES: [(&str, &str); 1] = [
+    ("TIPL", "IPLS"),
+];
+
+/// ID3v2.3 has no release time frame, the `TDRL` date is written to a user defined text frame with
+/// this description instead.
+pub const ID3V23_RELEASE_DATE: &str = "RELEASEDATE";
+
+/// Frames that only exist in ID3v2.4 and have no ID3v2.3 equivalent, so they are left out.
+const V24_ONLY_FRAMES: [&str; 6] = ["TDEN", "TDTG", "TMCL", "TMOO", "TPRO", "TSST"];
+
+pub fn read_id3v2(file_path: &Path) -> Result<Option<id3::Tag>, String> {
+    return id3::no_tag_ok(id3::Tag::read_from_path(file_path))
+        .map_err(|e| format!("Failed to read ID3v2 tag: {}", e));
+}
+
+/// Re-encodes the ID3v2 tag lofty wrote to `file_path` when the settings ask for something lofty
+/// can't do: another version, a specific text encoding, or frames from `previous` that lofty has no
+/// generic representation for and dropped.
+pub fn rewrite_id3v2(file_path: &Path, previous: Option<&id3::Tag>, writer_settings: &WriterSettings) -> Result<(), String> {
+    let settings = &writer_settings.id3v2;
+    let written = match read_id3v2(file_path)? {
+        Some(written) => written,
+        None => return Ok(()),
+    };
+
+    let written_keys: HashSet<String> = written.frames().map(frame_key).collect();
+    let dropped_frames: Vec<&Frame> = match previous {
+        Some(previous) if settings.preserve_unknown_frames => previous.frames()
+            .filter(|frame| !written_keys.contains(&frame_key(frame)))
+            .filter(|frame| !removed_by_rules(frame, &writer_settings.custom_tags))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    if settings.version == Id3v2Version::V24 && settings.encoding.is_none() && dropped_frames.is_empty() {
+        return Ok(());
+    }
+    if settings.version == Id3v2Version::V23 && settings.encoding == Some(TextEncoding::Utf8) {
+        return Err("ID3v2.3 tags can not be UTF-8 encoded".to_string());
+    }
+
+    let mut tag = id3::Tag::with_version(settings.version.to_id3());
+    // Written frames come last so they replace a carried over frame they were converted to.
+    for frame in dropped_frames.into_iter().chain(written.frames()) {
+        let mut frame = frame.clone();
+        if settings.version == Id3v2Version::V23 {
+            frame = match downgrade_frame(frame, &writer_settings.multi_value_separator) {
+                Some(frame) => frame,
+                None => continue,
+            };
+        }
+        if let Some(encoding) = settings.encoding {
+            frame = frame.set_encoding(Some(encoding.to_id3()));
+        }
+        tag.add_frame(frame);
+    }
+
+    let mut encoder = Encoder::new().version(settings.version.to_id3());
+    if let Some(padding) = writer_settings.padding {
+        encoder = encoder.padding(padding as usize);
+    }
+    return encoder.write_to_path(&tag, file_path)
+        .map_err(|e| format!("Failed to write ID3v2 tag: {}", e));
+}
+
+/// Checks that the file's ID3v2 tag has the configured version.
+pub fn verify_id3v2(file_path: &Path, settings: &Id3v2Settings) -> Result<(), String> {
+    if let Some(tag) = read_id3v2(file_path)? {
+        if tag.version() != settings.version.to_id3() {
+            return Err(format!("ID3v2 tag is version {} instead of {}", tag.version(), settings.version.to_id3()));
+        }
+    }
+    return Ok(());
+}
+
+/// Converts an ID3v2.4 frame to its ID3v2.3 equivalent, or `None` when ID3v2.3 has none. ID3v2.3
+/// has no multi-value text frames, so null separated values are joined with `separator`.
+fn downgrade_frame(frame: Frame, separator: &str) -> Option<Frame> {
+    if let Content::Text(text) = frame.content() {
+        if text.contains('\0') {
+            let joined = text.split('\0').collect::<Vec<&str>>().join(separator);
+            return downgrade_frame(Frame::text(frame.id(), joined), separator);
+        }
+    }
+    if V24_ONLY_FRAMES.contains(&frame.id()) {
+        return None;
+    }
+    if frame.id() == "TDRL" {
+        if let Content::Text(text) = frame.content() {
+            return Some(Frame::with_content("TXXX", Content::ExtendedText(ExtendedText {
+                description: ID3V23_RELEASE_DATE.to_string(),
+                value: text.clone(),
+            })));
+        }
+    }
+    for (v24_id, v23_id) in V24_TO_V23_YEAR_FRAMES {
+        if frame.id() == v24_id {
+            if let Content::Text(text) = frame.content() {
+                // ID3v2.3 year frames only hold the year of the v2.4 timestamp.
+                return Some(Frame::text(v23_id, text.chars().take(4).collect::<String>()));
+            }
+        }
+    }
+    for (v24_id, v23_id) in V24_TO_V23_FRAMES {
+        if frame.id() == v24_id {
+            return Some(Frame::with_content(v23_id, frame.content().clone()));
+        }
+    }
+    return Some(frame);
+}
+
+/// Whether a custom tag rule removed or renamed the frame, so it must not be carried over.
+fn removed_by_rules(frame: &Frame, rules: &CustomTagSettings) -> bool {
+    return match frame.content() {
+        Content::ExtendedText(text) => rules.is_deleted(&text.description) || rules.renamed(&text.description).is_some(),
+        _ => false,
+    };
+}
+
+/// Identifies a frame the way ID3v2 does for uniqueness, the frame ID plus the description or
+/// owner for frame types that may occur more than once.
+fn frame_key(frame: &Frame) -> String {
+    let qualifier = match frame.content() {
+        Content::ExtendedText(text) => text.description.clone(),
+        Content::ExtendedLink(link) => link.description.clone(),
+        Content::Comment(comment) => format!("{}/{}", comment.lang, comment.description),
+        Content::Lyrics(lyrics) => format!("{}/{}", lyrics.lang, lyrics.description),
+        Content::EncapsulatedObject(object) => object.description.clone(),
+        Content::Private(private) => private.owner_identifier.clone(),
+        Content::UniqueFileIdentifier(identifier) => identifier.owner_identifier.clone(),
+        Content::Picture(picture) => format!("{:?}", picture.picture_type),
+        _ => String::new(),
+    };
+    return format!("{}:{}", frame.id(), qualifier);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::frame::{InvolvedPeopleList, InvolvedPeopleListItem, Private};
+    use crate::metadata::song_metadata::SongMetadata;
+    use crate::settings::WriterSettings;
+    use crate::test_support::TestAudioFile;
+
+    #[test]
+    fn test_write_id3v23_latin1_and_keep_unknown_frames() {
+        let audio_file = TestAudioFile::mp3("id3v23");
+        let mut original = id3::Tag::new();
+        original.set_title("Old");
+        original.add_frame(Private {
+            owner_identifier: "imd-test".to_string(),
+            private_data: vec![1, 2, 3],
+        });
+        original.write_to_path(audio_file.path(), Version::Id3v24).unwrap();
+
+        let writer_settings = WriterSettings {
+            id3v2: Id3v2Settings {
+                version: Id3v2Version::V23,
+                encoding: Some(TextEncoding::Latin1),
+                ..Id3v2Settings::default()
+            },
+            ..WriterSettings::default()
+        };
+        SongMetadata {
+            title: Some("New".to_string()),
+            artists: Some(vec!["First".to_string(), "Second".to_string()]),
+            year: Some(1999),
+            ..SongMetadata::default()
+        }.write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+
+        let written = read_id3v2(audio_file.path()).unwrap().unwrap();
+        assert_eq!(Version::Id3v23, written.version());
+        assert_eq!(Some("New"), written.title());
+        assert_eq!(Some("First; Second"), written.artist());
+        assert_eq!(Some("1999"), written.get("TYER").and_then(|frame| frame.content().text()));
+        assert!(written.get("TDRC").is_none());
+        assert_eq!(1, written.frames().filter(|frame| frame.id() == "PRIV").count());
+    }
+
+    #[test]
+    fn test_write_id3v23_downgrades_v24_only_frames() {
+        let audio_file = TestAudioFile::mp3("id3v23-frames");
+        let mut original = id3::Tag::new();
+        original.add_frame(Frame::text("TMOO", "Calm"));
+        original.add_frame(InvolvedPeopleList {
+            items: vec![InvolvedPeopleListItem {
+                involvement: "producer".to_string(),
+                involvee: "Producer".to_string(),
+            }],
+        });
+        original.write_to_path(audio_file.path(), Version::Id3v24).unwrap();
+
+        let writer_settings = WriterSettings {
+            id3v2: Id3v2Settings {
+                version: Id3v2Version::V23,
+                ..Id3v2Settings::default()
+            },
+            ..WriterSettings::default()
+        };
+        SongMetadata {
+            sort_artist: Some("Artist, The".to_string()),
+            ..SongMetadata::default()
+        }.write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+
+        let written = read_id3v2(audio_file.path()).unwrap().unwrap();
+        let ids: HashSet<&str> = written.frames().map(|frame| frame.id()).collect();
+        assert_eq!(Some("Artist, The"), written.get("TSOP").and_then(|frame| frame.content().text()));
+        assert!(ids.contains("IPLS"));
+        for id in ["TIPL", "TMOO"] {
+            assert!(!ids.contains(id), "{} was written to an ID3v2.3 tag", id);
+        }
+    }
+
+    #[test]
+    fn test_write_id3v23_keeps_release_date() {
+        let audio_file = TestAudioFile::mp3("id3v23-release-date");
+        let writer_settings = WriterSettings {
+            id3v2: Id3v2Settings {
+                version: Id3v2Version::V23,
+                ..Id3v2Settings::default()
+            },
+            ..WriterSettings::default()
+        };
+        for release_date in ["2001-02-03", "2004-05-06"] {
+            SongMetadata {
+                release_date: Some(release_date.to_string()),
+                ..SongMetadata::default()
+            }.write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+        }
+
+        let written = read_id3v2(audio_file.path()).unwrap().unwrap();
+        assert!(written.get("TDRL").is_none());
+        let release_dates: Vec<&str> = written.extended_texts()
+            .filter(|text| text.description == ID3V23_RELEASE_DATE)
+            .map(|text| text.value.as_str())
+            .collect();
+        assert_eq!(vec!["2004-05-06"], release_dates);
+        let read = SongMetadata::read_metadata_from_audio_file(audio_file.path());
+        assert_eq!(Some("2004-05-06".to_string()), read.release_date);
+    }
+}
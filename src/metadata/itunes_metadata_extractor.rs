@@ -2,10 +2,21 @@ use std::time::Duration;
 
 use chrono::{DateTime, Datelike};
 use serde::Deserialize;
-use url::Url;
+use url::{form_urlencoded, Url};
 use regex::Regex;
+use crate::settings::Settings;
+use super::rate_limiter::RateLimiter;
+use super::album_matcher::AlbumRelease;
+use super::response_cache::ResponseCache;
 use super::song_metadata::SongMetadata;
 
+const ITUNES_PROVIDER: &str = "itunes";
+pub const KNOWN_PROVIDERS: [&str; 1] = [ITUNES_PROVIDER];
+const PROVIDER_TEST_TERM: &str = "Yesterday+The+Beatles";
+const ITUNES_SEARCH_PATH: &str = "search";
+const ITUNES_LOOKUP_PATH: &str = "lookup";
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 struct ItunesSearchResult {
@@ -76,100 +87,230 @@ struct ItunesSearchResultItem {
     primary_genre_name: Option<String>,
     #[serde(rename = "isStreamable")]
     is_streamable: Option<bool>,
+    /// Only present on collection results.
+    copyright: Option<String>,
 }
 
-pub fn find_matching_metadata(song_metadata: &SongMetadata) -> Vec<SongMetadata> {
-    validate_initial_data(song_metadata);
+/// Searches every provider for the song. Without a title and artist there is nothing to search
+/// for, so there are no candidates and the file stays unmatched.
+pub fn find_matching_metadata(song_metadata: &SongMetadata, settings: &Settings) -> Vec<SongMetadata> {
+    if song_metadata.title.is_none() || song_metadata.artists.is_none() {
+        eprintln!("WARN: Title and artist are needed to search, no candidates");
+        return Vec::new();
+    }
     let mut matching_items: Vec<SongMetadata> = Vec::new();
+    let cache = settings.cache.open();
+
+    for provider in &settings.providers.order {
+        match provider.as_str() {
+            ITUNES_PROVIDER => {
+                let rate_limiter = RateLimiter::shared(ITUNES_PROVIDER, settings.providers.requests_per_minute);
+                matching_items.extend(find_matching_itunes_metadata(song_metadata, &settings.providers.storefronts, cache.as_ref(), &rate_limiter));
+            },
+            _ => eprintln!("WARN: Unknown metadata provider {:?}, skipping", provider),
+        }
+    }
+
+    return matching_items;
+}
+
+/// Runs a fixed search against the provider, bypassing the cache, and reports how many results came back.
+pub fn test_provider(provider: &str, settings: &Settings) -> Result<u32, String> {
+    return match provider {
+        ITUNES_PROVIDER => {
+            let storefront = settings.providers.storefronts.first().map(|s| s.as_str()).unwrap_or("us");
+            let url = build_itunes_search_url(PROVIDER_TEST_TERM, storefront);
+            let body = reqwest::blocking::get(&url)
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| response.text())
+                .map_err(|e| e.to_string())?;
+            let result: ItunesSearchResult = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+            Ok(result.result_count)
+        },
+        _ => Err(format!("Unknown metadata provider {:?}", provider)),
+    };
+}
+
+fn fetch_itunes_search_result(url: &str, cache: Option<&ResponseCache>, rate_limiter: &RateLimiter) -> Option<ItunesSearchResult> {
+    if let Some(cached_body) = cache.and_then(|cache| cache.get(url)) {
+        if let Ok(result) = serde_json::from_str(&cached_body) {
+            println!("Using cached iTunes response");
+            return Some(result);
+        }
+    }
 
-    let itunes_metadata_url = build_itunes_metadata_url(song_metadata);
-    println!("iTunes metadata URL: {}", itunes_metadata_url);
-    let itunes_search_result: Option<ItunesSearchResult> = reqwest::blocking::get(&itunes_metadata_url)
+    rate_limiter.wait();
+    let body = reqwest::blocking::get(url)
         .expect("Failed to get metadata from iTunes")
-        .json()
-        .expect("Failed to parse JSON response");
-
-    match itunes_search_result {
-        Some(r) => {
-            if r.result_count == 0 {
-                println!("No results found for matching metadata in iTunes, trying again with simplified search terms");
-                let original_title = song_metadata.title.clone().unwrap_or_else(|| "".to_string());
-                let original_artist = song_metadata.artist.clone().unwrap_or_else(|| "".to_string());
-        
-                let simplified_title = simplify_metadata_string(&original_title);
-                let simplified_artist = simplify_metadata_string(&original_artist);
-        
-                if original_title == simplified_title && original_artist == simplified_artist {
-                    panic!("ERROR: No results found for metadata matching for song: {} by {}", original_title, original_artist)
-                } else {
-                    let simplified_metadata = SongMetadata {
-                        title: Some(simplified_title.clone().to_owned()),
-                        artist: Some(simplified_artist.clone().to_owned()),
-                        ..song_metadata.clone()
-                    };
-                    return find_matching_metadata(&simplified_metadata);
+        .text()
+        .expect("Failed to read iTunes response");
+    let result: Option<ItunesSearchResult> = serde_json::from_str(&body).expect("Failed to parse JSON response");
+    if let (Some(cache), Some(_)) = (cache, &result) {
+        cache.put(url, &body);
+    }
+    return result;
+}
+
+fn find_matching_itunes_metadata(song_metadata: &SongMetadata, storefronts: &[String], cache: Option<&ResponseCache>, rate_limiter: &RateLimiter) -> Vec<SongMetadata> {
+    let mut matching_items: Vec<SongMetadata> = Vec::new();
+
+    for storefront in storefronts {
+        let itunes_metadata_url = build_itunes_metadata_url(song_metadata, storefront);
+        println!("iTunes metadata URL: {}", itunes_metadata_url);
+        let itunes_search_result: Option<ItunesSearchResult> = fetch_itunes_search_result(&itunes_metadata_url, cache, rate_limiter);
+
+        match itunes_search_result {
+            Some(r) => {
+                if r.result_count == 0 {
+                    println!("No results found for matching metadata in iTunes storefront {:?}", storefront);
+                    continue;
                 }
-            } else {
                 let result_items = r.results;
                 println!("Found {} results", result_items.len());
                 println!("Results: {:?}", result_items);
 
                 let result_metadata = result_items.iter()
                     .filter(|item| item.wrapper_type.as_ref().map(|s| s == "track").unwrap_or(false))
-                    .map(|item| {
-                        SongMetadata {
-                            title: item.track_name.clone(),
-                            artist: item.artist_name.clone(),
-                            album: item.collection_name.clone(),
-                            album_artist: None,
-                            composer: None,
-                            genre: item.primary_genre_name.clone(),
-                            track_number: item.track_number.clone(),
-                            disc_number: item.disc_number.clone(),
-                            year: item.release_date.as_ref().map(|s| itunes_release_date_to_year(s)),
-                            comment: None,
-                            duration: item.track_time_millis.map(|n| Duration::from_millis(n)),
-                            total_tracks: item.track_count.clone(),
-                            total_discs: item.disc_count.clone(),
-                            is_compilation: None,
-                        }
-                    })
+                    .map(track_item_to_song_metadata)
                     .collect::<Vec<SongMetadata>>();
                 matching_items.extend(result_metadata);
                 return matching_items;
-            }
-        },
-        None => { panic!("Somthing went wrong searching for metadata")}
+            },
+            None => { panic!("Somthing went wrong searching for metadata")}
+        }
     }
-}
 
-fn build_itunes_metadata_url(song_metadata: &SongMetadata) -> String {
-    const ITUNES: &'static str = "https://itunes.apple.com";
-    const SEARCH_API_PATH: &'static str = "search";
-    let mut url = Url::parse(ITUNES).expect("hardcoded url is valid");
-    url.set_path(SEARCH_API_PATH);
+    println!("No results found for matching metadata in iTunes, trying again with simplified search terms");
+    let original_title = song_metadata.title.clone().unwrap_or_default();
+    let original_artist = song_metadata.artist().unwrap_or_default();
 
-    let query_items = format!("{}+{}", song_metadata.title.as_ref().unwrap(), song_metadata.artist.as_ref().unwrap());
-    let query = format!("term={}", query_items);
+    let simplified_title = simplify_metadata_string(&original_title);
+    let simplified_artist = simplify_metadata_string(&original_artist);
 
-    url.set_query(Some(&query));
+    if original_title == simplified_title && original_artist == simplified_artist {
+        eprintln!("WARN: No results found for metadata matching for song: {} by {}", original_title, original_artist);
+        return matching_items;
+    }
+    let simplified_metadata = SongMetadata {
+        title: Some(simplified_title),
+        artists: Some(vec![simplified_artist]),
+        ..song_metadata.clone()
+    };
+    return find_matching_itunes_metadata(&simplified_metadata, storefronts, cache, rate_limiter);
+}
 
-    return url.to_string();
+fn track_item_to_song_metadata(item: &ItunesSearchResultItem) -> SongMetadata {
+    return SongMetadata {
+        title: item.track_name.clone(),
+        artists: item.artist_name.clone().map(|artist| vec![artist]),
+        album: item.collection_name.clone(),
+        album_artist: None,
+        composer: None,
+        genres: item.primary_genre_name.clone().map(|genre| vec![genre]),
+        track_number: item.track_number,
+        disc_number: item.disc_number,
+        year: item.release_date.as_ref().map(|s| itunes_release_date_to_year(s)),
+        comment: None,
+        duration: item.track_time_millis.map(Duration::from_millis),
+        total_tracks: item.track_count,
+        total_discs: item.disc_count,
+        is_compilation: None,
+        release_date: item.release_date.as_ref().map(|s| itunes_release_date_to_date(s)),
+        copyright: item.copyright.clone(),
+        artwork_url: item.artwork_url100.clone(),
+        ..SongMetadata::default()
+    };
 }
 
-fn validate_initial_data(initial_song_metadata: &SongMetadata) {
-    if initial_song_metadata.title.is_none() {
-        panic!("ERROR: Title is required!");
-    }
-    if initial_song_metadata.artist.is_none() {
-        panic!("ERROR: Artist is required!");
+/// Searches the iTunes albums matching `term` and looks up the tracks of the best
+/// `album.max_candidates` results.
+pub fn find_album_releases(term: &str, settings: &Settings) -> Vec<AlbumRelease> {
+    let cache = settings.cache.open();
+    let rate_limiter = RateLimiter::shared(ITUNES_PROVIDER, settings.providers.requests_per_minute);
+    for storefront in &settings.providers.storefronts {
+        let search_url = build_itunes_album_search_url(term, storefront);
+        println!("iTunes album search URL: {}", search_url);
+        let collections = fetch_itunes_search_result(&search_url, cache.as_ref(), &rate_limiter)
+            .map(|result| result.results)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|item| item.wrapper_type.as_deref() == Some("collection") && item.collection_id.is_some())
+            .take(settings.album.max_candidates)
+            .collect::<Vec<ItunesSearchResultItem>>();
+        if collections.is_empty() {
+            println!("No albums found in iTunes storefront {:?}", storefront);
+            continue;
+        }
+
+        return collections.iter()
+            .map(|collection| lookup_album_release(collection, storefront, cache.as_ref(), &rate_limiter))
+            .collect();
     }
+    return Vec::new();
+}
+
+fn lookup_album_release(collection: &ItunesSearchResultItem, storefront: &str, cache: Option<&ResponseCache>, rate_limiter: &RateLimiter) -> AlbumRelease {
+    let collection_id = collection.collection_id.expect("collections without an id are filtered out");
+    let lookup_url = build_itunes_url(ITUNES_LOOKUP_PATH, &format!("id={}&entity=song&country={}", collection_id, storefront));
+    let album_artist = collection.artist_name.clone();
+    let is_compilation = album_artist.as_deref() == Some(VARIOUS_ARTISTS);
+    let mut tracks = fetch_itunes_search_result(&lookup_url, cache, rate_limiter)
+        .map(|result| result.results)
+        .unwrap_or_default()
+        .iter()
+        .filter(|item| item.wrapper_type.as_deref() == Some("track"))
+        .map(|item| SongMetadata {
+            album_artist: album_artist.clone(),
+            is_compilation: Some(is_compilation),
+            copyright: collection.copyright.clone(),
+            ..track_item_to_song_metadata(item)
+        })
+        .collect::<Vec<SongMetadata>>();
+    tracks.sort_by_key(|track| (track.disc_number, track.track_number));
+
+    return AlbumRelease {
+        collection_id,
+        album: collection.collection_name.clone().unwrap_or_default(),
+        artist: album_artist,
+        tracks,
+    };
+}
+
+fn build_itunes_metadata_url(song_metadata: &SongMetadata, storefront: &str) -> String {
+    let query_items = format!("{}+{}", song_metadata.title.as_ref().unwrap(), song_metadata.artist().unwrap());
+    return build_itunes_search_url(&query_items, storefront);
+}
+
+fn build_itunes_search_url(term: &str, storefront: &str) -> String {
+    return build_itunes_url(ITUNES_SEARCH_PATH, &format!("term={}&country={}", term, storefront));
+}
+
+/// The term is encoded as a whole, so names like "Simon & Garfunkel" stay part of it.
+fn build_itunes_album_search_url(term: &str, storefront: &str) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("term", term)
+        .append_pair("entity", "album")
+        .append_pair("country", storefront)
+        .finish();
+    return build_itunes_url(ITUNES_SEARCH_PATH, &query);
+}
+
+fn build_itunes_url(path: &str, query: &str) -> String {
+    const ITUNES: &str = "https://itunes.apple.com";
+    let mut url = Url::parse(ITUNES).expect("hardcoded url is valid");
+    url.set_path(path);
+    url.set_query(Some(query));
+    return url.to_string();
 }
 
 fn itunes_release_date_to_year(release_date: &str) -> u16 {
     return DateTime::parse_from_rfc3339(release_date).expect("Failed to parse date").year() as u16;
 }
 
+fn itunes_release_date_to_date(release_date: &str) -> String {
+    return DateTime::parse_from_rfc3339(release_date).expect("Failed to parse date").format("%Y-%m-%d").to_string();
+}
+
 fn simplify_metadata_string(metadata_string: &str) -> String {
     let re = Regex::new(r"\s[\(\[].*[\)\]]").unwrap();
     return re.replace_all(metadata_string, "").trim().to_string();
@@ -190,3 +331,11 @@ fn test_simplify_metadata_string() {
         assert_eq!(expected, simplify_metadata_string(input));
     }
 }
+
+#[test]
+fn test_build_itunes_album_search_url() {
+    assert_eq!(
+        "https://itunes.apple.com/search?term=Simon+%26+Garfunkel+Bookends&entity=album&country=gb",
+        build_itunes_album_search_url("Simon & Garfunkel Bookends", "gb"),
+    );
+}
@@ -1,30 +1,40 @@
+use std::time::Duration;
 use strsim::jaro_winkler;
+use crate::settings::ScoringSettings;
 use super::song_metadata::SongMetadata;
 
 pub struct MetadataComparator {
     song_metadata: SongMetadata,
     potential_metadata_match: SongMetadata,
+    scoring: ScoringSettings,
 }
 
 impl MetadataComparator {
-    pub fn new(song_metadata: SongMetadata, potential_metadata_match: SongMetadata) -> MetadataComparator {
+    pub fn new(song_metadata: SongMetadata, potential_metadata_match: SongMetadata, scoring: ScoringSettings) -> MetadataComparator {
         MetadataComparator {
             song_metadata,
             potential_metadata_match,
+            scoring,
         }
     }
 
     pub fn get_overall_score(&self) -> f64 {
-        let title_artist_score = self.get_title_artist_score();
-        let duration_score = self.get_duration_score();
-        return average([title_artist_score, duration_score]);
+        return weighted_average(self.get_score_breakdown().map(|(_, score, weight)| (score, weight)));
+    }
 
+    /// Each part of the overall score with its weight, e.g. `("title", 0.93, 1.0)`.
+    pub fn get_score_breakdown(&self) -> [(&'static str, f64, f64); 3] {
+        return [
+            ("title", self.get_title_score() * self.inferred_factor("title"), self.scoring.title_weight),
+            ("artist", self.get_artist_score() * self.inferred_factor("artists"), self.scoring.artist_weight),
+            ("duration", self.get_duration_score(), self.scoring.duration_weight),
+        ];
     }
 
-    fn get_title_artist_score(&self) -> f64 {
-        let title_score = self.get_title_score();
-        let artist_score = self.get_artist_score();
-        return average([title_score, artist_score]);
+    /// Values guessed from the file path are less reliable than tags, so their similarity counts for
+    /// less. Lowering their weight instead would shift it onto the other fields and raise the score.
+    fn inferred_factor(&self, field: &str) -> f64 {
+        return if self.song_metadata.inferred.contains(&field) { self.scoring.inferred_weight } else { 1.0 };
     }
 
     fn get_title_score(&self) -> f64 {
@@ -37,16 +47,17 @@ impl MetadataComparator {
     }
 
     fn get_artist_score(&self) -> f64 {
-        return match (&self.song_metadata.artist, &self.potential_metadata_match.artist) {
+        return match (self.song_metadata.artist(), self.potential_metadata_match.artist()) {
             (Some(song_artist), Some(itunes_artist)) => {
-                jaro_winkler_distance(song_artist, itunes_artist)
+                jaro_winkler_distance(&song_artist, &itunes_artist)
             },
             _ => 0.0,
         };
     }
 
     fn get_duration_score(&self) -> f64 {
-        return if song_time_within_tolerance(&self.song_metadata, &self.potential_metadata_match) {
+        let tolerance = Duration::from_secs(self.scoring.duration_tolerance_secs);
+        return if song_time_within_tolerance(&self.song_metadata, &self.potential_metadata_match, tolerance) {
             1.0
         } else {
             0.0
@@ -54,28 +65,79 @@ impl MetadataComparator {
     }
 }
 
-fn song_time_within_tolerance(song_metadata: &SongMetadata, itunes_metadata: &SongMetadata) -> bool {
-    const TOLERANCE_S: u64 = 10;
-    let song_duration = song_metadata.duration.unwrap();
-    let itunes_duration = itunes_metadata.duration.unwrap();
-    let duration_diff = song_duration.checked_sub(itunes_duration).unwrap_or_else(|| itunes_duration.checked_sub(song_duration).unwrap());
-    return duration_diff.as_secs() <= TOLERANCE_S;
+fn song_time_within_tolerance(song_metadata: &SongMetadata, itunes_metadata: &SongMetadata, tolerance: Duration) -> bool {
+    return match (song_metadata.duration, itunes_metadata.duration) {
+        (Some(song_duration), Some(itunes_duration)) => song_duration.abs_diff(itunes_duration) <= tolerance,
+        _ => false,
+    };
 }
 
 fn jaro_winkler_distance(s1: &str, s2: &str) -> f64 {
     return jaro_winkler(s1, s2);
 }
 
-fn average<T>(values: T) -> f64
+fn weighted_average<T>(values: T) -> f64
 where
-    T: IntoIterator,
-    T::Item: Into<f64>,
+    T: IntoIterator<Item = (f64, f64)>,
 {
     let mut sum = 0.0;
-    let mut count = 0;
-    for value in values {
-        sum += value.into();
-        count += 1;
+    let mut total_weight = 0.0;
+    for (value, weight) in values {
+        sum += value * weight;
+        total_weight += weight;
+    }
+    if total_weight == 0.0 {
+        return 0.0;
+    }
+    return sum / total_weight;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inferred_values_weigh_less() {
+        let song_metadata = SongMetadata {
+            title: Some("Digital Love".to_string()),
+            artists: Some(vec!["Daft Punk".to_string()]),
+            inferred: vec!["title"],
+            ..SongMetadata::default()
+        };
+        let comparator = MetadataComparator::new(song_metadata.clone(), song_metadata, ScoringSettings::default());
+        let [title, artist, _] = comparator.get_score_breakdown();
+        assert_eq!(("title", 0.5, 1.0), title);
+        assert_eq!(("artist", 1.0, 1.0), artist);
+    }
+
+    #[test]
+    fn test_inferred_match_never_scores_above_tagged() {
+        let tagged = SongMetadata {
+            title: Some("Digital Love".to_string()),
+            artists: Some(vec!["Daft Punk".to_string()]),
+            duration: Some(Duration::from_secs(301)),
+            ..SongMetadata::default()
+        };
+        let inferred = SongMetadata {
+            inferred: vec!["title", "artists"],
+            ..tagged.clone()
+        };
+        let candidates = [
+            ("Digital Love", "Daft Punk", 301),
+            ("Digital Lover", "Daft Punk", 301),
+            ("Something Else", "Someone Else", 301),
+            ("Digital Love", "Daft Punk", 200),
+        ];
+        for (title, artist, secs) in candidates {
+            let candidate = SongMetadata {
+                title: Some(title.to_string()),
+                artists: Some(vec![artist.to_string()]),
+                duration: Some(Duration::from_secs(secs)),
+                ..SongMetadata::default()
+            };
+            let tagged_score = MetadataComparator::new(tagged.clone(), candidate.clone(), ScoringSettings::default()).get_overall_score();
+            let inferred_score = MetadataComparator::new(inferred.clone(), candidate, ScoringSettings::default()).get_overall_score();
+            assert!(inferred_score <= tagged_score, "{} scored {} inferred and {} tagged", title, inferred_score, tagged_score);
+        }
     }
-    return sum / count as f64;
 }
@@ -1,20 +1,19 @@
 use crate::metadata::itunes_metadata_extractor::find_matching_metadata;
 use crate::metadata::metadata_comparator::MetadataComparator;
+use crate::settings::{MergePolicy, MergeSettings, Settings};
+use super::path_inference::without_inferred;
 use super::song_metadata::SongMetadata;
 
 
-pub fn get_fixed_metadata(metadata: &SongMetadata) -> SongMetadata {
-    let matching_metadata_candidates: Vec<SongMetadata> = find_matching_metadata(metadata);
-
-    let mut metadata_scores: Vec<(&SongMetadata, f64)> = matching_metadata_candidates.iter()
-        .map(|metadata_candidate| {
-            let score = MetadataComparator::new(metadata.clone(), metadata_candidate.clone()).get_overall_score();
-            (metadata_candidate, score)
-        })
+/// Finds the best matching metadata and combines it with the original metadata.
+/// Returns the combined metadata along with the score of the best match, or `None` when no
+/// candidate reaches the configured minimum score.
+pub fn get_fixed_metadata(metadata: &SongMetadata, settings: &Settings) -> Option<(SongMetadata, f64)> {
+    let metadata_scores: Vec<(SongMetadata, f64)> = rank_candidates(metadata, find_matching_metadata(metadata, settings), settings)
+        .into_iter()
+        .filter(|(_, score)| *score >= settings.thresholds.minimum_score)
         .collect();
 
-    metadata_scores.sort_by_key(|(_, score)| (*score * 1000.0) as i64);
-
     // print top 5 matches
     println!("########################################################################################");
     println!("Top 5 matches:");
@@ -23,32 +22,105 @@ pub fn get_fixed_metadata(metadata: &SongMetadata) -> SongMetadata {
     }
     println!("########################################################################################");
 
-    let best_match = metadata_scores.last().unwrap();
-
-    let best_match_song_metadata = best_match.0;
+    let (best_match_song_metadata, best_match_score) = match metadata_scores.first() {
+        Some((best_match, score)) => (best_match, *score),
+        None => {
+            eprintln!("WARN: No match scored at least {:.2}, keeping original metadata", settings.thresholds.minimum_score);
+            return None;
+        },
+    };
 
     println!("########################################################################################");
     println!("Best match: {:?}", best_match_song_metadata);
     println!("########################################################################################");
 
-    return combine_metadata(metadata, best_match_song_metadata);
+    return Some((combine_metadata(metadata, best_match_song_metadata, &settings.merge), best_match_score));
+}
+
+/// Scores every candidate against the metadata, best first.
+pub fn rank_candidates(metadata: &SongMetadata, candidates: Vec<SongMetadata>, settings: &Settings) -> Vec<(SongMetadata, f64)> {
+    let mut metadata_scores: Vec<(SongMetadata, f64)> = candidates.into_iter()
+        .map(|metadata_candidate| {
+            let score = MetadataComparator::new(metadata.clone(), metadata_candidate.clone(), settings.scoring.clone()).get_overall_score();
+            (metadata_candidate, score)
+        })
+        .collect();
+    // Stable, so equally scored candidates keep the provider order.
+    metadata_scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    return metadata_scores;
 }
 
-fn combine_metadata(original_song_metadata: &SongMetadata, best_match: &SongMetadata) -> SongMetadata {
+pub fn combine_metadata(original_song_metadata: &SongMetadata, best_match: &SongMetadata, merge_settings: &MergeSettings) -> SongMetadata {
+    // Values guessed from the path only served to find the match, they never outrank it.
+    let original_song_metadata = &without_inferred(original_song_metadata.clone());
+    macro_rules! merge {
+        ($field:ident) => {
+            merge_field(merge_settings.policy_for(stringify!($field)), &original_song_metadata.$field, &best_match.$field)
+        };
+    }
+
     SongMetadata {
-        title: best_match.title.clone().or(original_song_metadata.title.clone()),
-        artist: best_match.artist.clone().or(original_song_metadata.artist.clone()),
-        album: best_match.album.clone().or(original_song_metadata.album.clone()),
-        album_artist: best_match.album_artist.clone().or(original_song_metadata.album_artist.clone()),
-        composer: best_match.composer.clone(),
-        genre: best_match.genre.clone().or(original_song_metadata.genre.clone()),
-        track_number: best_match.track_number.or(original_song_metadata.track_number.clone()),
-        disc_number: best_match.disc_number.or(original_song_metadata.disc_number.clone()),
-        year: best_match.year.or(original_song_metadata.year.clone()),
-        comment: best_match.comment.clone(),
-        duration: original_song_metadata.duration.clone(),
-        total_tracks: best_match.total_tracks.or(original_song_metadata.total_tracks.clone()),
-        total_discs: best_match.total_discs.or(original_song_metadata.total_discs.clone()),
-        is_compilation: best_match.is_compilation.or(original_song_metadata.is_compilation.clone()),
+        title: merge!(title),
+        artists: merge!(artists),
+        album: merge!(album),
+        album_artist: merge!(album_artist),
+        composer: merge!(composer),
+        genres: merge!(genres),
+        track_number: merge!(track_number),
+        disc_number: merge!(disc_number),
+        year: merge!(year),
+        comment: merge!(comment),
+        duration: original_song_metadata.duration,
+        audio: original_song_metadata.audio.clone(),
+        total_tracks: merge!(total_tracks),
+        total_discs: merge!(total_discs),
+        is_compilation: merge!(is_compilation),
+        release_date: merge!(release_date),
+        isrc: merge!(isrc),
+        label: merge!(label),
+        copyright: merge!(copyright),
+        catalogue_number: merge!(catalogue_number),
+        barcode: merge!(barcode),
+        sort_title: merge!(sort_title),
+        sort_artist: merge!(sort_artist),
+        sort_album_artist: merge!(sort_album_artist),
+        bpm: merge!(bpm),
+        initial_key: merge!(initial_key),
+        lyrics: merge!(lyrics),
+        replay_gain: merge!(replay_gain),
+        artwork_url: merge!(artwork_url),
+        front_cover: merge!(front_cover),
+        inferred: Vec::new(),
+        // Providers don't return custom tags.
+        custom: original_song_metadata.custom.clone(),
     }
-}
\ No newline at end of file
+}
+
+fn merge_field<T: Clone>(policy: MergePolicy, original: &Option<T>, matched: &Option<T>) -> Option<T> {
+    return match policy {
+        MergePolicy::PreferMatch => matched.clone().or(original.clone()),
+        MergePolicy::PreferOriginal => original.clone().or(matched.clone()),
+        MergePolicy::MatchOnly => matched.clone(),
+        MergePolicy::KeepOriginal => original.clone(),
+    };
+}
+
+#[test]
+fn test_merge_field() {
+    let original = Some("Original".to_string());
+    let matched = Some("Match".to_string());
+
+    // policy, original, matched, expected
+    let test_cases = vec![
+        (MergePolicy::PreferMatch, &original, &matched, &matched),
+        (MergePolicy::PreferMatch, &original, &None, &original),
+        (MergePolicy::PreferOriginal, &original, &matched, &original),
+        (MergePolicy::PreferOriginal, &None, &matched, &matched),
+        (MergePolicy::MatchOnly, &original, &None, &None),
+        (MergePolicy::KeepOriginal, &None, &matched, &None),
+    ];
+
+    for (policy, original, matched, expected) in test_cases {
+        assert_eq!(expected, &merge_field(policy, original, matched));
+    }
+}
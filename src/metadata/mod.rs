@@ -1,4 +1,17 @@
 pub mod song_metadata;
+pub mod audio_properties;
 pub mod itunes_metadata_extractor;
 pub mod metadata_fixer;
-mod metadata_comparator;
\ No newline at end of file
+pub mod metadata_comparator;
+pub mod atomic_write;
+mod custom_tags;
+pub mod id3v2_writer;
+pub mod response_cache;
+pub mod tag_target;
+pub mod path_inference;
+pub mod album_checker;
+pub mod album_matcher;
+pub mod artwork;
+mod assignment;
+pub mod replay_gain;
+pub mod rate_limiter;
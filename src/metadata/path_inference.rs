@@ -0,0 +1,204 @@
+use std::path::Path;
+use regex::Regex;
+use crate::settings::PathInferenceSettings;
+use super::song_metadata::SongMetadata;
+
+const TEXT_FIELDS: [&str; 4] = ["title", "artist", "album", "album_artist"];
+const NUMBER_FIELDS: [&str; 3] = ["track", "disc", "year"];
+
+/// A pattern matched against the end of a file's path, without its extension, e.g.
+/// `{artist} - {album}/{track} - {title}`. Every `/` separated part matches one path component.
+/// Raw regex patterns name their captures after the placeholders instead.
+#[derive(Debug)]
+pub struct PathPattern {
+    regex: Regex,
+}
+
+impl PathPattern {
+    pub fn from_template(template: &str) -> PathPattern {
+        let components = template.split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| template_component_to_regex(template, component))
+            .collect::<Vec<String>>();
+        if components.is_empty() {
+            panic!("ERROR: Path pattern {:?} is empty", template);
+        }
+        return PathPattern::from_regex(&format!("(?:^|/){}$", components.join("/")));
+    }
+
+    pub fn from_regex(pattern: &str) -> PathPattern {
+        let regex = Regex::new(pattern)
+            .unwrap_or_else(|e| panic!("ERROR: Invalid path pattern {:?}: {}", pattern, e));
+        return PathPattern { regex };
+    }
+
+    /// The fields this pattern captures from the path, or `None` when it doesn't match.
+    pub fn infer(&self, file_path: &Path) -> Option<SongMetadata> {
+        let path = file_path.with_extension("");
+        let path = path.components()
+            .map(|component| clean_component(&component.as_os_str().to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join("/");
+        let captures = self.regex.captures(&path)?;
+        let text = |name: &str| captures.name(name).map(|value| value.as_str().trim().to_string()).filter(|value| !value.is_empty());
+        let number = |name: &str| captures.name(name).and_then(|value| value.as_str().trim().parse::<u16>().ok());
+
+        return Some(SongMetadata {
+            title: text("title"),
+            artists: text("artist").map(|artist| vec![artist]),
+            album: text("album"),
+            album_artist: text("album_artist"),
+            track_number: number("track"),
+            disc_number: number("disc"),
+            year: number("year"),
+            ..SongMetadata::default()
+        });
+    }
+}
+
+fn template_component_to_regex(template: &str, component: &str) -> String {
+    let mut regex = String::new();
+    let mut rest = component;
+    while let Some(start) = rest.find('{') {
+        regex.push_str(&regex::escape(&rest[..start]));
+        let end = rest[start..].find('}')
+            .map(|end| start + end)
+            .unwrap_or_else(|| panic!("ERROR: Unclosed placeholder in path pattern {:?}", template));
+        let name = &rest[start + 1..end];
+        if TEXT_FIELDS.contains(&name) {
+            regex.push_str(&format!("(?P<{}>[^/]+?)", name));
+        } else if NUMBER_FIELDS.contains(&name) {
+            regex.push_str(&format!("(?P<{}>[0-9]+)", name));
+        } else if name == "ignore" {
+            regex.push_str("[^/]*?");
+        } else {
+            panic!("ERROR: Unknown placeholder {:?} in path pattern {:?}", name, template);
+        }
+        rest = &rest[end + 1..];
+    }
+    regex.push_str(&regex::escape(rest));
+    return regex;
+}
+
+/// Turns underscores into spaces in names like `Artist_-_Title`, so patterns don't need to
+/// spell out both forms.
+fn clean_component(component: &str) -> String {
+    if component.contains(' ') {
+        return component.to_string();
+    }
+    return component.replace('_', " ");
+}
+
+/// The fields captured by the first configured pattern matching the file's path.
+pub fn infer_path_fields(file_path: &Path, settings: &PathInferenceSettings) -> Option<SongMetadata> {
+    return settings.regex_patterns.iter().map(|pattern| PathPattern::from_regex(pattern))
+        .chain(settings.patterns.iter().map(|template| PathPattern::from_template(template)))
+        .find_map(|pattern| pattern.infer(file_path));
+}
+
+/// Fills fields still missing after reading the tags from the first pattern matching the file's
+/// path. Names are less reliable than tags, so values that were read from tags are never replaced.
+pub fn infer_from_path(file_path: &Path, song_metadata: SongMetadata, settings: &PathInferenceSettings) -> SongMetadata {
+    let has_gaps = song_metadata.title.is_none() || song_metadata.artists.is_none() || song_metadata.album.is_none()
+        || song_metadata.track_number.is_none();
+    if !settings.enabled || !has_gaps {
+        return song_metadata;
+    }
+
+    let Some(inferred) = infer_path_fields(file_path, settings) else {
+        return song_metadata;
+    };
+
+    let mut inferred_fields: Vec<&str> = Vec::new();
+    macro_rules! fill {
+        ($($field:ident),+) => {
+            SongMetadata {
+                $($field: song_metadata.$field.clone().or_else(|| {
+                    inferred.$field.clone().inspect(|_| inferred_fields.push(stringify!($field)))
+                }),)+
+                ..song_metadata
+            }
+        };
+    }
+    let mut filled = fill!(title, artists, album, album_artist, track_number, disc_number, year);
+    if !inferred_fields.is_empty() {
+        println!("Inferred from the file path: {}", inferred_fields.join(", "));
+    }
+    filled.inferred.extend(inferred_fields);
+    return filled;
+}
+
+/// The metadata without the values guessed from the file path, as it may be written.
+pub fn without_inferred(mut song_metadata: SongMetadata) -> SongMetadata {
+    macro_rules! clear {
+        ($($field:ident),+) => {
+            $(if song_metadata.inferred.contains(&stringify!($field)) {
+                song_metadata.$field = None;
+            })+
+        };
+    }
+    clear!(title, artists, album, album_artist, track_number, disc_number, year);
+    song_metadata.inferred.clear();
+    return song_metadata;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn infer(path: &str) -> SongMetadata {
+        return infer_from_path(&PathBuf::from(path), SongMetadata::default(), &PathInferenceSettings::default());
+    }
+
+    #[test]
+    fn test_infer_from_default_patterns() {
+        let inferred = infer("/music/Daft Punk - Discovery/03 - Digital Love.mp3");
+        assert_eq!(Some("Digital Love".to_string()), inferred.title);
+        assert_eq!(Some(vec!["Daft Punk".to_string()]), inferred.artists);
+        assert_eq!(Some("Discovery".to_string()), inferred.album);
+        assert_eq!(Some(3), inferred.track_number);
+        assert_eq!(vec!["title", "artists", "album", "track_number"], inferred.inferred);
+
+        let inferred = infer("/downloads/Daft_Punk_-_Digital_Love.flac");
+        assert_eq!(Some("Digital Love".to_string()), inferred.title);
+        assert_eq!(Some(vec!["Daft Punk".to_string()]), inferred.artists);
+        assert_eq!(None, inferred.album);
+    }
+
+    #[test]
+    fn test_tags_take_precedence() {
+        let song_metadata = SongMetadata {
+            title: Some("Tagged".to_string()),
+            ..SongMetadata::default()
+        };
+        let settings = PathInferenceSettings {
+            patterns: vec!["{artist}/{album}/{disc}-{track} {title}".to_string()],
+            ..PathInferenceSettings::default()
+        };
+        let inferred = infer_from_path(&PathBuf::from("Artist/Album/2-07 Named.ogg"), song_metadata, &settings);
+        assert_eq!(Some("Tagged".to_string()), inferred.title);
+        assert_eq!(Some("Album".to_string()), inferred.album);
+        assert_eq!((Some(7), Some(2)), (inferred.track_number, inferred.disc_number));
+
+        let written = without_inferred(inferred);
+        assert_eq!(Some("Tagged".to_string()), written.title);
+        assert_eq!((None, None, None), (written.album, written.track_number, written.disc_number));
+        assert!(written.inferred.is_empty());
+    }
+
+    #[test]
+    fn test_regex_pattern() {
+        let pattern = PathPattern::from_regex(r"/(?P<year>\d{4}) (?P<album>[^/]+)/(?P<title>[^/]+)$");
+        let inferred = pattern.infer(&PathBuf::from("/music/1971 What's Going On/Save the Children.m4a")).unwrap();
+        assert_eq!(Some(1971), inferred.year);
+        assert_eq!(Some("What's Going On".to_string()), inferred.album);
+        assert_eq!(Some("Save the Children".to_string()), inferred.title);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown placeholder \"bitrate\"")]
+    fn test_unknown_placeholder() {
+        PathPattern::from_template("{bitrate}/{title}");
+    }
+}
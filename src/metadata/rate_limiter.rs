@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Spaces out requests to a service so parallel workers together stay within its rate limit.
+/// Each caller reserves the next free slot and sleeps until it comes up.
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// A limiter allowing `requests_per_minute` requests, 0 meaning unlimited.
+    pub fn new(requests_per_minute: u32) -> RateLimiter {
+        let interval = match requests_per_minute {
+            0 => Duration::ZERO,
+            requests => Duration::from_secs(60) / requests,
+        };
+        RateLimiter {
+            interval,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// The limiter for the named service, shared by every thread in the process. The rate given by
+    /// the first caller applies.
+    pub fn shared(name: &str, requests_per_minute: u32) -> Arc<RateLimiter> {
+        static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<RateLimiter>>>> = OnceLock::new();
+        let mut limiters = LIMITERS.get_or_init(Default::default).lock().expect("ERROR: Rate limiter registry poisoned");
+        return limiters.entry(name.to_string())
+            .or_insert_with(|| Arc::new(RateLimiter::new(requests_per_minute)))
+            .clone();
+    }
+
+    /// Blocks until the caller may send its request.
+    pub fn wait(&self) {
+        let now = Instant::now();
+        let slot = {
+            let mut next_slot = self.next_slot.lock().expect("ERROR: Rate limiter poisoned");
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + self.interval;
+            slot
+        };
+        thread::sleep(slot - now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_are_spaced_across_threads() {
+        let limiter = RateLimiter::new(1200);
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| limiter.wait());
+            }
+        });
+        // The first request goes out immediately, the other three 50 ms apart.
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert!(start.elapsed() < Duration::from_millis(1000));
+    }
+}
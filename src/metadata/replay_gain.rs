@@ -0,0 +1,155 @@
+use lofty::file::FileType;
+use lofty::prelude::*;
+use lofty::tag::{ItemValue, Tag, TagItem, TagType};
+use serde::{Deserialize, Serialize};
+use crate::audio::loudness::Loudness;
+use super::tag_target::supports_key;
+
+/// ReplayGain 2.0 reference loudness.
+const REFERENCE_LUFS: f64 = -18.0;
+/// Opus gains are relative to EBU R128's -23 LUFS instead (RFC 7845).
+const R128_REFERENCE_LUFS: f64 = -23.0;
+pub const R128_TRACK_GAIN_KEY: &str = "R128_TRACK_GAIN";
+pub const R128_ALBUM_GAIN_KEY: &str = "R128_ALBUM_GAIN";
+
+/// Gains in dB to bring playback to the ReplayGain reference loudness, peaks as linear amplitude.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ReplayGain {
+    pub track_gain: f64,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl ReplayGain {
+    /// `None` when the track is silent.
+    pub fn from_loudness(track: &Loudness, album: Option<&Loudness>) -> Option<ReplayGain> {
+        let album_loudness = album.and_then(|album| album.integrated().map(|loudness| (loudness, album.true_peak)));
+        return Some(ReplayGain {
+            track_gain: REFERENCE_LUFS - track.integrated()?,
+            track_peak: Some(track.true_peak),
+            album_gain: album_loudness.map(|(loudness, _)| REFERENCE_LUFS - loudness),
+            album_peak: album_loudness.map(|(_, peak)| peak),
+        });
+    }
+
+    /// Reads the ReplayGain items, or the R128 gains Opus files carry instead.
+    pub fn from_tag(tag: &Tag) -> Option<ReplayGain> {
+        let value = |key: &ItemKey| tag.get_string(key).and_then(parse_gain);
+        if let Some(track_gain) = value(&ItemKey::ReplayGainTrackGain) {
+            return Some(ReplayGain {
+                track_gain,
+                track_peak: value(&ItemKey::ReplayGainTrackPeak),
+                album_gain: value(&ItemKey::ReplayGainAlbumGain),
+                album_peak: value(&ItemKey::ReplayGainAlbumPeak),
+            });
+        }
+
+        let r128 = |key: &str| tag.get_string(&ItemKey::Unknown(key.to_string()))
+            .and_then(|value| value.trim().parse::<i16>().ok())
+            .map(|q78| q78 as f64 / 256.0 + REFERENCE_LUFS - R128_REFERENCE_LUFS);
+        return Some(ReplayGain {
+            track_gain: r128(R128_TRACK_GAIN_KEY)?,
+            track_peak: None,
+            album_gain: r128(R128_ALBUM_GAIN_KEY),
+            album_peak: None,
+        });
+    }
+
+    /// Opus players ignore ReplayGain items, and Opus audio can't be decoded to measure it, so the
+    /// R128 gains an Opus file has are left as they are.
+    pub fn apply_to_tag(&self, tag: &mut Tag, file_type: FileType) {
+        if !ReplayGain::can_store(tag.tag_type(), file_type) {
+            return;
+        }
+
+        let values = [
+            (ItemKey::ReplayGainTrackGain, Some(format_gain(self.track_gain))),
+            (ItemKey::ReplayGainTrackPeak, self.track_peak.map(format_peak)),
+            (ItemKey::ReplayGainAlbumGain, self.album_gain.map(format_gain)),
+            (ItemKey::ReplayGainAlbumPeak, self.album_peak.map(format_peak)),
+        ];
+        for (key, value) in values {
+            tag.remove_key(&key);
+            if let Some(value) = value {
+                tag.insert(TagItem::new(key, ItemValue::Text(value)));
+            }
+        }
+    }
+
+    /// Whether `written` holds these values as precisely as the tag stores them.
+    pub fn matches(&self, written: &ReplayGain) -> bool {
+        return format_gain(self.track_gain) == format_gain(written.track_gain)
+            && self.track_peak.map(format_peak) == written.track_peak.map(format_peak)
+            && self.album_gain.map(format_gain) == written.album_gain.map(format_gain)
+            && self.album_peak.map(format_peak) == written.album_peak.map(format_peak);
+    }
+
+    pub fn can_store(tag_type: TagType, file_type: FileType) -> bool {
+        return file_type != FileType::Opus && supports_key(tag_type, &ItemKey::ReplayGainTrackGain);
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("track {}", format_gain(self.track_gain))];
+        if let Some(track_peak) = self.track_peak {
+            parts.push(format!("track peak {}", format_peak(track_peak)));
+        }
+        if let Some(album_gain) = self.album_gain {
+            parts.push(format!("album {}", format_gain(album_gain)));
+        }
+        if let Some(album_peak) = self.album_peak {
+            parts.push(format!("album peak {}", format_peak(album_peak)));
+        }
+        return parts.join(", ");
+    }
+}
+
+fn format_gain(gain: f64) -> String {
+    return format!("{:.2} dB", gain);
+}
+
+fn format_peak(peak: f64) -> String {
+    return format!("{:.6}", peak);
+}
+
+/// Parses values like `-6.52 dB` or `0.988553`.
+fn parse_gain(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = value.strip_suffix("dB").or_else(|| value.strip_suffix("DB")).unwrap_or(value);
+    return number.trim().parse::<f64>().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAIN: ReplayGain = ReplayGain {
+        track_gain: -6.523,
+        track_peak: Some(0.98855),
+        album_gain: Some(-7.1),
+        album_peak: Some(1.0),
+    };
+
+    #[test]
+    fn test_replay_gain_items() {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        GAIN.apply_to_tag(&mut tag, FileType::Flac);
+        assert_eq!(Some("-6.52 dB"), tag.get_string(&ItemKey::ReplayGainTrackGain));
+        assert_eq!(Some("0.988550"), tag.get_string(&ItemKey::ReplayGainTrackPeak));
+        let read = ReplayGain::from_tag(&tag).unwrap();
+        assert!(GAIN.matches(&read));
+    }
+
+    #[test]
+    fn test_opus_keeps_its_r128_gains() {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        tag.insert_unchecked(TagItem::new(ItemKey::Unknown(R128_TRACK_GAIN_KEY.to_string()), ItemValue::Text("-2950".to_string())));
+        GAIN.apply_to_tag(&mut tag, FileType::Opus);
+        assert_eq!(None, tag.get_string(&ItemKey::ReplayGainTrackGain));
+        assert_eq!(Some("-2950"), tag.get_string(&ItemKey::Unknown(R128_TRACK_GAIN_KEY.to_string())));
+        // -2950 / 256 dB to -23 LUFS is -6.52 dB to -18 LUFS.
+        let read = ReplayGain::from_tag(&tag).unwrap();
+        assert_eq!("-6.52 dB", format_gain(read.track_gain));
+        assert!(!ReplayGain::can_store(TagType::VorbisComments, FileType::Opus));
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use sha2::{Digest, Sha256};
+
+const CACHE_DIR_NAME: &str = "imd";
+const RESPONSES_DIR_NAME: &str = "responses";
+const RESPONSE_FILE_EXTENSION: &str = "json";
+
+/// Provider responses stored on disk, one file per request URL, so repeated lookups of the same
+/// song don't hit the network again until the entry expires.
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+pub struct CacheStats {
+    pub entries: usize,
+    pub expired: usize,
+    pub size_bytes: u64,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> ResponseCache {
+        ResponseCache {
+            dir,
+            ttl,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        return &self.dir;
+    }
+
+    pub fn get(&self, url: &str) -> Option<String> {
+        let path = self.entry_path(url);
+        if self.is_expired(&path) {
+            return None;
+        }
+        return fs::read_to_string(path).ok();
+    }
+
+    /// Failing to cache a response only costs a network request later, so errors are just reported.
+    pub fn put(&self, url: &str, body: &str) {
+        if let Err(e) = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.entry_path(url), body)) {
+            eprintln!("WARN: Failed to cache response for {}: {}", url, e);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            entries: 0,
+            expired: 0,
+            size_bytes: 0,
+        };
+        for path in self.entry_paths() {
+            stats.entries += 1;
+            stats.size_bytes += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            if self.is_expired(&path) {
+                stats.expired += 1;
+            }
+        }
+        return stats;
+    }
+
+    /// Removes every entry, or only the expired ones, returning how many were removed.
+    pub fn clear(&self, expired_only: bool) -> usize {
+        let mut removed = 0;
+        for path in self.entry_paths() {
+            if expired_only && !self.is_expired(&path) {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(_) => removed += 1,
+                Err(e) => eprintln!("WARN: Failed to remove {:?}: {}", path, e),
+            }
+        }
+        return removed;
+    }
+
+    fn entry_path(&self, url: &str) -> PathBuf {
+        return self.dir.join(format!("{:x}.{}", Sha256::digest(url.as_bytes()), RESPONSE_FILE_EXTENSION));
+    }
+
+    fn entry_paths(&self) -> Vec<PathBuf> {
+        return match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == RESPONSE_FILE_EXTENSION))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+    }
+
+    fn is_expired(&self, path: &Path) -> bool {
+        let modified = match fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return true,
+        };
+        return SystemTime::now().duration_since(modified).unwrap_or_default() > self.ttl;
+    }
+}
+
+pub fn default_cache_dir() -> PathBuf {
+    return dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(CACHE_DIR_NAME)
+        .join(RESPONSES_DIR_NAME);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_put_get_and_clear() {
+        let dir = std::env::temp_dir().join(format!("imd-cache-test-{}", std::process::id()));
+        let cache = ResponseCache::new(dir.clone(), Duration::from_secs(3600));
+        assert_eq!(None, cache.get("https://example.com/search?term=a"));
+
+        cache.put("https://example.com/search?term=a", "{\"resultCount\":0}");
+        assert_eq!(Some("{\"resultCount\":0}".to_string()), cache.get("https://example.com/search?term=a"));
+        assert_eq!(None, cache.get("https://example.com/search?term=b"));
+        assert_eq!(1, cache.stats().entries);
+
+        assert_eq!(0, cache.clear(true));
+        assert_eq!(1, cache.clear(false));
+        assert_eq!(0, cache.stats().entries);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
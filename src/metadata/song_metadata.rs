@@ -1,30 +1,85 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::time::Duration;
 use lofty::config::WriteOptions;
 use lofty::probe::Probe;
 use lofty::prelude::*;
-use lofty::tag::{ItemValue, Tag, TagItem};
+use lofty::file::TaggedFile;
+use lofty::id3::v2::{Frame, FrameFlags, Id3v2Tag, UnsynchronizedTextFrame};
+use lofty::picture::{Picture, PictureType};
+use lofty::tag::{ItemValue, Tag, TagItem, TagType};
+use lofty::TextEncoding;
+use serde::{Deserialize, Serialize};
+use crate::settings::WriterSettings;
+use super::atomic_write::write_atomically;
+use super::audio_properties::AudioProperties;
+use super::custom_tags::{apply_custom_tag_rules, copy_custom_items, read_custom_items};
+use super::replay_gain::ReplayGain;
+use super::id3v2_writer::{read_id3v2, rewrite_id3v2, verify_id3v2, ID3V23_RELEASE_DATE};
+use super::tag_target::{select_tag_types, supports_key, supports_multiple_values, tags_by_precedence, LEGACY_TAG_TYPES};
 
-#[derive(Clone, Debug)]
+/// The tag type each field was read from, keyed by field name.
+pub type FieldSources = Vec<(&'static str, TagType)>;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct SongMetadata {
     pub title: Option<String>,
-    pub artist: Option<String>,
+    /// Every artist, formats with multi-value support store each in its own value.
+    pub artists: Option<Vec<String>>,
     pub album: Option<String>,
     pub album_artist: Option<String>,
     pub composer: Option<String>,
-    pub genre: Option<String>,
+    pub genres: Option<Vec<String>>,
     pub track_number: Option<u16>,
     pub disc_number: Option<u16>,
     pub year: Option<u16>,
     pub comment: Option<String>,
     pub duration: Option<Duration>,
+    /// Codec, bitrate and the like, read from the file but never written.
+    pub audio: Option<AudioProperties>,
     pub total_tracks: Option<u16>,
     pub total_discs: Option<u16>,
-    pub is_compilation: Option<bool>
+    pub is_compilation: Option<bool>,
+    /// Full release date as written in the tag, usually `YYYY-MM-DD`.
+    pub release_date: Option<String>,
+    pub isrc: Option<String>,
+    pub label: Option<String>,
+    pub copyright: Option<String>,
+    pub catalogue_number: Option<String>,
+    pub barcode: Option<String>,
+    pub sort_title: Option<String>,
+    pub sort_artist: Option<String>,
+    pub sort_album_artist: Option<String>,
+    pub bpm: Option<u16>,
+    /// Musical key in the notation it was written with, e.g. `Am`, `8A` or `1m`.
+    pub initial_key: Option<String>,
+    pub lyrics: Option<String>,
+    /// Loudness normalisation, stored as ReplayGain 2.0 items or as R128 gains in Opus files.
+    pub replay_gain: Option<ReplayGain>,
+    /// Album artwork from the provider, only ever embedded as `front_cover`.
+    pub artwork_url: Option<String>,
+    /// Image embedded as the front cover when writing, replacing the one in the file.
+    #[serde(skip)]
+    pub front_cover: Option<Vec<u8>>,
+    /// Fields guessed from the file path rather than read from tags. They are only search terms,
+    /// count less when scoring and are never written unless a match replaces them.
+    #[serde(skip)]
+    pub inferred: Vec<&'static str>,
+    /// Items imd has no field for, e.g. TXXX frames or custom Vorbis comments, keyed by their
+    /// format specific key. They are left untouched in the file and copied into tags imd creates.
+    pub custom: BTreeMap<String, Vec<String>>,
 }
 
 impl SongMetadata {
-    pub fn read_metadata_from_audio_file(file_path: &PathBuf) -> SongMetadata {
+    pub fn read_metadata_from_audio_file(file_path: &Path) -> SongMetadata {
+        return SongMetadata::read_metadata_with_sources(file_path).0;
+    }
+
+    /// Reads every tag in the file and merges them field by field. The primary tag wins, the other
+    /// tags fill in the fields it lacks in `TAG_READ_PRECEDENCE` order. Also returns which tag
+    /// type each field was read from.
+    pub fn read_metadata_with_sources(file_path: &Path) -> (SongMetadata, FieldSources) {
 
         if !file_path.is_file() {
             panic!("ERROR: Path is not a file!");
@@ -35,155 +90,538 @@ impl SongMetadata {
             .read()
             .expect("ERROR: Failed to read file!");
 
-        let tag = match tagged_file.primary_tag() {
-            Some(primary_tag) => primary_tag,
-            None => tagged_file.first_tag().expect("ERROR: No tags found!"),
+        let tags = tags_by_precedence(&tagged_file);
+        if tags.is_empty() {
+            eprintln!("WARN: No tags found in {:?}", file_path);
+        }
+
+        let duration = tagged_file.properties().duration();
+
+        let mut merged = SongMetadata {
+            duration: Some(duration),
+            audio: Some(AudioProperties::from_tagged_file(&tagged_file)),
+            ..SongMetadata::default()
         };
+        let mut sources: FieldSources = Vec::new();
+        for tag in tags {
+            let tag_metadata = SongMetadata::from_tag(tag, duration);
 
-        let properties = tagged_file.properties();
-        let duration = properties.duration();
+            macro_rules! fill {
+                ($($field:ident),+) => {
+                    $(
+                        if merged.$field.is_none() && tag_metadata.$field.is_some() {
+                            merged.$field = tag_metadata.$field;
+                            sources.push((stringify!($field), tag.tag_type()));
+                        }
+                    )+
+                };
+            }
+            fill!(title, artists, album, album_artist, composer, genres, track_number, disc_number, year, comment, total_tracks, total_discs, is_compilation,
+                release_date, isrc, label, copyright, catalogue_number, barcode, sort_title, sort_artist, sort_album_artist, bpm, initial_key, lyrics, replay_gain);
+            for (key, values) in tag_metadata.custom {
+                merged.custom.entry(key).or_insert(values);
+            }
+        }
 
+        return (merged, sources);
+    }
+
+    fn from_tag(tag: &Tag, duration: Duration) -> SongMetadata {
         return SongMetadata {
             title: tag.title().map(|s| s.to_string()),
-            artist: tag.artist().map(|s| s.to_string()),
+            artists: string_list(tag, &ItemKey::TrackArtist),
             album: tag.album().map(|s| s.to_string()),
             album_artist: tag.get_string(&ItemKey::AlbumArtist).map(|s| s.to_string()),
             composer: tag.get_string(&ItemKey::Composer).map(|s| s.to_string()),
-            genre: tag.genre().map(|s| s.to_string()),
+            genres: string_list(tag, &ItemKey::Genre),
             track_number: tag.track().map(|s| s as u16),
             disc_number: tag.disk().map(|s| s as u16),
             year: tag.year().map(|s| s as u16),
             comment: tag.comment().map(|s| s.to_string()),
             duration: Some(duration),
+            audio: None,
             total_tracks: tag.track_total().map(|s| s as u16),
             total_discs: tag.disk_total().map(|s| s as u16),
-            is_compilation: match tag.get_string(&ItemKey::FlagCompilation) {
-                Some(s) => Some(s == "1"),
-                None => None,
-            }
+            is_compilation: tag.get_string(&ItemKey::FlagCompilation).map(|s| s == "1"),
+            release_date: tag.get_string(&ItemKey::ReleaseDate)
+                .or_else(|| tag.get_string(&ItemKey::Unknown(ID3V23_RELEASE_DATE.to_string())))
+                .map(|s| s.to_string()),
+            isrc: tag.get_string(&ItemKey::Isrc).map(|s| s.to_string()),
+            label: tag.get_string(&ItemKey::Label).map(|s| s.to_string()),
+            copyright: tag.get_string(&ItemKey::CopyrightMessage).map(|s| s.to_string()),
+            catalogue_number: tag.get_string(&ItemKey::CatalogNumber).map(|s| s.to_string()),
+            barcode: tag.get_string(&ItemKey::Barcode).map(|s| s.to_string()),
+            sort_title: tag.get_string(&ItemKey::TrackTitleSortOrder).map(|s| s.to_string()),
+            sort_artist: tag.get_string(&ItemKey::TrackArtistSortOrder).map(|s| s.to_string()),
+            sort_album_artist: tag.get_string(&ItemKey::AlbumArtistSortOrder).map(|s| s.to_string()),
+            // Formats store the tempo either as a whole number or as text that may have decimals.
+            bpm: tag.get_string(&ItemKey::IntegerBpm)
+                .or_else(|| tag.get_string(&ItemKey::Bpm))
+                .and_then(|s| s.trim().parse::<f64>().ok())
+                .map(|bpm| bpm.round() as u16),
+            initial_key: tag.get_string(&ItemKey::InitialKey).map(|s| s.to_string()),
+            lyrics: tag.get_string(&ItemKey::Lyrics).map(|s| s.to_string()),
+            replay_gain: ReplayGain::from_tag(tag),
+            artwork_url: None,
+            front_cover: None,
+            inferred: Vec::new(),
+            custom: read_custom_items(tag),
         };
     }
 
-    pub fn write_metadata_to_audio_file(&self, file_path: &PathBuf) {
-        let mut tagged_file = Probe::open(file_path)
-            .expect("ERROR: Bad path provided!")
-            .read()
-            .expect("ERROR: Failed to read file!");
+    /// Writes the tags to a temporary copy of the file, verifies them by reading the copy back and
+    /// then atomically replaces the original, so an interrupted or failed write never corrupts it.
+    pub fn write_metadata_to_audio_file(&self, file_path: &Path, writer_settings: &WriterSettings) {
+        let result = write_atomically(
+            file_path,
+            writer_settings.preserve_mtime,
+            |temp_path| self.write_tags(temp_path, writer_settings),
+            |temp_path| self.verify_tags(temp_path, writer_settings),
+        );
+
+        match result {
+            Ok(_) => println!("Metadata saved successfully!"),
+            Err(e) => panic!("ERROR: Failed to save metadata to file: {}", e),
+        }
+    }
+
+    /// Writes the fields to every tag selected by the writer settings, creating selected tags that
+    /// don't exist yet, and strips legacy tags first when asked to.
+    fn write_tags(&self, file_path: &Path, writer_settings: &WriterSettings) -> Result<(), String> {
+        let mut tagged_file = read_tagged_file(file_path)?;
 
-        let tag = match tagged_file.primary_tag_mut() {
-            Some(primary_tag) => primary_tag,
-            None => {
-                if let Some(first_tag) = tagged_file.first_tag_mut() {
-                    first_tag
-                } else {
-                    let tag_type = tagged_file.primary_tag_type();
-    
-                    eprintln!("WARN: No tags found, creating a new tag of type `{tag_type:?}`");
-                    tagged_file.insert_tag(Tag::new(tag_type));
-    
-                    tagged_file.primary_tag_mut().unwrap()
+        if writer_settings.strip_legacy_tags {
+            for tag_type in LEGACY_TAG_TYPES {
+                if tag_type != tagged_file.primary_tag_type() && tagged_file.remove(tag_type).is_some() {
+                    tag_type.remove_from_path(file_path)
+                        .map_err(|e| format!("Failed to remove {:?} tag: {:?}", tag_type, e))?;
                 }
-            },
+            }
+        }
+
+        let file_type = tagged_file.file_type();
+        let tag_types = select_tag_types(&tagged_file, writer_settings);
+        let previous_id3v2 = if tag_types.contains(&TagType::Id3v2) && writer_settings.id3v2.preserve_unknown_frames {
+            read_id3v2(file_path)?
+        } else {
+            None
         };
+        let mut write_options = WriteOptions::default();
+        if let Some(padding) = writer_settings.padding {
+            write_options = write_options.preferred_padding(padding);
+        }
+
+        if tagged_file.tags().is_empty() {
+            eprintln!("WARN: No tags found, creating a new tag of type `{:?}`", tagged_file.primary_tag_type());
+        }
+
+        for tag_type in tag_types.iter().copied() {
+            let created = tagged_file.tag(tag_type).is_none();
+            if created {
+                tagged_file.insert_tag(Tag::new(tag_type));
+            }
+            let tag = tagged_file.tag_mut(tag_type).expect("tag was just inserted");
+            self.apply_to_tag(tag, &writer_settings.multi_value_separator);
+            if let Some(replay_gain) = &self.replay_gain {
+                replay_gain.apply_to_tag(tag, file_type);
+            }
+            if created {
+                copy_custom_items(tag, &self.custom);
+            }
+            apply_custom_tag_rules(tag, &writer_settings.custom_tags);
+            let saved = if tag_type == TagType::Id3v2 {
+                // Saving the generic tag keeps one value per frame, converting it first keeps them all.
+                to_id3v2_tag(tag).save_to_path(file_path, write_options)
+            } else {
+                tag.save_to_path(file_path, write_options)
+            };
+            saved.map_err(|e| format!("Failed to save {:?} tag: {:?}", tag_type, e))?;
+        }
+
+        if tag_types.contains(&TagType::Id3v2) {
+            rewrite_id3v2(file_path, previous_id3v2.as_ref(), writer_settings)?;
+        }
+
+        return Ok(());
+    }
 
-        if self.title.is_some() {
-            tag.set_title(self.title.clone().unwrap());
+    fn apply_to_tag(&self, tag: &mut Tag, separator: &str) {
+        if let Some(title) = &self.title {
+            tag.set_title(title.clone());
         }
-        if self.artist.is_some() {
-            tag.set_artist(self.artist.clone().unwrap());
+        if let Some(artists) = &self.artists {
+            set_string_list(tag, ItemKey::TrackArtist, artists, separator);
         }
-        if self.album.is_some() {
-            tag.set_album(self.album.clone().clone().unwrap());
+        if let Some(album) = &self.album {
+            tag.set_album(album.clone());
         }
-        if self.album_artist.is_some() {
-            tag.insert(TagItem::new(ItemKey::AlbumArtist, ItemValue::Text(self.album_artist.clone().unwrap())));
+        if let Some(album_artist) = &self.album_artist {
+            tag.insert(TagItem::new(ItemKey::AlbumArtist, ItemValue::Text(album_artist.clone())));
         }
-        if self.composer.is_some() {
-            tag.insert(TagItem::new(ItemKey::Composer, ItemValue::Text(self.composer.clone().unwrap())));
+        if let Some(composer) = &self.composer {
+            tag.insert(TagItem::new(ItemKey::Composer, ItemValue::Text(composer.clone())));
         }
-        if self.genre.is_some() {
-            tag.set_genre(self.genre.clone().unwrap());
+        if let Some(genres) = &self.genres {
+            set_string_list(tag, ItemKey::Genre, genres, separator);
         }
-        if self.track_number.is_some() {
-            tag.set_track(self.track_number.clone().unwrap() as u32);
+        if let Some(track_number) = self.track_number {
+            tag.set_track(track_number as u32);
         }
-        if self.disc_number.is_some() {
-            tag.set_disk(self.disc_number.clone().unwrap() as u32);
+        if let Some(disc_number) = self.disc_number {
+            tag.set_disk(disc_number as u32);
         }
-        if self.year.is_some() {
-            tag.set_year(self.year.clone().unwrap() as u32);
+        if let Some(year) = self.year {
+            tag.set_year(year as u32);
         }
-        if self.comment.is_some() {
-            tag.set_comment(self.comment.clone().unwrap());
+        if let Some(comment) = &self.comment {
+            tag.set_comment(comment.clone());
         }
-        if self.total_tracks.is_some() {
-            tag.set_track_total(self.total_tracks.clone().unwrap() as u32);
+        if let Some(total_tracks) = self.total_tracks {
+            tag.set_track_total(total_tracks as u32);
         }
-        if self.total_discs.is_some() {
-            tag.set_disk_total(self.total_discs.clone().unwrap() as u32);
+        if let Some(total_discs) = self.total_discs {
+            tag.set_disk_total(total_discs as u32);
         }
-        if self.is_compilation.is_some() {
-            tag.insert(TagItem::new(ItemKey::FlagCompilation, ItemValue::Text(if self.is_compilation.unwrap() { "1" } else { "0" }.to_string())));
+        if let Some(is_compilation) = self.is_compilation {
+            tag.insert(TagItem::new(ItemKey::FlagCompilation, ItemValue::Text(if is_compilation { "1" } else { "0" }.to_string())));
         }
 
-        match tag.save_to_path(file_path, WriteOptions::default()) {
-            Ok(_) => println!("Metadata saved successfully!"),
-            Err(e) => panic!("ERROR: Failed to save metadata to file: {:?}", e),
+        let text_fields = [
+            (ItemKey::ReleaseDate, &self.release_date),
+            (ItemKey::Isrc, &self.isrc),
+            (ItemKey::Label, &self.label),
+            (ItemKey::CopyrightMessage, &self.copyright),
+            (ItemKey::CatalogNumber, &self.catalogue_number),
+            (ItemKey::Barcode, &self.barcode),
+            (ItemKey::TrackTitleSortOrder, &self.sort_title),
+            (ItemKey::TrackArtistSortOrder, &self.sort_artist),
+            (ItemKey::AlbumArtistSortOrder, &self.sort_album_artist),
+            (ItemKey::InitialKey, &self.initial_key),
+            (ItemKey::Lyrics, &self.lyrics),
+        ];
+        for (key, value) in text_fields {
+            if let Some(value) = value {
+                tag.insert(TagItem::new(key, ItemValue::Text(value.clone())));
+            }
+        }
+        if self.release_date.is_some() && tag.tag_type() == TagType::Id3v2 {
+            // The date an ID3v2.3 tag held is written as `TDRL` again.
+            tag.remove_key(&ItemKey::Unknown(ID3V23_RELEASE_DATE.to_string()));
+        }
+        if let Some(bpm) = self.bpm {
+            let key = if supports_key(tag.tag_type(), &ItemKey::IntegerBpm) { ItemKey::IntegerBpm } else { ItemKey::Bpm };
+            tag.insert(TagItem::new(key, ItemValue::Text(bpm.to_string())));
+        }
+        // Tag types without pictures, e.g. RIFF INFO, ignore it.
+        if let Some(mut picture) = self.front_cover.as_ref().and_then(|front_cover| Picture::from_reader(&mut front_cover.as_slice()).ok()) {
+            picture.set_pic_type(PictureType::CoverFront);
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.push_picture(picture);
         }
     }
 
+    /// Checks that every field set on `self` reads back unchanged from each written tag, as far as
+    /// the tag type can hold the field. ID3v1 truncates and drops values, so only its presence is checked.
+    fn verify_tags(&self, file_path: &Path, writer_settings: &WriterSettings) -> Result<(), String> {
+        let tagged_file = read_tagged_file(file_path)?;
+        let duration = tagged_file.properties().duration();
+        let file_type = tagged_file.file_type();
+        let mut mismatches: Vec<String> = Vec::new();
+
+        if writer_settings.strip_legacy_tags {
+            for tag_type in LEGACY_TAG_TYPES {
+                if tag_type != tagged_file.primary_tag_type() && tagged_file.tag(tag_type).is_some() {
+                    mismatches.push(format!("{:?} tag was not removed", tag_type));
+                }
+            }
+        }
+
+        let tag_types = select_tag_types(&tagged_file, writer_settings);
+        if tag_types.contains(&TagType::Id3v2) {
+            if let Err(e) = verify_id3v2(file_path, &writer_settings.id3v2) {
+                mismatches.push(e);
+            }
+        }
+
+        for tag_type in tag_types {
+            let tag = match tagged_file.tag(tag_type) {
+                Some(tag) => tag,
+                None => {
+                    mismatches.push(format!("{:?} tag is missing", tag_type));
+                    continue;
+                },
+            };
+            if tag_type == TagType::Id3v1 {
+                continue;
+            }
+            let written = SongMetadata::from_tag(tag, duration);
+
+            macro_rules! verify {
+                ($($field:ident => $key:expr),+) => {
+                    $(
+                        if self.$field.is_some() && supports_key(tag_type, &$key) && self.$field != written.$field {
+                            mismatches.push(format!("{:?} {} is {:?} instead of {:?}", tag_type, stringify!($field), written.$field, self.$field));
+                        }
+                    )+
+                };
+            }
+            verify!(
+                title => ItemKey::TrackTitle,
+                album => ItemKey::AlbumTitle,
+                album_artist => ItemKey::AlbumArtist,
+                composer => ItemKey::Composer,
+                track_number => ItemKey::TrackNumber,
+                disc_number => ItemKey::DiscNumber,
+                comment => ItemKey::Comment,
+                total_tracks => ItemKey::TrackTotal,
+                total_discs => ItemKey::DiscTotal,
+                is_compilation => ItemKey::FlagCompilation,
+                release_date => ItemKey::ReleaseDate,
+                isrc => ItemKey::Isrc,
+                label => ItemKey::Label,
+                copyright => ItemKey::CopyrightMessage,
+                catalogue_number => ItemKey::CatalogNumber,
+                barcode => ItemKey::Barcode,
+                sort_title => ItemKey::TrackTitleSortOrder,
+                sort_artist => ItemKey::TrackArtistSortOrder,
+                sort_album_artist => ItemKey::AlbumArtistSortOrder,
+                initial_key => ItemKey::InitialKey,
+                lyrics => ItemKey::Lyrics
+            );
+            // Formats without multi-value support hold the values joined into one.
+            let separator = &writer_settings.multi_value_separator;
+            for (field, key, expected, actual) in [
+                ("artists", ItemKey::TrackArtist, &self.artists, &written.artists),
+                ("genres", ItemKey::Genre, &self.genres, &written.genres),
+            ] {
+                let joined = |values: &Option<Vec<String>>| values.as_ref().map(|values| values.join(separator));
+                if expected.is_some() && supports_key(tag_type, &key) && joined(expected) != joined(actual) {
+                    mismatches.push(format!("{:?} {} is {:?} instead of {:?}", tag_type, field, actual, expected));
+                }
+            }
+            if self.bpm.is_some() && (supports_key(tag_type, &ItemKey::IntegerBpm) || supports_key(tag_type, &ItemKey::Bpm)) && self.bpm != written.bpm {
+                mismatches.push(format!("{:?} bpm is {:?} instead of {:?}", tag_type, written.bpm, self.bpm));
+            }
+            if let Some(replay_gain) = &self.replay_gain {
+                let matches = written.replay_gain.is_some_and(|written| replay_gain.matches(&written));
+                if ReplayGain::can_store(tag_type, file_type) && !matches {
+                    mismatches.push(format!("{:?} replay_gain is {:?} instead of {:?}", tag_type, written.replay_gain, self.replay_gain));
+                }
+            }
+            if self.year.is_some() && (supports_key(tag_type, &ItemKey::Year) || supports_key(tag_type, &ItemKey::RecordingDate)) && self.year != written.year {
+                mismatches.push(format!("{:?} year is {:?} instead of {:?}", tag_type, written.year, self.year));
+            }
+        }
+
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        return Err(format!("Written tags did not verify: {}", mismatches.join(", ")));
+    }
+
+    /// The artists joined into one name, e.g. for searching and naming files.
+    pub fn artist(&self) -> Option<String> {
+        return self.artists.as_ref().map(|artists| artists.join(" & "));
+    }
+
+    /// The first genre, e.g. for naming files.
+    pub fn genre(&self) -> Option<String> {
+        return self.genres.as_ref().and_then(|genres| genres.first().cloned());
+    }
+
+    /// Every field as a display label and value, empty when the field is not set.
+    pub fn display_fields(&self) -> Vec<(&'static str, String)> {
+        fn text<T: ToString>(value: &Option<T>) -> String {
+            return value.as_ref().map(|v| v.to_string()).unwrap_or_default();
+        }
+        fn list(values: &Option<Vec<String>>) -> String {
+            return values.as_ref().map(|values| values.join("; ")).unwrap_or_default();
+        }
+
+        return vec![
+            ("Title", text(&self.title)),
+            ("Artists", list(&self.artists)),
+            ("Album", text(&self.album)),
+            ("Album Artist", text(&self.album_artist)),
+            ("Composer", text(&self.composer)),
+            ("Genres", list(&self.genres)),
+            ("Track Number", text(&self.track_number)),
+            ("Disc Number", text(&self.disc_number)),
+            ("Year", text(&self.year)),
+            ("Comment", text(&self.comment)),
+            ("Duration", self.duration.map(|d| format!("{:?}", d)).unwrap_or_default()),
+            ("Audio", self.audio.as_ref().map(|audio| audio.describe()).unwrap_or_default()),
+            ("Total Tracks", text(&self.total_tracks)),
+            ("Total Discs", text(&self.total_discs)),
+            ("Is Compilation", text(&self.is_compilation)),
+            ("Release Date", text(&self.release_date)),
+            ("ISRC", text(&self.isrc)),
+            ("Label", text(&self.label)),
+            ("Copyright", text(&self.copyright)),
+            ("Catalogue No.", text(&self.catalogue_number)),
+            ("Barcode", text(&self.barcode)),
+            ("Sort Title", text(&self.sort_title)),
+            ("Sort Artist", text(&self.sort_artist)),
+            ("Sort Album Artist", text(&self.sort_album_artist)),
+            ("BPM", text(&self.bpm)),
+            ("Initial Key", text(&self.initial_key)),
+            ("Lyrics", text(&self.lyrics)),
+            ("ReplayGain", self.replay_gain.map(|replay_gain| replay_gain.describe()).unwrap_or_default()),
+        ];
+    }
+
     pub fn pretty_print(&self) {
-        let track_number: String = match self.track_number.clone() {
-            Some(n) => n.to_string(),
-            None => "".to_string(),
-        };
-        let disc_number: String = match self.disc_number.clone() {
-            Some(n) => n.to_string(),
-            None => "".to_string(),
-        };
-        let year: String = match self.year.clone() {
-            Some(n) => n.to_string(),
-            None => "".to_string(),
-        };
-        let duration: String = match self.duration.clone() {
-            Some(d) => format!("{:?}", d),
-            None => "".to_string(),
-        };
-        let total_tracks: String = match self.total_tracks.clone() {
-            Some(n) => n.to_string(),
-            None => "".to_string(),
-        };
-        let total_discs: String = match self.total_discs.clone() {
-            Some(n) => n.to_string(),
-            None => "".to_string(),
-        };
-        let is_compilation: String = match self.is_compilation.clone() {
-            Some(b) => b.to_string(),
-            None => "".to_string(),
+        for (label, value) in self.display_fields() {
+            println!("{:<20}{:?}", format!("{}:", label), value);
+        }
+        if !self.custom.is_empty() {
+            println!("Custom tags:");
+            for (key, values) in &self.custom {
+                println!("  {:<18}{:?}", format!("{}:", key), values.join("; "));
+            }
+        }
+    }
+}
+
+/// Every value stored under `key`, or `None` when there are none.
+fn string_list(tag: &Tag, key: &ItemKey) -> Option<Vec<String>> {
+    let values: Vec<String> = tag.get_strings(key).map(|s| s.to_string()).collect();
+    if values.is_empty() {
+        return None;
+    }
+    return Some(values);
+}
+
+/// Stores each value separately where the tag type supports multiple values, otherwise joined with
+/// `separator`. ID3v1 genres come from a fixed list, so only the first genre is kept there.
+fn set_string_list(tag: &mut Tag, key: ItemKey, values: &[String], separator: &str) {
+    tag.remove_key(&key);
+    let tag_type = tag.tag_type();
+    if supports_multiple_values(tag_type) {
+        for value in values {
+            tag.push(TagItem::new(key.clone(), ItemValue::Text(value.clone())));
+        }
+    } else if tag_type == TagType::Id3v1 && key == ItemKey::Genre {
+        if let Some(genre) = values.first() {
+            tag.insert(TagItem::new(key, ItemValue::Text(genre.clone())));
+        }
+    } else {
+        tag.insert(TagItem::new(key, ItemValue::Text(values.join(separator))));
+    }
+}
+
+/// Converts a generic tag to ID3v2. lofty's conversion turns lyrics into an invalid text frame, so
+/// they are added as a proper USLT frame afterwards.
+fn to_id3v2_tag(tag: &Tag) -> Id3v2Tag {
+    let mut tag = tag.clone();
+    let lyrics = tag.take_strings(&ItemKey::Lyrics).next();
+    let mut id3v2_tag = Id3v2Tag::from(tag);
+    if let Some(lyrics) = lyrics {
+        let frame = UnsynchronizedTextFrame {
+            encoding: TextEncoding::UTF8,
+            language: *b"XXX",
+            description: String::new(),
+            content: lyrics,
         };
-        println!("Title:          {:?}", self.title.clone().unwrap_or("".to_string()));
-        println!("Artist:         {:?}", self.artist.clone().unwrap_or("".to_string()));
-        println!("Album:          {:?}", self.album.clone().unwrap_or("".to_string()));
-        println!("Album Artist:   {:?}", self.album_artist.clone().unwrap_or("".to_string()));
-        println!("Composer:       {:?}", self.composer.clone().unwrap_or("".to_string()));
-        println!("Genre:          {:?}", self.genre.clone().unwrap_or("".to_string()));
-        println!("Track Number:   {:?}", track_number);
-        println!("Disc Number:    {:?}", disc_number);
-        println!("Year:           {:?}", year);
-        println!("Comment:        {:?}", self.comment.clone().unwrap_or("".to_string()));
-        println!("Duration:       {:?}", duration);
-        println!("Total Tracks:   {:?}", total_tracks);
-        println!("Total Discs:    {:?}", total_discs);
-        println!("Is Compilation: {:?}", is_compilation);
+        id3v2_tag.insert(Frame::new("USLT", frame, FrameFlags::default()).expect("USLT is a valid frame ID"));
     }
+    return id3v2_tag;
+}
+
+fn read_tagged_file(file_path: &Path) -> Result<TaggedFile, String> {
+    return Probe::open(file_path)
+        .map_err(|e| format!("Bad path provided: {:?}", e))?
+        .read()
+        .map_err(|e| format!("Failed to read file: {:?}", e));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::metadata::tag_target::TagTarget;
+    use crate::test_support::TestAudioFile;
 
     #[test]
     fn tests_work() {
         assert_eq!(2 + 2, 4);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_extended_fields_round_trip() {
+        let audio_file = TestAudioFile::mp3("extended-fields");
+        let metadata = SongMetadata {
+            title: Some("Title".to_string()),
+            release_date: Some("2005-03-01".to_string()),
+            isrc: Some("GBAYE0500001".to_string()),
+            label: Some("Label".to_string()),
+            copyright: Some("2005 Label".to_string()),
+            catalogue_number: Some("CAT 001".to_string()),
+            barcode: Some("0123456789012".to_string()),
+            sort_title: Some("Title, The".to_string()),
+            sort_artist: Some("Artist, The".to_string()),
+            sort_album_artist: Some("Album Artist, The".to_string()),
+            bpm: Some(128),
+            initial_key: Some("Am".to_string()),
+            lyrics: Some("First line\nSecond line".to_string()),
+            ..SongMetadata::default()
+        };
+        metadata.write_metadata_to_audio_file(audio_file.path(), &WriterSettings::default());
+
+        let read = SongMetadata::read_metadata_from_audio_file(audio_file.path());
+        assert_eq!(metadata.release_date, read.release_date);
+        assert_eq!(metadata.isrc, read.isrc);
+        assert_eq!(metadata.label, read.label);
+        assert_eq!(metadata.copyright, read.copyright);
+        assert_eq!(metadata.catalogue_number, read.catalogue_number);
+        assert_eq!(metadata.barcode, read.barcode);
+        assert_eq!(metadata.sort_title, read.sort_title);
+        assert_eq!(metadata.sort_artist, read.sort_artist);
+        assert_eq!(metadata.sort_album_artist, read.sort_album_artist);
+        assert_eq!(metadata.bpm, read.bpm);
+        assert_eq!(metadata.initial_key, read.initial_key);
+        assert_eq!(metadata.lyrics, read.lyrics);
+    }
+
+    #[test]
+    fn test_front_cover_replaces_existing() {
+        let audio_file = TestAudioFile::mp3("front-cover");
+        let png = |marker: u8| [b"\x89PNG\r\n\x1a\n".as_slice(), &[marker; 8]].concat();
+        for marker in [1, 2] {
+            SongMetadata {
+                front_cover: Some(png(marker)),
+                ..SongMetadata::default()
+            }.write_metadata_to_audio_file(audio_file.path(), &WriterSettings::default());
+        }
+
+        let tagged_file = read_tagged_file(audio_file.path()).unwrap();
+        let pictures = tagged_file.primary_tag().unwrap().pictures();
+        assert_eq!(1, pictures.len());
+        assert_eq!(PictureType::CoverFront, pictures[0].pic_type());
+        assert_eq!(png(2), pictures[0].data());
+    }
+
+    #[test]
+    fn test_multi_value_artists_and_genres() {
+        let audio_file = TestAudioFile::wav("multi-value");
+        let metadata = SongMetadata {
+            artists: Some(vec!["First".to_string(), "Second".to_string()]),
+            genres: Some(vec!["Soul".to_string(), "Funk".to_string()]),
+            ..SongMetadata::default()
+        };
+        let writer_settings = WriterSettings {
+            tag_target: TagTarget::Types,
+            tag_types: vec!["id3v2".to_string(), "riff_info".to_string()],
+            multi_value_separator: " / ".to_string(),
+            ..WriterSettings::default()
+        };
+        metadata.write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+
+        let tagged_file = read_tagged_file(audio_file.path()).unwrap();
+        let id3v2 = tagged_file.tag(TagType::Id3v2).unwrap();
+        assert_eq!(vec!["First", "Second"], id3v2.get_strings(&ItemKey::TrackArtist).collect::<Vec<&str>>());
+        assert_eq!(vec!["Soul", "Funk"], id3v2.get_strings(&ItemKey::Genre).collect::<Vec<&str>>());
+        let riff_info = tagged_file.tag(TagType::RiffInfo).unwrap();
+        assert_eq!(vec!["First / Second"], riff_info.get_strings(&ItemKey::TrackArtist).collect::<Vec<&str>>());
+        assert_eq!(Some("First & Second".to_string()), SongMetadata::read_metadata_from_audio_file(audio_file.path()).artist());
+    }
+}
@@ -0,0 +1,232 @@
+use lofty::file::TaggedFile;
+use lofty::prelude::*;
+use lofty::tag::{Tag, TagType};
+use serde::{Deserialize, Serialize};
+use crate::settings::WriterSettings;
+
+/// Keys an ID3v1 tag can hold, everything else is dropped when it is written.
+const ID3V1_KEYS: [ItemKey; 7] = [
+    ItemKey::TrackTitle,
+    ItemKey::TrackArtist,
+    ItemKey::AlbumTitle,
+    ItemKey::Year,
+    ItemKey::Comment,
+    ItemKey::TrackNumber,
+    ItemKey::Genre,
+];
+
+/// Tag types that are only kept around for old players and are often left stale by other taggers.
+pub const LEGACY_TAG_TYPES: [TagType; 2] = [TagType::Id3v1, TagType::Ape];
+
+/// Order in which tags fill in fields missing from the primary tag. Legacy tags come last, they
+/// hold the fewest fields and truncate values.
+const TAG_READ_PRECEDENCE: [TagType; 7] = [
+    TagType::Id3v2,
+    TagType::Mp4Ilst,
+    TagType::VorbisComments,
+    TagType::AiffText,
+    TagType::RiffInfo,
+    TagType::Ape,
+    TagType::Id3v1,
+];
+
+/// Which of a file's tags are written.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagTarget {
+    /// Only the format's primary tag, e.g. ID3v2 for MP3.
+    Primary,
+    /// Every tag present in the file, so players reading different tags agree.
+    AllExisting,
+    /// The tag types listed in `tag_types`, created when missing.
+    Types,
+}
+
+impl TagTarget {
+    pub fn from_name(name: &str) -> Option<TagTarget> {
+        return match name {
+            "primary" => Some(TagTarget::Primary),
+            "all_existing" => Some(TagTarget::AllExisting),
+            "types" => Some(TagTarget::Types),
+            _ => None,
+        };
+    }
+}
+
+/// The tag types to write in `tagged_file` according to the writer settings.
+pub fn select_tag_types(tagged_file: &TaggedFile, writer_settings: &WriterSettings) -> Vec<TagType> {
+    let primary_tag_type = tagged_file.primary_tag_type();
+
+    return match writer_settings.tag_target {
+        TagTarget::Primary => vec![primary_tag_type],
+        TagTarget::AllExisting => {
+            let existing: Vec<TagType> = tagged_file.tags().iter().map(|tag| tag.tag_type()).collect();
+            if existing.is_empty() {
+                vec![primary_tag_type]
+            } else {
+                existing
+            }
+        },
+        TagTarget::Types => writer_settings.tag_types.iter()
+            .map(|name| tag_type_from_name(name)
+                .unwrap_or_else(|| panic!("ERROR: Unknown tag type {:?} in writer.tag_types", name)))
+            .filter(|tag_type| {
+                let supported = tagged_file.supports_tag_type(*tag_type);
+                if !supported {
+                    eprintln!("WARN: {:?} files can not hold {:?} tags, skipping", tagged_file.file_type(), tag_type);
+                }
+                supported
+            })
+            .collect(),
+    };
+}
+
+/// The file's tags, primary tag first and the rest in `TAG_READ_PRECEDENCE` order.
+pub fn tags_by_precedence(tagged_file: &TaggedFile) -> Vec<&Tag> {
+    let primary_tag_type = tagged_file.primary_tag_type();
+    let mut tags: Vec<&Tag> = tagged_file.tags().iter().collect();
+    tags.sort_by_key(|tag| {
+        if tag.tag_type() == primary_tag_type {
+            return 0;
+        }
+        return 1 + TAG_READ_PRECEDENCE.iter().position(|tag_type| *tag_type == tag.tag_type()).unwrap_or(TAG_READ_PRECEDENCE.len());
+    });
+    return tags;
+}
+
+/// Whether a tag of `tag_type` can store `key`.
+pub fn supports_key(tag_type: TagType, key: &ItemKey) -> bool {
+    if tag_type == TagType::Id3v1 {
+        return ID3V1_KEYS.contains(key);
+    }
+    return key.map_key(tag_type, false).is_some();
+}
+
+/// Whether a tag of `tag_type` can hold several values for one key, e.g. two ARTIST comments. ID3v2
+/// multi-values are joined again when writing ID3v2.3.
+pub fn supports_multiple_values(tag_type: TagType) -> bool {
+    return matches!(tag_type, TagType::Id3v2 | TagType::VorbisComments);
+}
+
+pub fn tag_type_name(tag_type: TagType) -> &'static str {
+    return match tag_type {
+        TagType::Ape => "ape",
+        TagType::Id3v1 => "id3v1",
+        TagType::Id3v2 => "id3v2",
+        TagType::Mp4Ilst => "mp4_ilst",
+        TagType::VorbisComments => "vorbis_comments",
+        TagType::RiffInfo => "riff_info",
+        TagType::AiffText => "aiff_text",
+        _ => "unknown",
+    };
+}
+
+pub fn tag_type_from_name(name: &str) -> Option<TagType> {
+    return match name {
+        "ape" => Some(TagType::Ape),
+        "id3v1" => Some(TagType::Id3v1),
+        "id3v2" => Some(TagType::Id3v2),
+        "mp4_ilst" => Some(TagType::Mp4Ilst),
+        "vorbis_comments" => Some(TagType::VorbisComments),
+        "riff_info" => Some(TagType::RiffInfo),
+        "aiff_text" => Some(TagType::AiffText),
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lofty::config::WriteOptions;
+    use lofty::probe::Probe;
+    use crate::metadata::song_metadata::SongMetadata;
+    use crate::test_support::TestAudioFile;
+
+    fn read_tag_titles(path: &std::path::Path) -> Vec<(TagType, Option<String>)> {
+        let tagged_file = Probe::open(path).unwrap().read().unwrap();
+        return tagged_file.tags().iter()
+            .map(|tag| (tag.tag_type(), tag.title().map(|title| title.to_string())))
+            .collect();
+    }
+
+    fn title_metadata(title: &str) -> SongMetadata {
+        return SongMetadata {
+            title: Some(title.to_string()),
+            ..SongMetadata::default()
+        };
+    }
+
+    #[test]
+    fn test_write_specific_tag_types() {
+        let audio_file = TestAudioFile::wav("tag-types");
+        let writer_settings = WriterSettings {
+            tag_target: TagTarget::Types,
+            tag_types: vec!["id3v2".to_string(), "riff_info".to_string()],
+            ..WriterSettings::default()
+        };
+        title_metadata("Both").write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+
+        let mut titles = read_tag_titles(audio_file.path());
+        titles.sort_by_key(|(tag_type, _)| tag_type_name(*tag_type));
+        assert_eq!(vec![
+            (TagType::Id3v2, Some("Both".to_string())),
+            (TagType::RiffInfo, Some("Both".to_string())),
+        ], titles);
+
+        // All existing is the default, both tags stay in sync.
+        title_metadata("Synced").write_metadata_to_audio_file(audio_file.path(), &WriterSettings::default());
+        assert!(read_tag_titles(audio_file.path()).iter().all(|(_, title)| title.as_deref() == Some("Synced")));
+    }
+
+    #[test]
+    fn test_strip_legacy_tags() {
+        let audio_file = TestAudioFile::mp3("strip-legacy");
+        let mut id3v1 = Tag::new(TagType::Id3v1);
+        id3v1.set_title("Stale".to_string());
+        id3v1.save_to_path(audio_file.path(), WriteOptions::default()).unwrap();
+        let mut id3v2 = Tag::new(TagType::Id3v2);
+        id3v2.set_title("Stale".to_string());
+        id3v2.save_to_path(audio_file.path(), WriteOptions::default()).unwrap();
+
+        title_metadata("Synced").write_metadata_to_audio_file(audio_file.path(), &WriterSettings::default());
+        let mut titles = read_tag_titles(audio_file.path());
+        titles.sort_by_key(|(tag_type, _)| tag_type_name(*tag_type));
+        assert_eq!(vec![
+            (TagType::Id3v1, Some("Synced".to_string())),
+            (TagType::Id3v2, Some("Synced".to_string())),
+        ], titles);
+
+        let writer_settings = WriterSettings {
+            strip_legacy_tags: true,
+            ..WriterSettings::default()
+        };
+        title_metadata("Stripped").write_metadata_to_audio_file(audio_file.path(), &writer_settings);
+        assert_eq!(vec![(TagType::Id3v2, Some("Stripped".to_string()))], read_tag_titles(audio_file.path()));
+    }
+
+    #[test]
+    fn test_read_merges_all_tags() {
+        let audio_file = TestAudioFile::mp3("merge-tags");
+        let mut id3v1 = Tag::new(TagType::Id3v1);
+        id3v1.set_title("Legacy Title".to_string());
+        id3v1.set_artist("Legacy Artist".to_string());
+        id3v1.save_to_path(audio_file.path(), WriteOptions::default()).unwrap();
+        let mut ape = Tag::new(TagType::Ape);
+        ape.set_album("Ape Album".to_string());
+        ape.set_artist("Ape Artist".to_string());
+        ape.save_to_path(audio_file.path(), WriteOptions::default()).unwrap();
+        let mut id3v2 = Tag::new(TagType::Id3v2);
+        id3v2.set_artist("Artist".to_string());
+        id3v2.save_to_path(audio_file.path(), WriteOptions::default()).unwrap();
+
+        let (metadata, sources) = SongMetadata::read_metadata_with_sources(audio_file.path());
+        assert_eq!(Some("Legacy Title".to_string()), metadata.title);
+        assert_eq!(Some(vec!["Artist".to_string()]), metadata.artists);
+        assert_eq!(Some("Ape Album".to_string()), metadata.album);
+        assert_eq!(vec![
+            ("artists", TagType::Id3v2),
+            ("album", TagType::Ape),
+            ("title", TagType::Id3v1),
+        ], sources);
+    }
+}
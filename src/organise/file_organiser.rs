@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::metadata::song_metadata::SongMetadata;
+use super::path_template::PathTemplate;
+
+const MAX_COLLISION_SUFFIX: u32 = 999;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrganiseMode {
+    Copy,
+    Move,
+    Hardlink,
+}
+
+impl OrganiseMode {
+    pub fn from_name(name: &str) -> Option<OrganiseMode> {
+        return match name {
+            "copy" => Some(OrganiseMode::Copy),
+            "move" => Some(OrganiseMode::Move),
+            "hardlink" => Some(OrganiseMode::Hardlink),
+            _ => None,
+        };
+    }
+}
+
+pub struct FileOrganiser {
+    template: PathTemplate,
+    destination_dir: PathBuf,
+    mode: OrganiseMode,
+    dry_run: bool,
+}
+
+impl FileOrganiser {
+    pub fn new(template: PathTemplate, destination_dir: PathBuf, mode: OrganiseMode, dry_run: bool) -> FileOrganiser {
+        FileOrganiser {
+            template,
+            destination_dir,
+            mode,
+            dry_run,
+        }
+    }
+
+    /// Places `source` under the destination directory at the path rendered from `song_metadata`,
+    /// returning where the file ended up (or would end up for a dry run).
+    pub fn organise(&self, source: &Path, song_metadata: &SongMetadata) -> PathBuf {
+        let extension = source.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let target = self.destination_dir.join(self.template.render(song_metadata, &extension));
+
+        if is_same_file(source, &target) {
+            println!("File is already organised: {:?}", source);
+            return target;
+        }
+        let target = resolve_collision(&target);
+
+        if self.dry_run {
+            println!("[dry run] {:?}: {:?} -> {:?}", self.mode, source, target);
+            return target;
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .unwrap_or_else(|e| panic!("ERROR: Failed to create directory {:?}: {}", parent, e));
+        }
+        let result = match self.mode {
+            OrganiseMode::Copy => fs::copy(source, &target).map(|_| ()),
+            OrganiseMode::Move => move_file(source, &target),
+            OrganiseMode::Hardlink => fs::hard_link(source, &target),
+        };
+        match result {
+            Ok(_) => println!("{:?}: {:?} -> {:?}", self.mode, source, target),
+            Err(e) => panic!("ERROR: Failed to organise {:?} to {:?}: {}", source, target, e),
+        }
+
+        return target;
+    }
+}
+
+/// Appends " (2)", " (3)", ... to the file stem until the path does not exist.
+pub fn resolve_collision(target: &Path) -> PathBuf {
+    if !target.exists() {
+        return target.to_path_buf();
+    }
+
+    let stem = target.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = target.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    for suffix in 2..=MAX_COLLISION_SUFFIX {
+        let candidate = target.with_file_name(format!("{} ({}){}", stem, suffix, extension));
+        if !candidate.exists() {
+            return candidate;
+        }
+    }
+    panic!("ERROR: Too many files colliding with {:?}", target);
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    return match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    };
+}
+
+/// Renames the file, falling back to copy and delete when the target is on another filesystem.
+pub fn move_file(source: &Path, target: &Path) -> io::Result<()> {
+    if fs::rename(source, target).is_ok() {
+        return Ok(());
+    }
+    fs::copy(source, target)?;
+    return fs::remove_file(source);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_collision() {
+        let dir = std::env::temp_dir().join(format!("imd-collision-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("01 Song.mp3");
+        assert_eq!(target, resolve_collision(&target));
+
+        fs::write(&target, b"").unwrap();
+        fs::write(dir.join("01 Song (2).mp3"), b"").unwrap();
+        assert_eq!(dir.join("01 Song (3).mp3"), resolve_collision(&target));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,2 @@
+pub mod path_template;
+pub mod file_organiser;
@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use crate::metadata::song_metadata::SongMetadata;
+
+const UNKNOWN_VALUE: &str = "Unknown";
+const ILLEGAL_CHARACTERS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A path template such as `{album_artist}/{year} - {album}/{disc}-{track:02} {title}.{ext}`.
+///
+/// Each `/` separated part of the template becomes one path component. Placeholders may carry a
+/// zero padded width, e.g. `{track:02}`. Missing text values render as "Unknown", with
+/// `album_artist` falling back to `artist`, and missing numbers render as 0 (or 1 for `disc`).
+#[derive(Debug)]
+pub struct PathTemplate {
+    components: Vec<Vec<TemplatePart>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Placeholder { field: TemplateField, width: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TemplateField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Composer,
+    Genre,
+    Year,
+    Track,
+    Disc,
+    TotalTracks,
+    TotalDiscs,
+    Ext,
+}
+
+impl TemplateField {
+    fn from_name(name: &str) -> Option<TemplateField> {
+        return match name {
+            "title" => Some(TemplateField::Title),
+            "artist" => Some(TemplateField::Artist),
+            "album" => Some(TemplateField::Album),
+            "album_artist" => Some(TemplateField::AlbumArtist),
+            "composer" => Some(TemplateField::Composer),
+            "genre" => Some(TemplateField::Genre),
+            "year" => Some(TemplateField::Year),
+            "track" => Some(TemplateField::Track),
+            "disc" => Some(TemplateField::Disc),
+            "total_tracks" => Some(TemplateField::TotalTracks),
+            "total_discs" => Some(TemplateField::TotalDiscs),
+            "ext" => Some(TemplateField::Ext),
+            _ => None,
+        };
+    }
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> PathTemplate {
+        let components = template.split('/')
+            .filter(|component| !component.is_empty())
+            .map(parse_component)
+            .collect::<Vec<Vec<TemplatePart>>>();
+
+        if components.is_empty() {
+            panic!("ERROR: Naming template {:?} is empty", template);
+        }
+
+        return PathTemplate { components };
+    }
+
+    /// Renders the template to a relative path, sanitizing every component for the filesystem.
+    pub fn render(&self, song_metadata: &SongMetadata, extension: &str) -> PathBuf {
+        return self.components.iter()
+            .map(|parts| {
+                let component = parts.iter()
+                    .map(|part| match part {
+                        TemplatePart::Literal(text) => text.clone(),
+                        TemplatePart::Placeholder { field, width } => render_field(*field, *width, song_metadata, extension),
+                    })
+                    .collect::<String>();
+                sanitize_path_component(&component)
+            })
+            .collect::<PathBuf>();
+    }
+}
+
+fn parse_component(component: &str) -> Vec<TemplatePart> {
+    let mut parts: Vec<TemplatePart> = Vec::new();
+    let mut rest = component;
+
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(TemplatePart::Literal(rest[..start].to_string()));
+        }
+        let end = rest[start..].find('}')
+            .map(|end| start + end)
+            .unwrap_or_else(|| panic!("ERROR: Unclosed placeholder in naming template component {:?}", component));
+        let placeholder = &rest[start + 1..end];
+        let (name, width) = match placeholder.split_once(':') {
+            Some((name, width)) => (name, width.parse::<usize>()
+                .unwrap_or_else(|_| panic!("ERROR: Invalid width in naming template placeholder {:?}", placeholder))),
+            None => (placeholder, 0),
+        };
+        let field = TemplateField::from_name(name)
+            .unwrap_or_else(|| panic!("ERROR: Unknown naming template placeholder {:?}", name));
+        parts.push(TemplatePart::Placeholder { field, width });
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest.to_string()));
+    }
+
+    return parts;
+}
+
+fn render_field(field: TemplateField, width: usize, song_metadata: &SongMetadata, extension: &str) -> String {
+    let text = |value: &Option<String>| value.clone().unwrap_or_else(|| UNKNOWN_VALUE.to_string());
+    let number = |value: Option<u16>, default: u16| format!("{:0width$}", value.unwrap_or(default), width = width);
+
+    return match field {
+        TemplateField::Title => text(&song_metadata.title),
+        TemplateField::Artist => text(&song_metadata.artist()),
+        TemplateField::Album => text(&song_metadata.album),
+        TemplateField::AlbumArtist => text(&song_metadata.album_artist.clone().or(song_metadata.artist())),
+        TemplateField::Composer => text(&song_metadata.composer),
+        TemplateField::Genre => text(&song_metadata.genre()),
+        TemplateField::Year => number(song_metadata.year, 0),
+        TemplateField::Track => number(song_metadata.track_number, 0),
+        TemplateField::Disc => number(song_metadata.disc_number, 1),
+        TemplateField::TotalTracks => number(song_metadata.total_tracks, 0),
+        TemplateField::TotalDiscs => number(song_metadata.total_discs, 1),
+        TemplateField::Ext => extension.to_string(),
+    };
+}
+
+/// Replaces characters that are illegal in file names on common filesystems and avoids names
+/// that Windows reserves or silently alters.
+pub fn sanitize_path_component(component: &str) -> String {
+    let replaced = component.chars()
+        .map(|c| if ILLEGAL_CHARACTERS.contains(&c) || c.is_control() { '_' } else { c })
+        .collect::<String>();
+    let trimmed = replaced.trim().trim_end_matches('.').trim_end();
+
+    if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
+        return "_".to_string();
+    }
+    let stem = trimmed.split('.').next().unwrap_or(trimmed);
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+        return format!("_{}", trimmed);
+    }
+    return trimmed.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_metadata() -> SongMetadata {
+        SongMetadata {
+            title: Some("What's Going On?".to_string()),
+            artists: Some(vec!["Marvin Gaye".to_string()]),
+            album: Some("What's Going On".to_string()),
+            album_artist: None,
+            composer: None,
+            genres: None,
+            track_number: Some(1),
+            disc_number: None,
+            year: Some(1971),
+            comment: None,
+            duration: None,
+            total_tracks: Some(9),
+            total_discs: None,
+            is_compilation: None,
+            ..SongMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let template = PathTemplate::parse("{album_artist}/{year} - {album}/{disc}-{track:02} {title}.{ext}");
+        let expected: PathBuf = ["Marvin Gaye", "1971 - What's Going On", "1-01 What's Going On_.m4a"].iter().collect();
+        assert_eq!(expected, template.render(&test_metadata(), "m4a"));
+    }
+
+    #[test]
+    fn test_render_missing_values() {
+        let template = PathTemplate::parse("{genre}/{composer}/{track:03}");
+        let expected: PathBuf = ["Unknown", "Unknown", "001"].iter().collect();
+        assert_eq!(expected, template.render(&test_metadata(), "m4a"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown naming template placeholder \"bitrate\"")]
+    fn test_parse_unknown_placeholder() {
+        PathTemplate::parse("{bitrate}/{title}");
+    }
+
+    #[test]
+    fn test_sanitize_path_component() {
+        // input, expected
+        let test_cases = vec![
+            ("AC/DC", "AC_DC"),
+            ("Why? <Live>", "Why_ _Live_"),
+            ("Trailing dots...", "Trailing dots"),
+            ("..", "_"),
+            ("", "_"),
+            ("con", "_con"),
+            ("Nul.mp3", "_Nul.mp3"),
+            ("Console", "Console"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(expected, sanitize_path_component(input));
+        }
+    }
+}
@@ -0,0 +1,538 @@
+pub mod view;
+
+use std::path::PathBuf;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crate::metadata::metadata_comparator::MetadataComparator;
+use crate::metadata::metadata_fixer::combine_metadata;
+use crate::metadata::song_metadata::SongMetadata;
+use crate::settings::Settings;
+
+/// Fields that can be edited before accepting, with their display labels.
+pub const EDITABLE_FIELDS: [(&str, &str); 10] = [
+    ("title", "Title"),
+    ("artists", "Artists"),
+    ("album", "Album"),
+    ("album_artist", "Album Artist"),
+    ("composer", "Composer"),
+    ("genres", "Genres"),
+    ("track_number", "Track Number"),
+    ("total_tracks", "Total Tracks"),
+    ("disc_number", "Disc Number"),
+    ("year", "Year"),
+];
+/// Separates multiple artists or genres while editing.
+const LIST_SEPARATOR: &str = "; ";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItemStatus {
+    Pending,
+    Written,
+    Skipped,
+    Failed(String),
+}
+
+/// A provider result ranked against a queued file.
+pub struct Candidate {
+    pub metadata: SongMetadata,
+    pub score: f64,
+    /// Each part of the score with its weight, e.g. `("title", 0.93, 1.0)`.
+    pub breakdown: [(&'static str, f64, f64); 3],
+}
+
+impl Candidate {
+    /// Candidates ranked best first, as scored by the matcher.
+    pub fn rank(current: &SongMetadata, ranked: Vec<(SongMetadata, f64)>, settings: &Settings) -> Vec<Candidate> {
+        return ranked.into_iter()
+            .map(|(metadata, score)| Candidate {
+                breakdown: MetadataComparator::new(current.clone(), metadata.clone(), settings.scoring.clone()).get_score_breakdown(),
+                metadata,
+                score,
+            })
+            .collect();
+    }
+}
+
+/// A file waiting for review.
+pub struct ReviewItem {
+    pub path: PathBuf,
+    pub current: SongMetadata,
+    /// `None` until the providers have been searched for the file.
+    pub candidates: Option<Vec<Candidate>>,
+    pub selected_candidate: usize,
+    /// Values typed over the result, by field name.
+    pub edits: Vec<(&'static str, String)>,
+    pub marked: bool,
+    pub status: ItemStatus,
+}
+
+impl ReviewItem {
+    pub fn new(path: PathBuf, current: SongMetadata) -> ReviewItem {
+        ReviewItem {
+            path,
+            current,
+            candidates: None,
+            selected_candidate: 0,
+            edits: Vec::new(),
+            marked: false,
+            status: ItemStatus::Pending,
+        }
+    }
+
+    pub fn candidate(&self) -> Option<&Candidate> {
+        return self.candidates.as_ref().and_then(|candidates| candidates.get(self.selected_candidate));
+    }
+
+    pub fn best_score(&self) -> Option<f64> {
+        return self.candidates.as_ref().and_then(|candidates| candidates.first()).map(|candidate| candidate.score);
+    }
+
+    /// The tags accepting would write: the selected candidate merged into the current tags like
+    /// a match, then the edits. Without a candidate only the edits are applied, and values guessed
+    /// from the file path are left out when writing.
+    pub fn result(&self, settings: &Settings) -> SongMetadata {
+        let mut result = match self.candidate() {
+            Some(candidate) => combine_metadata(&self.current, &candidate.metadata, &settings.merge),
+            None => self.current.clone(),
+        };
+        for (field, value) in &self.edits {
+            // Edits are checked when they are made.
+            let _ = apply_edit(&mut result, field, value);
+            // A typed value is no longer a guess, so it gets written.
+            result.inferred.retain(|inferred| inferred != field);
+        }
+        return result;
+    }
+}
+
+/// What the keyboard is doing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mode {
+    Browse,
+    /// Typing a manual search as `artist - title`.
+    Search(String),
+    /// Typing a new value for `EDITABLE_FIELDS[field]`.
+    Edit { field: usize, value: String },
+}
+
+/// Which list the arrow keys move in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Focus {
+    Files,
+    Candidates,
+}
+
+/// Work the review screen needs done outside of it.
+#[derive(Debug)]
+pub enum Action {
+    Quit,
+    /// Search the providers for the item with this metadata.
+    Search(usize, Box<SongMetadata>),
+    /// Write the result of each item.
+    Accept(Vec<usize>),
+    /// Search the items not searched yet, then accept the best candidate of each that has one
+    /// reaching `minimum_score`.
+    AcceptBest { items: Vec<usize>, minimum_score: f64 },
+}
+
+pub struct ReviewApp {
+    pub items: Vec<ReviewItem>,
+    pub selected: usize,
+    pub focus: Focus,
+    pub mode: Mode,
+    /// Shown at the bottom, e.g. the outcome of the last write.
+    pub message: String,
+    /// Unmarked files batch accept takes when their best candidate reaches this score.
+    pub auto_accept_score: f64,
+}
+
+impl ReviewApp {
+    pub fn new(items: Vec<ReviewItem>, auto_accept_score: f64) -> ReviewApp {
+        ReviewApp {
+            items,
+            selected: 0,
+            focus: Focus::Files,
+            mode: Mode::Browse,
+            message: String::new(),
+            auto_accept_score,
+        }
+    }
+
+    pub fn item(&self) -> Option<&ReviewItem> {
+        return self.items.get(self.selected);
+    }
+
+    fn item_mut(&mut self) -> Option<&mut ReviewItem> {
+        return self.items.get_mut(self.selected);
+    }
+
+    /// The selected item when it hasn't been searched yet.
+    pub fn pending_search(&self) -> Option<Action> {
+        let item = self.item()?;
+        if item.candidates.is_some() || item.status != ItemStatus::Pending {
+            return None;
+        }
+        return Some(Action::Search(self.selected, Box::new(item.current.clone())));
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Quit);
+        }
+        return match self.mode.clone() {
+            Mode::Browse => self.handle_browse_key(key),
+            Mode::Search(query) => self.handle_search_key(key, query),
+            Mode::Edit { field, value } => self.handle_edit_key(key, field, value),
+        };
+    }
+
+    fn handle_browse_key(&mut self, key: KeyEvent) -> Option<Action> {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Files => Focus::Candidates,
+                    Focus::Candidates => Focus::Files,
+                };
+            },
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Char(' ') => {
+                if let Some(item) = self.item_mut() {
+                    item.marked = !item.marked;
+                }
+                self.move_selection_in(Focus::Files, 1);
+            },
+            // Failed files hold no tags, accepting them would write empty ones.
+            KeyCode::Enter | KeyCode::Char('a') if self.item().is_some_and(|item| !matches!(item.status, ItemStatus::Written | ItemStatus::Failed(_))) => {
+                return Some(Action::Accept(vec![self.selected]));
+            },
+            KeyCode::Char('A') => return self.batch_accept(),
+            KeyCode::Char('s') => {
+                if let Some(item) = self.item_mut() {
+                    item.status = ItemStatus::Skipped;
+                }
+                self.move_selection_in(Focus::Files, 1);
+            },
+            KeyCode::Char('/') => {
+                if let Some(item) = self.item() {
+                    let query = format!("{} - {}", item.current.artist().unwrap_or_default(), item.current.title.clone().unwrap_or_default());
+                    self.mode = Mode::Search(query);
+                }
+            },
+            KeyCode::Char('e') if self.item().is_some() => self.start_edit(0),
+            _ => {},
+        }
+        return None;
+    }
+
+    fn handle_search_key(&mut self, key: KeyEvent, mut query: String) -> Option<Action> {
+        match key.code {
+            KeyCode::Esc => self.mode = Mode::Browse,
+            KeyCode::Enter => {
+                self.mode = Mode::Browse;
+                let item = self.item()?;
+                let mut metadata = item.current.clone();
+                match query.split_once(" - ") {
+                    Some((artist, title)) => {
+                        metadata.artists = Some(vec![artist.trim().to_string()]);
+                        metadata.title = Some(title.trim().to_string());
+                    },
+                    None => metadata.title = Some(query.trim().to_string()),
+                }
+                return Some(Action::Search(self.selected, Box::new(metadata)));
+            },
+            KeyCode::Backspace => {
+                query.pop();
+                self.mode = Mode::Search(query);
+            },
+            KeyCode::Char(character) => {
+                query.push(character);
+                self.mode = Mode::Search(query);
+            },
+            _ => {},
+        }
+        return None;
+    }
+
+    fn handle_edit_key(&mut self, key: KeyEvent, field: usize, mut value: String) -> Option<Action> {
+        match key.code {
+            KeyCode::Esc => self.mode = Mode::Browse,
+            KeyCode::Tab | KeyCode::Down => self.start_edit((field + 1) % EDITABLE_FIELDS.len()),
+            KeyCode::BackTab | KeyCode::Up => self.start_edit((field + EDITABLE_FIELDS.len() - 1) % EDITABLE_FIELDS.len()),
+            KeyCode::Enter => {
+                let name = EDITABLE_FIELDS[field].0;
+                let mut check = SongMetadata::default();
+                match apply_edit(&mut check, name, &value) {
+                    Ok(_) => {
+                        let item = self.item_mut()?;
+                        item.edits.retain(|(edited, _)| *edited != name);
+                        item.edits.push((name, value));
+                        self.mode = Mode::Browse;
+                    },
+                    Err(e) => self.message = e,
+                }
+            },
+            KeyCode::Backspace => {
+                value.pop();
+                self.mode = Mode::Edit { field, value };
+            },
+            KeyCode::Char(character) => {
+                value.push(character);
+                self.mode = Mode::Edit { field, value };
+            },
+            _ => {},
+        }
+        return None;
+    }
+
+    /// Starts editing a field, filled in with its value in the current result.
+    fn start_edit(&mut self, field: usize) {
+        let name = EDITABLE_FIELDS[field].0;
+        let value = self.item()
+            .and_then(|item| item.edits.iter().find(|(edited, _)| *edited == name).map(|(_, value)| value.clone()))
+            .or_else(|| {
+                let item = self.item()?;
+                let current = item.candidate().map(|candidate| &candidate.metadata).unwrap_or(&item.current);
+                Some(field_value(current, name))
+            })
+            .unwrap_or_default();
+        self.mode = Mode::Edit { field, value };
+    }
+
+    /// Marked files, or when none are marked every pending file whose best candidate reaches
+    /// the auto accept score, like `imd write` would.
+    fn batch_accept(&mut self) -> Option<Action> {
+        let marked: Vec<usize> = (0..self.items.len())
+            .filter(|index| self.items[*index].marked && matches!(self.items[*index].status, ItemStatus::Pending | ItemStatus::Skipped))
+            .collect();
+        if !marked.is_empty() {
+            return Some(Action::AcceptBest { items: marked, minimum_score: 0.0 });
+        }
+        let confident: Vec<usize> = (0..self.items.len())
+            .filter(|index| {
+                let item = &self.items[*index];
+                item.status == ItemStatus::Pending && item.best_score().is_none_or(|score| score >= self.auto_accept_score)
+            })
+            .collect();
+        return (!confident.is_empty()).then_some(Action::AcceptBest { items: confident, minimum_score: self.auto_accept_score });
+    }
+
+    fn move_selection(&mut self, step: isize) {
+        self.move_selection_in(self.focus, step);
+    }
+
+    fn move_selection_in(&mut self, focus: Focus, step: isize) {
+        match focus {
+            Focus::Files => self.selected = step_index(self.selected, step, self.items.len()),
+            Focus::Candidates => {
+                if let Some(item) = self.item_mut() {
+                    let count = item.candidates.as_ref().map_or(0, |candidates| candidates.len());
+                    item.selected_candidate = step_index(item.selected_candidate, step, count);
+                }
+            },
+        }
+    }
+
+    /// Moves to the next pending file after the selected one, if there is any.
+    pub fn select_next_pending(&mut self) {
+        if let Some(next) = (self.selected + 1..self.items.len()).find(|index| self.items[*index].status == ItemStatus::Pending) {
+            self.selected = next;
+        }
+    }
+
+    /// Counts of pending, written, skipped and failed files.
+    pub fn counts(&self) -> (usize, usize, usize, usize) {
+        let count = |predicate: &dyn Fn(&ItemStatus) -> bool| self.items.iter().filter(|item| predicate(&item.status)).count();
+        return (
+            count(&|status| *status == ItemStatus::Pending),
+            count(&|status| *status == ItemStatus::Written),
+            count(&|status| *status == ItemStatus::Skipped),
+            count(&|status| matches!(status, ItemStatus::Failed(_))),
+        );
+    }
+}
+
+fn step_index(index: usize, step: isize, count: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    return index.saturating_add_signed(step).min(count - 1);
+}
+
+/// The field's value as it is edited, lists joined with `; `.
+pub fn field_value(metadata: &SongMetadata, field: &str) -> String {
+    fn text<T: ToString>(value: &Option<T>) -> String {
+        return value.as_ref().map(|value| value.to_string()).unwrap_or_default();
+    }
+    return match field {
+        "title" => text(&metadata.title),
+        "artists" => metadata.artists.as_ref().map(|artists| artists.join(LIST_SEPARATOR)).unwrap_or_default(),
+        "album" => text(&metadata.album),
+        "album_artist" => text(&metadata.album_artist),
+        "composer" => text(&metadata.composer),
+        "genres" => metadata.genres.as_ref().map(|genres| genres.join(LIST_SEPARATOR)).unwrap_or_default(),
+        "track_number" => text(&metadata.track_number),
+        "total_tracks" => text(&metadata.total_tracks),
+        "disc_number" => text(&metadata.disc_number),
+        "year" => text(&metadata.year),
+        _ => String::new(),
+    };
+}
+
+/// Sets the field from its edited text, an empty text clears it.
+pub fn apply_edit(metadata: &mut SongMetadata, field: &str, value: &str) -> Result<(), String> {
+    let value = value.trim();
+    let text = (!value.is_empty()).then(|| value.to_string());
+    let list = text.as_ref().map(|text| text.split(';').map(|part| part.trim().to_string()).filter(|part| !part.is_empty()).collect());
+    let number = || match &text {
+        Some(text) => text.parse::<u16>().map(Some).map_err(|_| format!("{:?} is not a number", text)),
+        None => Ok(None),
+    };
+    match field {
+        "title" => metadata.title = text,
+        "artists" => metadata.artists = list,
+        "album" => metadata.album = text,
+        "album_artist" => metadata.album_artist = text,
+        "composer" => metadata.composer = text,
+        "genres" => metadata.genres = list,
+        "track_number" => metadata.track_number = number()?,
+        "total_tracks" => metadata.total_tracks = number()?,
+        "disc_number" => metadata.disc_number = number()?,
+        "year" => metadata.year = number()?,
+        _ => return Err(format!("Unknown field {:?}", field)),
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        return KeyEvent::new(code, KeyModifiers::NONE);
+    }
+
+    fn metadata(title: &str, artist: &str) -> SongMetadata {
+        return SongMetadata {
+            title: Some(title.to_string()),
+            artists: Some(vec![artist.to_string()]),
+            ..SongMetadata::default()
+        };
+    }
+
+    fn item(name: &str, scores: &[f64]) -> ReviewItem {
+        let mut item = ReviewItem::new(PathBuf::from(name), metadata("Let It Be", "The Beatles"));
+        item.candidates = Some(scores.iter()
+            .map(|score| Candidate {
+                metadata: SongMetadata {
+                    album: Some("Let It Be".to_string()),
+                    year: Some(1970),
+                    ..metadata("Let It Be", "The Beatles")
+                },
+                score: *score,
+                breakdown: [("title", *score, 1.0), ("artist", *score, 1.0), ("duration", 0.0, 0.0)],
+            })
+            .collect());
+        return item;
+    }
+
+    #[test]
+    fn test_navigation_and_marking() {
+        let mut app = ReviewApp::new(vec![item("a.mp3", &[0.9, 0.5]), item("b.mp3", &[0.7])], 0.95);
+        app.handle_key(key(KeyCode::Char(' ')));
+        assert!(app.items[0].marked);
+        assert_eq!(1, app.selected);
+        app.handle_key(key(KeyCode::Down));
+        assert_eq!(1, app.selected);
+
+        app.handle_key(key(KeyCode::Up));
+        app.handle_key(key(KeyCode::Tab));
+        app.handle_key(key(KeyCode::Char('j')));
+        app.handle_key(key(KeyCode::Char('j')));
+        assert_eq!(0, app.selected);
+        assert_eq!(1, app.items[0].selected_candidate);
+
+        app.handle_key(key(KeyCode::Char('s')));
+        assert_eq!(ItemStatus::Skipped, app.items[0].status);
+        app.items[1].status = ItemStatus::Failed("ERROR: Failed to read file".to_string());
+        app.selected = 1;
+        assert!(app.handle_key(key(KeyCode::Enter)).is_none());
+        app.selected = 0;
+        assert!(matches!(app.handle_key(key(KeyCode::Char('q'))), Some(Action::Quit)));
+    }
+
+    #[test]
+    fn test_batch_accept() {
+        let unsearched = ReviewItem::new(PathBuf::from("c.mp3"), SongMetadata::default());
+        let mut app = ReviewApp::new(vec![item("a.mp3", &[0.96]), item("b.mp3", &[0.7]), unsearched], 0.95);
+        let Some(Action::AcceptBest { items, minimum_score }) = app.handle_key(key(KeyCode::Char('A'))) else {
+            panic!("ERROR: Expected a batch accept");
+        };
+        assert_eq!(vec![0, 2], items);
+        assert_eq!(0.95, minimum_score);
+
+        // Marked files are accepted whatever their score.
+        app.items[1].marked = true;
+        let Some(Action::AcceptBest { items, minimum_score }) = app.handle_key(key(KeyCode::Char('A'))) else {
+            panic!("ERROR: Expected a batch accept");
+        };
+        assert_eq!(vec![1], items);
+        assert_eq!(0.0, minimum_score);
+    }
+
+    #[test]
+    fn test_edit_applies_over_candidate() {
+        let settings = Settings::default();
+        let mut app = ReviewApp::new(vec![item("a.mp3", &[0.9])], 0.95);
+        app.handle_key(key(KeyCode::Char('e')));
+        assert_eq!(Mode::Edit { field: 0, value: "Let It Be".to_string() }, app.mode);
+        for _ in 0..9 {
+            app.handle_key(key(KeyCode::Tab));
+        }
+        assert_eq!(Mode::Edit { field: 9, value: "1970".to_string() }, app.mode);
+
+        app.handle_key(key(KeyCode::Char('x')));
+        app.handle_key(key(KeyCode::Enter));
+        assert!(matches!(app.mode, Mode::Edit { .. }));
+        assert_eq!("\"1970x\" is not a number", app.message);
+
+        app.handle_key(key(KeyCode::Backspace));
+        app.handle_key(key(KeyCode::Backspace));
+        app.handle_key(key(KeyCode::Char('1')));
+        app.handle_key(key(KeyCode::Enter));
+        assert_eq!(Mode::Browse, app.mode);
+        let result = app.items[0].result(&settings);
+        assert_eq!(Some(1971), result.year);
+        assert_eq!(Some("Let It Be".to_string()), result.album);
+    }
+
+    #[test]
+    fn test_manual_search_query() {
+        let mut app = ReviewApp::new(vec![item("a.mp3", &[])], 0.95);
+        app.handle_key(key(KeyCode::Char('/')));
+        assert_eq!(Mode::Search("The Beatles - Let It Be".to_string()), app.mode);
+        for _ in 0.."Let It Be".len() {
+            app.handle_key(key(KeyCode::Backspace));
+        }
+        for character in "Get Back".chars() {
+            app.handle_key(key(KeyCode::Char(character)));
+        }
+        let Some(Action::Search(index, query)) = app.handle_key(key(KeyCode::Enter)) else {
+            panic!("ERROR: Expected a search");
+        };
+        assert_eq!(0, index);
+        assert_eq!(Some("Get Back".to_string()), query.title);
+        assert_eq!(Some(vec!["The Beatles".to_string()]), query.artists);
+    }
+
+    #[test]
+    fn test_apply_edit() {
+        let mut metadata = metadata("Title", "Artist");
+        apply_edit(&mut metadata, "artists", "John Lennon; Paul McCartney;").unwrap();
+        assert_eq!("John Lennon; Paul McCartney", field_value(&metadata, "artists"));
+        apply_edit(&mut metadata, "title", "  ").unwrap();
+        assert_eq!(None, metadata.title);
+        assert!(apply_edit(&mut metadata, "track_number", "one").is_err());
+        assert!(apply_edit(&mut metadata, "mood", "happy").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table};
+use ratatui::Frame;
+use crate::metadata::path_inference::without_inferred;
+use crate::settings::Settings;
+use super::{field_value, Focus, ItemStatus, Mode, ReviewApp, ReviewItem, EDITABLE_FIELDS};
+
+const BROWSE_HELP: &str = "↑↓ move  Tab files/candidates  Enter accept  Space mark  A batch accept  / search  e edit  s skip  q quit";
+const EDIT_HELP: &str = "Tab next field  Enter save  Esc cancel";
+
+pub fn draw(frame: &mut Frame, app: &ReviewApp, settings: &Settings) {
+    let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(0), Constraint::Length(2)]).areas(frame.area());
+    let [files, details] = Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(body);
+    let [tags, candidates] = Layout::vertical([Constraint::Length(EDITABLE_FIELDS.len() as u16 + 3), Constraint::Min(0)]).areas(details);
+
+    let (pending, written, skipped, failed) = app.counts();
+    frame.render_widget(
+        Paragraph::new(format!(" imd review: {} pending, {} written, {} skipped, {} failed", pending, written, skipped, failed)).bold(),
+        header,
+    );
+    draw_files(frame, app, files);
+    if let Some(item) = app.item() {
+        draw_tags(frame, app, item, settings, tags);
+        draw_candidates(frame, app, item, candidates);
+    }
+    draw_footer(frame, app, footer);
+}
+
+fn draw_files(frame: &mut Frame, app: &ReviewApp, area: Rect) {
+    let items: Vec<ListItem> = app.items.iter()
+        .map(|item| {
+            let (symbol, color) = match item.status {
+                ItemStatus::Pending => (" ", Color::Reset),
+                ItemStatus::Written => ("✓", Color::Green),
+                ItemStatus::Skipped => ("-", Color::DarkGray),
+                ItemStatus::Failed(_) => ("!", Color::Red),
+            };
+            let score = item.best_score().map(|score| format!("{:.2}", score)).unwrap_or_else(|| "    ".to_string());
+            let name = item.path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            ListItem::new(Line::from(vec![
+                Span::raw(if item.marked { "*" } else { " " }),
+                Span::styled(symbol, Style::new().fg(color)),
+                Span::raw(format!(" {} {}", score, name)),
+            ]))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(pane("Files", app.focus == Focus::Files))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, area, &mut ListState::default().with_selected(Some(app.selected)));
+}
+
+/// The current tags next to the tags accepting would write, changes highlighted.
+fn draw_tags(frame: &mut Frame, app: &ReviewApp, item: &ReviewItem, settings: &Settings, area: Rect) {
+    // What is written, without the guesses from the file path.
+    let result = without_inferred(item.result(settings));
+    let editing = match app.mode {
+        Mode::Edit { field, .. } => Some(field),
+        _ => None,
+    };
+    let rows: Vec<Row> = EDITABLE_FIELDS.iter().enumerate()
+        .map(|(index, (field, label))| {
+            let current = field_value(&item.current, field);
+            let new = field_value(&result, field);
+            let edited = item.edits.iter().any(|(edited, _)| edited == field);
+            let style = if edited {
+                Style::new().fg(Color::Cyan)
+            } else if new != current {
+                Style::new().fg(Color::Yellow)
+            } else {
+                Style::new()
+            };
+            // Guessed from the file path, not in the file's tags.
+            let current_style = if item.current.inferred.contains(field) { Style::new().dark_gray().italic() } else { Style::new() };
+            let row = Row::new(vec![Cell::from(*label), Cell::from(current).style(current_style), Cell::from(new).style(style)]);
+            if editing == Some(index) { row.add_modifier(Modifier::REVERSED) } else { row }
+        })
+        .collect();
+    let table = Table::new(rows, [Constraint::Length(14), Constraint::Percentage(45), Constraint::Percentage(55)])
+        .header(Row::new(vec!["", "Current", "Result"]).bold())
+        .block(pane(&item.path.to_string_lossy(), false));
+    frame.render_widget(table, area);
+}
+
+/// Ranked candidates with how each part of the score came about.
+fn draw_candidates(frame: &mut Frame, app: &ReviewApp, item: &ReviewItem, area: Rect) {
+    let block = pane("Candidates", app.focus == Focus::Candidates);
+    let Some(candidates) = &item.candidates else {
+        frame.render_widget(Paragraph::new("Searching...").block(block), area);
+        return;
+    };
+    if candidates.is_empty() {
+        frame.render_widget(Paragraph::new("No candidates, press / to search by hand or e to edit the tags").block(block), area);
+        return;
+    }
+    let items: Vec<ListItem> = candidates.iter()
+        .map(|candidate| {
+            let metadata = &candidate.metadata;
+            let breakdown = candidate.breakdown.iter()
+                .map(|(part, score, weight)| format!("{} {:.2}×{}", part, score, weight))
+                .collect::<Vec<String>>()
+                .join("  ");
+            let color = if candidate.score >= app.auto_accept_score { Color::Green } else { Color::Yellow };
+            ListItem::new(vec![
+                Line::from(vec![
+                    Span::styled(format!("{:.2} ", candidate.score), Style::new().fg(color).bold()),
+                    Span::raw(format!(
+                        "{} - {} ({}, {})",
+                        metadata.artist().unwrap_or_default(),
+                        metadata.title.clone().unwrap_or_default(),
+                        metadata.album.clone().unwrap_or_default(),
+                        metadata.year.map(|year| year.to_string()).unwrap_or_default(),
+                    )),
+                ]),
+                Line::from(format!("     {}", breakdown)).dark_gray(),
+            ])
+        })
+        .collect();
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, area, &mut ListState::default().with_selected(Some(item.selected_candidate)));
+}
+
+fn draw_footer(frame: &mut Frame, app: &ReviewApp, area: Rect) {
+    let input = match &app.mode {
+        Mode::Browse => BROWSE_HELP.to_string(),
+        Mode::Search(query) => format!("Search (artist - title): {}▏ Enter search  Esc cancel", query),
+        Mode::Edit { field, value } => format!("{}: {}▏ {}", EDITABLE_FIELDS[*field].1, value, EDIT_HELP),
+    };
+    let status = match app.item().map(|item| &item.status) {
+        Some(ItemStatus::Failed(e)) if app.message.is_empty() => e.clone(),
+        _ => app.message.clone(),
+    };
+    frame.render_widget(Paragraph::new(vec![Line::from(input), Line::from(status).yellow()]), area);
+}
+
+fn pane(title: &str, focused: bool) -> Block<'static> {
+    let style = if focused { Style::new().fg(Color::Cyan) } else { Style::new() };
+    return Block::new().borders(Borders::ALL).border_style(style).title(format!(" {} ", title));
+}
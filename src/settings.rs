@@ -0,0 +1,646 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use toml::Table;
+use crate::audio::key::KeyNotation;
+use crate::history::state_db::SkipMode;
+use crate::metadata::album_matcher::AlbumGrouping;
+use crate::metadata::id3v2_writer::{Id3v2Version, TextEncoding};
+use crate::metadata::response_cache::{default_cache_dir, ResponseCache};
+use crate::metadata::tag_target::TagTarget;
+use crate::organise::file_organiser::OrganiseMode;
+
+const CONFIG_DIR_NAME: &str = "imd";
+const CONFIG_FILE_NAME: &str = "config.toml";
+const PROFILES_KEY: &str = "profile";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub general: GeneralSettings,
+    pub providers: ProviderSettings,
+    pub scoring: ScoringSettings,
+    pub thresholds: ThresholdSettings,
+    pub merge: MergeSettings,
+    pub artwork: ArtworkSettings,
+    pub naming: NamingSettings,
+    pub journal: JournalSettings,
+    pub writer: WriterSettings,
+    pub cache: CacheSettings,
+    pub fingerprint: FingerprintSettings,
+    pub path_inference: PathInferenceSettings,
+    pub album: AlbumSettings,
+    pub analysis: AnalysisSettings,
+    pub batch: BatchSettings,
+    pub library: LibrarySettings,
+    pub duplicates: DuplicateSettings,
+    pub quality: QualitySettings,
+    pub watch: WatchSettings,
+    pub server: ServerSettings,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GeneralSettings {
+    pub debug: bool,
+    pub write: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProviderSettings {
+    /// Providers to query, in order of preference.
+    pub order: Vec<String>,
+    /// Storefront country codes to search, tried in order until one returns results.
+    pub storefronts: Vec<String>,
+    /// Requests per minute sent to each provider across all workers, 0 for no limit.
+    pub requests_per_minute: u32,
+}
+
+impl Default for ProviderSettings {
+    fn default() -> ProviderSettings {
+        ProviderSettings {
+            order: vec!["itunes".to_string()],
+            storefronts: vec!["us".to_string()],
+            requests_per_minute: 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ScoringSettings {
+    pub title_weight: f64,
+    pub artist_weight: f64,
+    pub duration_weight: f64,
+    pub duration_tolerance_secs: u64,
+    /// Multiplies the title and artist scores when they were guessed from the file path.
+    pub inferred_weight: f64,
+}
+
+impl Default for ScoringSettings {
+    fn default() -> ScoringSettings {
+        ScoringSettings {
+            title_weight: 1.0,
+            artist_weight: 1.0,
+            duration_weight: 2.0,
+            duration_tolerance_secs: 10,
+            inferred_weight: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ThresholdSettings {
+    /// Candidates scoring below this are never considered a match.
+    pub minimum_score: f64,
+    /// The best match is only written to the file when it scores at least this.
+    pub auto_accept_score: f64,
+}
+
+impl Default for ThresholdSettings {
+    fn default() -> ThresholdSettings {
+        ThresholdSettings {
+            minimum_score: 0.5,
+            auto_accept_score: 0.9,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergePolicy {
+    /// Use the matched value, falling back to the original value.
+    PreferMatch,
+    /// Keep the original value, falling back to the matched value.
+    PreferOriginal,
+    /// Always use the matched value, even when it is empty.
+    MatchOnly,
+    /// Never change the original value.
+    KeepOriginal,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MergeSettings {
+    pub default: MergePolicy,
+    /// Per-field overrides keyed by `SongMetadata` field name, e.g. `comment = "keep_original"`.
+    /// Fields the config doesn't mention keep their default policy.
+    #[serde(deserialize_with = "merge_over_default_fields")]
+    pub fields: HashMap<String, MergePolicy>,
+}
+
+fn merge_over_default_fields<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, MergePolicy>, D::Error> {
+    let mut fields = MergeSettings::default().fields;
+    fields.extend(HashMap::<String, MergePolicy>::deserialize(deserializer)?);
+    return Ok(fields);
+}
+
+impl MergeSettings {
+    pub fn policy_for(&self, field: &str) -> MergePolicy {
+        return *self.fields.get(field).unwrap_or(&self.default);
+    }
+}
+
+impl Default for MergeSettings {
+    fn default() -> MergeSettings {
+        MergeSettings {
+            default: MergePolicy::PreferMatch,
+            fields: HashMap::from([
+                ("composer".to_string(), MergePolicy::MatchOnly),
+                ("comment".to_string(), MergePolicy::MatchOnly),
+            ]),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ArtworkSettings {
+    /// Embed the matched album artwork as the front cover when writing.
+    pub embed: bool,
+    /// Requested artwork width and height in pixels.
+    pub size: u32,
+}
+
+impl Default for ArtworkSettings {
+    fn default() -> ArtworkSettings {
+        ArtworkSettings {
+            embed: false,
+            size: 600,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NamingSettings {
+    pub template: String,
+    /// How files are placed at their organised path: copy, move or hardlink.
+    pub mode: OrganiseMode,
+}
+
+impl Default for NamingSettings {
+    fn default() -> NamingSettings {
+        NamingSettings {
+            template: "{album_artist}/{year} - {album}/{disc}-{track:02} {title}.{ext}".to_string(),
+            mode: OrganiseMode::Copy,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JournalSettings {
+    /// Record every tag write so it can be undone with `imd undo`.
+    pub enabled: bool,
+    /// Defaults to imd/journal.jsonl in the user data directory.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for JournalSettings {
+    fn default() -> JournalSettings {
+        JournalSettings {
+            enabled: true,
+            path: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WriterSettings {
+    /// Keep the file's modification time when writing tags, so sync tools don't see a change.
+    pub preserve_mtime: bool,
+    /// Which tags are written: primary, all_existing or types.
+    pub tag_target: TagTarget,
+    /// Tag types written when `tag_target` is `types`, e.g. `["id3v2", "ape"]`.
+    pub tag_types: Vec<String>,
+    /// Remove ID3v1 and APE tags unless they are the format's primary tag.
+    pub strip_legacy_tags: bool,
+    /// Bytes of padding left after tags so later edits don't rewrite the file, lofty's default when unset.
+    pub padding: Option<u32>,
+    /// Joins artists and genres for tag types that can only hold one value per field.
+    pub multi_value_separator: String,
+    pub id3v2: Id3v2Settings,
+    pub custom_tags: CustomTagSettings,
+}
+
+impl Default for WriterSettings {
+    fn default() -> WriterSettings {
+        WriterSettings {
+            preserve_mtime: false,
+            tag_target: TagTarget::AllExisting,
+            tag_types: Vec::new(),
+            strip_legacy_tags: false,
+            padding: None,
+            multi_value_separator: "; ".to_string(),
+            id3v2: Id3v2Settings::default(),
+            custom_tags: CustomTagSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Id3v2Settings {
+    /// "2.3" for devices that can't read ID3v2.4, or "2.4".
+    pub version: Id3v2Version,
+    /// Text encoding of written frames: latin1, utf16 or utf8. Defaults to UTF-16 for ID3v2.3 and UTF-8 for ID3v2.4.
+    pub encoding: Option<TextEncoding>,
+    /// Keep frames imd doesn't know about, e.g. private frames written by other applications.
+    pub preserve_unknown_frames: bool,
+}
+
+impl Default for Id3v2Settings {
+    fn default() -> Id3v2Settings {
+        Id3v2Settings {
+            version: Id3v2Version::V24,
+            encoding: None,
+            preserve_unknown_frames: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    /// Cache provider responses on disk.
+    pub enabled: bool,
+    /// How long a cached response is used before it is fetched again.
+    pub ttl_hours: u64,
+    /// Defaults to imd/responses in the user cache directory.
+    pub dir: Option<PathBuf>,
+}
+
+impl CacheSettings {
+    pub fn open(&self) -> Option<ResponseCache> {
+        if !self.enabled {
+            return None;
+        }
+        let dir = self.dir.clone().unwrap_or_else(default_cache_dir);
+        return Some(ResponseCache::new(dir, Duration::from_secs(self.ttl_hours * 3600)));
+    }
+}
+
+impl Default for CacheSettings {
+    fn default() -> CacheSettings {
+        CacheSettings {
+            enabled: true,
+            ttl_hours: 24 * 7,
+            dir: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FingerprintSettings {
+    /// Identify files without a title or artist by their acoustic fingerprint.
+    pub enabled: bool,
+    /// An AcoustID compatible lookup service.
+    pub base_url: String,
+    /// AcoustID application API key, lookups are skipped without one.
+    pub api_key: Option<String>,
+    /// Recordings matching with a lower AcoustID score are ignored.
+    pub minimum_score: f64,
+    /// How much audio from the start of the file is fingerprinted.
+    pub max_length_secs: u64,
+    /// Lookups per minute across all workers, AcoustID allows 3 per second.
+    pub requests_per_minute: u32,
+}
+
+impl Default for FingerprintSettings {
+    fn default() -> FingerprintSettings {
+        FingerprintSettings {
+            enabled: true,
+            base_url: "https://api.acoustid.org".to_string(),
+            api_key: None,
+            minimum_score: 0.5,
+            max_length_secs: 120,
+            requests_per_minute: 180,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PathInferenceSettings {
+    /// Fill fields missing from the tags from the file name and its parent folders.
+    pub enabled: bool,
+    /// Template patterns such as `{artist} - {album}/{track} - {title}`, the first match wins.
+    pub patterns: Vec<String>,
+    /// Regular expressions with named captures, tried before `patterns`.
+    pub regex_patterns: Vec<String>,
+}
+
+impl Default for PathInferenceSettings {
+    fn default() -> PathInferenceSettings {
+        PathInferenceSettings {
+            enabled: true,
+            patterns: [
+                "{artist} - {album}/{track} - {title}",
+                "{artist} - {album}/{track} {title}",
+                "{artist}/{album}/{track} - {title}",
+                "{artist}/{album}/{track} {title}",
+                "{track} - {artist} - {title}",
+                "{track} - {title}",
+                "{artist} - {title}",
+                "{track} {title}",
+            ].map(String::from).to_vec(),
+            regex_patterns: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AlbumSettings {
+    /// How `imd album` decides which files form an album: folder or album_tag.
+    pub group_by: AlbumGrouping,
+    /// Number of album search results whose track lists are compared.
+    pub max_candidates: usize,
+    /// Weight of a file sitting at the same disc and track position as a release track.
+    pub track_order_weight: f64,
+}
+
+impl Default for AlbumSettings {
+    fn default() -> AlbumSettings {
+        AlbumSettings {
+            group_by: AlbumGrouping::Folder,
+            max_candidates: 5,
+            track_order_weight: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AnalysisSettings {
+    /// How detected keys are written: standard, camelot or open_key.
+    pub key_notation: KeyNotation,
+    /// Tempo range searched, a tempo outside it is reported at a multiple or fraction that fits.
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    /// Replace BPM and key values already in the tags.
+    pub overwrite: bool,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> AnalysisSettings {
+        AnalysisSettings {
+            key_notation: KeyNotation::Standard,
+            min_bpm: 70.0,
+            max_bpm: 180.0,
+            overwrite: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BatchSettings {
+    /// Files processed at the same time when given a directory, 0 for one per CPU core.
+    pub workers: usize,
+    /// Show a progress bar while processing a directory.
+    pub progress: bool,
+    /// Which previously processed files a directory run skips: none, done or unchanged.
+    pub skip: SkipMode,
+    /// Record every file a directory run processes in the state database.
+    pub record_state: bool,
+    /// Defaults to imd/state.sqlite3 in the user data directory.
+    pub state_path: Option<PathBuf>,
+}
+
+impl Default for BatchSettings {
+    fn default() -> BatchSettings {
+        BatchSettings {
+            workers: 0,
+            progress: true,
+            skip: SkipMode::None,
+            record_state: true,
+            state_path: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LibrarySettings {
+    /// Defaults to imd/library.sqlite3 in the user data directory.
+    pub index_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DuplicateSettings {
+    /// Files with the same title and artist are only duplicates when their lengths differ less.
+    pub duration_tolerance_secs: u64,
+    /// Also compare acoustic fingerprints, finding duplicates with missing or differing tags.
+    pub use_fingerprints: bool,
+    /// Fingerprints at least this similar, from 0.5 for unrelated audio to 1, hold the same recording.
+    pub fingerprint_similarity: f64,
+    /// File extensions from most to least preferred when recommending which copy to keep.
+    pub format_preference: Vec<String>,
+    /// Where `--action quarantine` moves duplicates, defaults to imd/quarantine in the user data directory.
+    pub quarantine_dir: Option<PathBuf>,
+}
+
+impl Default for DuplicateSettings {
+    fn default() -> DuplicateSettings {
+        DuplicateSettings {
+            duration_tolerance_secs: 3,
+            use_fingerprints: false,
+            fingerprint_similarity: 0.85,
+            format_preference: ["flac", "wv", "ape", "wav", "aiff", "aif", "m4a", "opus", "ogg", "mp3", "aac"].map(String::from).to_vec(),
+            quarantine_dir: None,
+        }
+    }
+}
+
+/// Audio quality a file needs for batch runs to process it, and to be kept by duplicate resolution.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct QualitySettings {
+    /// Lowest bitrate in kbps of lossy files, lossless files and files of unknown bitrate always pass.
+    pub min_bitrate: Option<u32>,
+    /// Only lossless formats pass, e.g. FLAC, ALAC or WAV.
+    pub lossless_only: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WatchSettings {
+    /// Seconds a new file must go unchanged before it is processed, so downloads can finish.
+    pub settle_secs: u64,
+    /// Where matched files are organised to, like `--organise`. They stay in the inbox when unset.
+    pub organise_dir: Option<PathBuf>,
+    /// Where files below the auto accept score go, defaults to `review` in the watched folder.
+    pub review_dir: Option<PathBuf>,
+}
+
+impl Default for WatchSettings {
+    fn default() -> WatchSettings {
+        WatchSettings {
+            settle_secs: 10,
+            organise_dir: None,
+            review_dir: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    /// Address `imd serve` listens on, only reachable from this machine by default.
+    pub bind: String,
+}
+
+impl Default for ServerSettings {
+    fn default() -> ServerSettings {
+        ServerSettings {
+            bind: "127.0.0.1:7878".to_string(),
+        }
+    }
+}
+
+/// Rules for tag items imd doesn't model, e.g. TXXX frames or custom Vorbis comments. Keys are
+/// matched case-insensitively, a trailing `*` matches any key with that prefix.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CustomTagSettings {
+    /// Keys removed from written files.
+    pub delete: Vec<String>,
+    /// Keys renamed in written files, old key to new key.
+    pub rename: BTreeMap<String, String>,
+}
+
+impl CustomTagSettings {
+    pub fn is_deleted(&self, key: &str) -> bool {
+        return self.delete.iter().any(|pattern| key_matches(pattern, key));
+    }
+
+    pub fn renamed(&self, key: &str) -> Option<&str> {
+        return self.rename.iter()
+            .find(|(pattern, _)| key_matches(pattern, key))
+            .map(|(_, new_key)| new_key.as_str());
+    }
+}
+
+fn key_matches(pattern: &str, key: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let key = key.to_lowercase();
+    return match pattern.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix),
+        None => key == pattern,
+    };
+}
+
+impl Settings {
+    /// Loads settings from `config_path`, or from the default config location when no path is given.
+    /// A missing default config file is not an error, the built-in defaults are used instead.
+    pub fn load(config_path: Option<&Path>, profile: Option<&str>) -> Settings {
+        let contents = match config_path {
+            Some(path) => Some(fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("ERROR: Failed to read config file {:?}: {}", path, e))),
+            None => default_config_path()
+                .filter(|path| path.is_file())
+                .map(|path| fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("ERROR: Failed to read config file {:?}: {}", path, e))),
+        };
+
+        return match contents {
+            Some(contents) => Settings::from_toml_str(&contents, profile),
+            None => {
+                if let Some(profile) = profile {
+                    panic!("ERROR: Profile {:?} requested but no config file was found", profile);
+                }
+                Settings::default()
+            },
+        };
+    }
+
+    pub fn from_toml_str(contents: &str, profile: Option<&str>) -> Settings {
+        let mut table: Table = contents.parse().unwrap_or_else(|e| panic!("ERROR: Invalid config file: {}", e));
+        let profiles = table.remove(PROFILES_KEY);
+
+        if let Some(profile) = profile {
+            let profile_table = profiles.as_ref()
+                .and_then(|profiles| profiles.get(profile))
+                .and_then(|profile_table| profile_table.as_table())
+                .unwrap_or_else(|| panic!("ERROR: Profile {:?} not found in config file", profile));
+            merge_tables(&mut table, profile_table);
+        }
+
+        return table.try_into().unwrap_or_else(|e| panic!("ERROR: Invalid config file: {}", e));
+    }
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    return dirs::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME));
+}
+
+fn merge_tables(base: &mut Table, overlay: &Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table);
+            },
+            _ => {
+                base.insert(key.clone(), value.clone());
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [providers]
+        storefronts = ["gb", "us"]
+
+        [scoring]
+        title_weight = 2.0
+
+        [merge.fields]
+        genre = "keep_original"
+
+        [profile.classical.scoring]
+        artist_weight = 0.25
+
+        [profile.classical.merge]
+        default = "prefer_original"
+    "#;
+
+    #[test]
+    fn test_defaults_fill_missing_values() {
+        let settings = Settings::from_toml_str(CONFIG, None);
+        assert_eq!(vec!["gb", "us"], settings.providers.storefronts);
+        assert_eq!(vec!["itunes"], settings.providers.order);
+        assert_eq!(2.0, settings.scoring.title_weight);
+        assert_eq!(1.0, settings.scoring.artist_weight);
+        assert_eq!(MergePolicy::KeepOriginal, settings.merge.policy_for("genre"));
+        assert_eq!(MergePolicy::PreferMatch, settings.merge.policy_for("title"));
+        assert_eq!(MergePolicy::MatchOnly, settings.merge.policy_for("composer"));
+    }
+
+    #[test]
+    fn test_profile_overrides_base_values() {
+        let settings = Settings::from_toml_str(CONFIG, Some("classical"));
+        assert_eq!(2.0, settings.scoring.title_weight);
+        assert_eq!(0.25, settings.scoring.artist_weight);
+        assert_eq!(MergePolicy::KeepOriginal, settings.merge.policy_for("genre"));
+        assert_eq!(MergePolicy::PreferOriginal, settings.merge.policy_for("title"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Profile \"jazz\" not found")]
+    fn test_unknown_profile_panics() {
+        Settings::from_toml_str(CONFIG, Some("jazz"));
+    }
+}
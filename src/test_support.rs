@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SAMPLE_RATE: u32 = 8000;
+const SAMPLE_COUNT: u32 = 8000;
+const MP3_FRAME_COUNT: usize = 40;
+
+/// A short silent audio file in its own temporary directory, removed again on drop.
+pub struct TestAudioFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl TestAudioFile {
+    /// Creates a one second, mono, 16 bit PCM WAV file.
+    pub fn wav(name: &str) -> TestAudioFile {
+        return TestAudioFile::wav_with_samples(name, &vec![0; SAMPLE_COUNT as usize]);
+    }
+
+    /// Creates a mono, 16 bit PCM WAV file at 8 kHz holding the given samples.
+    pub fn wav_with_samples(name: &str, samples: &[i16]) -> TestAudioFile {
+        let dir = std::env::temp_dir().join(format!("imd-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.wav", name));
+        fs::write(&path, wav_bytes(samples)).unwrap();
+        return TestAudioFile { dir, path };
+    }
+
+    /// Creates a short, silent MPEG-1 Layer III file without any tags.
+    pub fn mp3(name: &str) -> TestAudioFile {
+        let dir = std::env::temp_dir().join(format!("imd-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.mp3", name));
+        fs::write(&path, mp3_bytes()).unwrap();
+        return TestAudioFile { dir, path };
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    pub fn dir(&self) -> &Path {
+        return &self.dir;
+    }
+}
+
+impl Drop for TestAudioFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn wav_bytes(samples: &[i16]) -> Vec<u8> {
+    let data_size = samples.len() as u32 * 2;
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend(b"RIFF");
+    bytes.extend((36 + data_size).to_le_bytes());
+    bytes.extend(b"WAVEfmt ");
+    bytes.extend(16u32.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes()); // PCM
+    bytes.extend(1u16.to_le_bytes()); // mono
+    bytes.extend(SAMPLE_RATE.to_le_bytes());
+    bytes.extend((SAMPLE_RATE * 2).to_le_bytes());
+    bytes.extend(2u16.to_le_bytes());
+    bytes.extend(16u16.to_le_bytes());
+    bytes.extend(b"data");
+    bytes.extend(data_size.to_le_bytes());
+    for sample in samples {
+        bytes.extend(sample.to_le_bytes());
+    }
+    return bytes;
+}
+
+/// 128 kbit/s, 44.1 kHz frames with an empty payload.
+fn mp3_bytes() -> Vec<u8> {
+    const FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC4];
+    const FRAME_LENGTH: usize = 417;
+    let mut bytes: Vec<u8> = Vec::new();
+    for _ in 0..MP3_FRAME_COUNT {
+        bytes.extend(FRAME_HEADER);
+        bytes.extend(vec![0; FRAME_LENGTH - FRAME_HEADER.len()]);
+    }
+    return bytes;
+}